@@ -14,7 +14,7 @@ use std::mem;
 use std::path::Path;
 use std::{thread, time};
 
-use webvr::{VRServiceManager, VREvent, VRDisplayEvent, VRLayer, VRFrameData, VRFramebufferAttributes};
+use webvr::{VRServiceManager, VREvent, VRDisplayEvent, VRLayer, VRTexture, VRFrameData, VRFramebufferAttributes};
 
 type Vec3 = Vector3<f32>;
 type Mat4 = Matrix4<f32>;
@@ -663,7 +663,7 @@ pub fn main() {
 
         // Render to HMD
         let layer = VRLayer {
-            texture_id: target_texture,
+            texture: VRTexture::Gl(target_texture),
             .. Default::default()
         };
 
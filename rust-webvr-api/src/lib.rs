@@ -18,9 +18,15 @@ pub mod utils;
 #[macro_use]
 extern crate serde_derive;
 
+#[cfg(feature = "serde-serialization")]
+extern crate serde_json;
+
 #[cfg(feature = "ipc")]
 extern crate ipc_channel;
 
+#[cfg(feature = "ipc")]
+extern crate serde;
+
 extern crate gleam;
 
 
@@ -36,13 +42,19 @@ pub mod vr_frame_data;
 pub mod vr_future_frame_data;
 pub mod vr_layer;
 pub mod vr_pose;
+pub mod vr_texture;
 pub mod vr_stage_parameters;
 pub mod vr_event;
 pub mod vr_field_view;
 pub mod vr_gamepad;
 pub mod vr_main_thread_heartbeat;
+#[cfg(feature = "utils")]
+pub mod vr_compositor;
 
-pub use mock::MockVRControlMsg;
+pub use mock::{MockVRControlMsg, MockVRDisplayProfile, MockVRGamepadInit, MockVRReply, MockVRSender};
+pub use mock::{MockVRFrameRecord, MockVRFrameTiming, MockVRSubmittedLayer, MOCK_VR_FRAME_LOG_LENGTH};
+pub use mock::{MockVRTimeline, MockVRPoseKeyframe, MockVRStageKeyframe};
+pub use mock::{MockVRTrackingLoss, MockVRTrackingNoise};
 pub use vr_display::{VRDisplay,VRDisplayPtr};
 pub use vr_service::{VRService,VRServiceCreator};
 pub use vr_display_data::VRDisplayData;
@@ -53,7 +65,8 @@ pub use vr_framebuffer::{VRFramebuffer, VRFramebufferAttributes, VRViewport};
 pub use vr_frame_data::VRFrameData;
 pub use vr_future_frame_data::VRFutureFrameData;
 pub use vr_future_frame_data::VRResolveFrameData;
-pub use vr_layer::VRLayer;
+pub use vr_layer::{VRLayer, VRQuadLayer};
+pub use vr_texture::{VRTexture, VRTextureKind, VRCpuBuffer, VRDmabuf, VRDmabufPlane};
 pub use vr_pose::VRPose;
 pub use vr_stage_parameters::VRStageParameters;
 pub use vr_event::{VREvent, VRDisplayEvent, VRDisplayEventReason, VRGamepadEvent};
@@ -61,3 +74,5 @@ pub use vr_field_view::VRFieldOfView;
 pub use vr_gamepad::{VRGamepad, VRGamepadPtr, VRGamepadHand,
                      VRGamepadData, VRGamepadState, VRGamepadButton};
 pub use vr_main_thread_heartbeat::VRMainThreadHeartbeat;
+#[cfg(feature = "utils")]
+pub use vr_compositor::VRCompositor;
@@ -1,4 +1,17 @@
-use crate::{VREyeParameters, VRStageParameters};
+use crate::{VRDisplayCapabilities, VRDisplayEventReason, VREyeParameters, VRFieldOfView};
+use crate::{VRDisplayData, VRFrameData, VRFramebufferAttributes, VRGamepadButton, VRGamepadHand};
+use crate::{VRLayer, VRPose, VRStageParameters};
+
+#[cfg(feature = "ipc")]
+use ipc_channel::ipc::IpcSender;
+#[cfg(feature = "ipc")]
+use serde::Serialize;
+use std::sync::mpsc::Sender;
+
+#[cfg(feature = "serde-serialization")]
+use std::fs;
+#[cfg(feature = "serde-serialization")]
+use std::path::Path;
 
 #[cfg_attr(feature = "serde-serialization", derive(Deserialize, Serialize))]
 #[derive(Debug)]
@@ -7,6 +20,440 @@ pub enum MockVRControlMsg {
     SetEyeParameters(VREyeParameters, VREyeParameters),
     SetProjectionMatrices([f32; 16], [f32; 16]),
     SetStageParameters(VRStageParameters),
+    /// Replaces the display data with the given headset profile.
+    /// Projection matrices are derived from the profile field of view again.
+    SetDisplayProfile(MockVRDisplayProfile),
+    /// Starts playing a pose animation, replacing any previous one.
+    SetTimeline(MockVRTimeline),
+    /// Stops the current pose animation, leaving the pose where it was.
+    ClearTimeline,
+    /// Connects a gamepad, which later messages refer to by the given index.
+    AddGamepad(u32, MockVRGamepadInit),
+    RemoveGamepad(u32),
+    SetGamepadPose(u32, VRPose),
+    SetGamepadButton(u32, usize, VRGamepadButton),
+    SetGamepadAxis(u32, usize, f64),
     Focus,
     Blur,
+    /// Simulates the headset being plugged in.
+    Connect,
+    /// Simulates the headset being unplugged.
+    Disconnect,
+    /// Simulates the headset being put on, after it was deactivated.
+    Activate(VRDisplayEventReason),
+    /// Simulates the headset being taken off, which ends any presentation.
+    Deactivate(VRDisplayEventReason),
+    Pause,
+    Resume,
+    Exit,
+    PresentChange(bool),
+    /// Replies with everything the application has presented since the log was last cleared.
+    GetFrameLog(MockVRSender<Vec<MockVRFrameRecord>>),
+    ClearFrameLog,
+    /// Keeps at most this many records in the frame log, dropping the oldest ones first.
+    /// The log keeps the last `MOCK_VR_FRAME_LOG_LENGTH` records unless this is sent.
+    SetFrameLogLength(usize),
+    /// Replies with the current display data.
+    GetDisplayData(MockVRSender<VRDisplayData>),
+    /// Replies with the last frame data handed to the application.
+    GetFrameData(MockVRSender<VRFrameData>),
+    SetFrameTiming(MockVRFrameTiming),
+    /// Stops reporting position and/or orientation in the display pose.
+    LoseTracking(MockVRTrackingLoss),
+    RestoreTracking,
+    /// Adds noise and drift to the display pose, replacing any previous settings.
+    SetTrackingNoise(MockVRTrackingNoise),
+}
+
+/// The replies the mock display sends. With the `ipc` feature they can be sent over IPC,
+/// which all the replies in `MockVRControlMsg` can.
+#[cfg(feature = "ipc")]
+pub trait MockVRReply: Serialize {}
+#[cfg(feature = "ipc")]
+impl<T: Serialize> MockVRReply for T {}
+#[cfg(not(feature = "ipc"))]
+pub trait MockVRReply {}
+#[cfg(not(feature = "ipc"))]
+impl<T> MockVRReply for T {}
+
+/// The default number of records kept in the mock display's frame log.
+pub const MOCK_VR_FRAME_LOG_LENGTH: usize = 1000;
+
+/// Where the mock display sends its replies to queries.
+/// The same type is used whether or not the `ipc` feature is enabled,
+/// only replies over IPC need the feature.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde-serialization", derive(Deserialize, Serialize))]
+pub enum MockVRSender<T: MockVRReply> {
+    #[cfg(feature = "ipc")]
+    Ipc(IpcSender<T>),
+    /// A reply in the same process. It can't be sent over IPC.
+    // Skipped variants go last, so they don't shift the indices of the serialized ones.
+    #[cfg_attr(feature = "serde-serialization", serde(skip))]
+    Local(Sender<T>),
+}
+
+impl<T: MockVRReply> MockVRSender<T> {
+    pub fn send(&self, msg: T) -> Result<(), String> {
+        match *self {
+            MockVRSender::Local(ref sender) => sender.send(msg).map_err(|e| e.to_string()),
+            #[cfg(feature = "ipc")]
+            MockVRSender::Ipc(ref sender) => sender.send(msg).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+
+impl<T: MockVRReply> From<Sender<T>> for MockVRSender<T> {
+    fn from(sender: Sender<T>) -> MockVRSender<T> {
+        MockVRSender::Local(sender)
+    }
+}
+
+#[cfg(feature = "ipc")]
+impl<T: MockVRReply> From<IpcSender<T>> for MockVRSender<T> {
+    fn from(sender: IpcSender<T>) -> MockVRSender<T> {
+        MockVRSender::Ipc(sender)
+    }
+}
+
+/// A presentation call made by the application on the mock display.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde-serialization", derive(Deserialize, Serialize))]
+pub enum MockVRFrameRecord {
+    StartPresent(Option<VRFramebufferAttributes>),
+    StopPresent,
+    BindFramebuffer(u32),
+    SubmitLayer(MockVRSubmittedLayer),
+    /// A layer was submitted after the vsync following its frame, param: frame timestamp.
+    MissedDeadline(f64),
+    /// More than one layer was submitted for the same frame, param: frame timestamp.
+    DoubleSubmit(f64),
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde-serialization", derive(Deserialize, Serialize))]
+pub struct MockVRSubmittedLayer {
+    pub layer: VRLayer,
+    /// Timestamp of the last frame data handed to the application before the submission.
+    pub frame_timestamp: f64,
+    /// The framebuffer attributes in use, as passed to `start_present`.
+    pub attributes: VRFramebufferAttributes,
+}
+
+/// A keyframed animation of the mock display pose and stage.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde-serialization", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde-serialization", serde(default))]
+pub struct MockVRTimeline {
+    /// Head pose keyframes. They needn't be in order, they are sorted by time when played.
+    /// Positions are interpolated linearly and orientations by slerp.
+    pub pose: Vec<MockVRPoseKeyframe>,
+
+    /// Stage keyframes, which are also sorted by time when played.
+    /// Each one replaces the stage parameters when its time is reached.
+    pub stage: Vec<MockVRStageKeyframe>,
+
+    /// Restart the animation once the last keyframe has been reached.
+    pub looping: bool,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde-serialization", derive(Deserialize, Serialize))]
+pub struct MockVRPoseKeyframe {
+    /// Time in milliseconds since the start of the timeline.
+    pub time: f64,
+    pub position: [f32; 3],
+    pub orientation: [f32; 4],
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde-serialization", derive(Deserialize, Serialize))]
+pub struct MockVRStageKeyframe {
+    /// Time in milliseconds since the start of the timeline.
+    pub time: f64,
+    pub stage_parameters: VRStageParameters,
+}
+
+/// Describes a mock gamepad when it is connected.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde-serialization", derive(Deserialize, Serialize))]
+pub struct MockVRGamepadInit {
+    pub name: String,
+    pub hand: VRGamepadHand,
+    pub button_count: usize,
+    pub axis_count: usize,
+}
+
+/// The simulated display timing.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde-serialization", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde-serialization", serde(default))]
+pub struct MockVRFrameTiming {
+    /// Refresh rate in Hz. Defaults to 1000Hz, so that frames are resolved quickly.
+    pub refresh_rate: f64,
+
+    /// Time in milliseconds from vsync until the frame is shown.
+    /// Frame poses are predicted this far ahead.
+    pub latency: f64,
+
+    /// Repeating pattern of vsyncs, where true means the vsync is dropped
+    /// and frame data is only resolved at a later one.
+    pub dropped_frames: Vec<bool>,
+
+    /// Repeating pattern of offsets in milliseconds, added to each vsync.
+    pub jitter: Vec<f64>,
+}
+
+impl Default for MockVRFrameTiming {
+    fn default() -> MockVRFrameTiming {
+        MockVRFrameTiming {
+            refresh_rate: 1000.0,
+            latency: 0.0,
+            dropped_frames: Vec::new(),
+            jitter: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde-serialization", derive(Deserialize, Serialize))]
+pub struct MockVRTrackingLoss {
+    pub position: bool,
+    pub orientation: bool,
+    /// How long tracking stays lost, in milliseconds. None means until `RestoreTracking`.
+    pub duration: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde-serialization", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde-serialization", serde(default))]
+pub struct MockVRTrackingNoise {
+    /// Largest random offset added to each position coordinate, in meters.
+    pub position: f32,
+    /// Largest random rotation added around each axis, in radians.
+    pub orientation: f32,
+    /// Position drift in meters per second.
+    pub position_drift: [f32; 3],
+    /// Drift around the vertical axis in radians per second.
+    pub yaw_drift: f32,
+    /// Seed of the random noise, so that runs can be reproduced.
+    pub seed: u64,
+}
+
+impl MockVRTimeline {
+    /// Length of the timeline in milliseconds.
+    pub fn duration(&self) -> f64 {
+        let pose = self.pose.iter().map(|k| k.time);
+        let stage = self.stage.iter().map(|k| k.time);
+        pose.chain(stage).fold(0.0, f64::max)
+    }
+
+    #[cfg(feature = "serde-serialization")]
+    pub fn from_json(json: &str) -> Result<MockVRTimeline, String> {
+        serde_json::from_str(json).map_err(|e| e.to_string())
+    }
+
+    #[cfg(feature = "serde-serialization")]
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<MockVRTimeline, String> {
+        let json = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        MockVRTimeline::from_json(&json)
+    }
+}
+
+/// The static description of a mock headset.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde-serialization", derive(Deserialize, Serialize))]
+pub struct MockVRDisplayProfile {
+    pub display_name: String,
+    pub capabilities: VRDisplayCapabilities,
+    pub stage_parameters: Option<VRStageParameters>,
+    pub left_eye_parameters: VREyeParameters,
+    pub right_eye_parameters: VREyeParameters,
+}
+
+impl MockVRDisplayProfile {
+    /// A room scale PC headset, modelled on the HTC Vive.
+    pub fn htc_vive() -> MockVRDisplayProfile {
+        let mut left_eye_parameters = eye_parameters(1512, 1680, 55.82093048095703, 51.26948547363281,
+                                                     55.707801818847656, 54.42263412475586);
+        left_eye_parameters.offset = [0.035949998, 0.0, 0.015];
+        let mut right_eye_parameters = eye_parameters(1512, 1680, 55.898048400878906, 54.37410354614258,
+                                                      55.614715576171875, 51.304901123046875);
+        right_eye_parameters.offset = [-0.035949998, 0.0, 0.015];
+
+        MockVRDisplayProfile {
+            display_name: "Mock VRDisplay".into(),
+            capabilities: capabilities(true, true),
+            stage_parameters: Some(VRStageParameters {
+                sitting_to_standing_transform: [-0.9317312, 0.0, 0.36314875, 0.0, 0.0, 0.99999994, 0.0, 0.0, -0.36314875,
+                                                0.0, -0.9317312, 0.0, 0.23767996, 1.6813644, 0.45370483, 1.0],
+                size_x: 2.0,
+                size_z: 2.0
+            }),
+            left_eye_parameters,
+            right_eye_parameters,
+        }
+    }
+
+    /// A phone in a Cardboard viewer: orientation only, rendered on the phone screen.
+    pub fn cardboard() -> MockVRDisplayProfile {
+        let (left_eye_parameters, right_eye_parameters) =
+            symmetric_eyes(0.032, 1080, 1080, 40.0, 40.0, 40.0, 40.0);
+        MockVRDisplayProfile {
+            display_name: "Mock Cardboard".into(),
+            capabilities: capabilities(false, false),
+            stage_parameters: None,
+            left_eye_parameters,
+            right_eye_parameters,
+        }
+    }
+
+    /// A standalone headset which only tracks orientation.
+    pub fn three_dof() -> MockVRDisplayProfile {
+        let (left_eye_parameters, right_eye_parameters) =
+            symmetric_eyes(0.032, 1280, 1440, 45.0, 45.0, 45.0, 45.0);
+        MockVRDisplayProfile {
+            display_name: "Mock 3DoF Headset".into(),
+            capabilities: capabilities(false, false),
+            stage_parameters: None,
+            left_eye_parameters,
+            right_eye_parameters,
+        }
+    }
+
+    /// A standalone headset with inside-out position tracking and a play area.
+    pub fn standalone() -> MockVRDisplayProfile {
+        let (left_eye_parameters, right_eye_parameters) =
+            symmetric_eyes(0.032, 1440, 1600, 48.0, 44.0, 55.0, 52.0);
+        MockVRDisplayProfile {
+            display_name: "Mock Standalone Headset".into(),
+            capabilities: capabilities(true, false),
+            stage_parameters: Some(VRStageParameters {
+                sitting_to_standing_transform: [1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0,
+                                                0.0, 0.0, 1.0, 0.0, 0.0, 1.6, 0.0, 1.0],
+                size_x: 2.5,
+                size_z: 2.5
+            }),
+            left_eye_parameters,
+            right_eye_parameters,
+        }
+    }
+
+    /// A position tracked PC headset used seated, without any stage.
+    pub fn no_stage() -> MockVRDisplayProfile {
+        let (left_eye_parameters, right_eye_parameters) =
+            symmetric_eyes(0.032, 1344, 1600, 41.65, 35.8, 48.0, 43.97);
+        MockVRDisplayProfile {
+            display_name: "Mock Seated Headset".into(),
+            capabilities: capabilities(true, true),
+            stage_parameters: None,
+            left_eye_parameters,
+            right_eye_parameters,
+        }
+    }
+
+    /// Looks up a built-in profile: "vive", "cardboard", "3dof", "standalone" or "no-stage".
+    pub fn named(name: &str) -> Option<MockVRDisplayProfile> {
+        match name {
+            "vive" => Some(MockVRDisplayProfile::htc_vive()),
+            "cardboard" => Some(MockVRDisplayProfile::cardboard()),
+            "3dof" => Some(MockVRDisplayProfile::three_dof()),
+            "standalone" => Some(MockVRDisplayProfile::standalone()),
+            "no-stage" => Some(MockVRDisplayProfile::no_stage()),
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "serde-serialization")]
+    pub fn from_json(json: &str) -> Result<MockVRDisplayProfile, String> {
+        serde_json::from_str(json).map_err(|e| e.to_string())
+    }
+
+    #[cfg(feature = "serde-serialization")]
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<MockVRDisplayProfile, String> {
+        let json = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        MockVRDisplayProfile::from_json(&json)
+    }
+}
+
+fn capabilities(has_position: bool, has_external_display: bool) -> VRDisplayCapabilities {
+    VRDisplayCapabilities {
+        has_position,
+        has_orientation: true,
+        has_external_display,
+        can_present: true,
+        max_layers: 1,
+        ..VRDisplayCapabilities::default()
+    }
+}
+
+fn eye_parameters(width: u32, height: u32, up: f64, right: f64, down: f64, left: f64) -> VREyeParameters {
+    VREyeParameters {
+        offset: [0.0, 0.0, 0.0],
+        render_width: width,
+        render_height: height,
+        field_of_view: VRFieldOfView {
+            up_degrees: up,
+            right_degrees: right,
+            down_degrees: down,
+            left_degrees: left,
+        },
+    }
+}
+
+// Left and right eyes which mirror each other, given the left eye field of view.
+// The offsets follow the Vive profile, which the mock has always reported:
+// the left eye is at +x and the right eye at -x.
+fn symmetric_eyes(half_ipd: f32, width: u32, height: u32, up: f64, right: f64, down: f64, left: f64)
+                  -> (VREyeParameters, VREyeParameters) {
+    let mut left_eye = eye_parameters(width, height, up, right, down, left);
+    left_eye.offset = [half_ipd, 0.0, 0.0];
+    let mut right_eye = eye_parameters(width, height, up, left, down, right);
+    right_eye.offset = [-half_ipd, 0.0, 0.0];
+    (left_eye, right_eye)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles_share_an_eye_offset_convention() {
+        for name in &["vive", "cardboard", "3dof", "standalone", "no-stage"] {
+            let profile = MockVRDisplayProfile::named(name).unwrap();
+            assert!(profile.left_eye_parameters.offset[0] > 0.0, "{}", name);
+            assert_eq!(profile.left_eye_parameters.offset[0], -profile.right_eye_parameters.offset[0], "{}", name);
+        }
+    }
+
+    #[test]
+    fn local_sender_replies() {
+        let (sender, receiver) = ::std::sync::mpsc::channel();
+        let sender: MockVRSender<u32> = sender.into();
+        sender.send(3).unwrap();
+        assert_eq!(receiver.recv().unwrap(), 3);
+    }
+
+    #[cfg(feature = "ipc")]
+    #[test]
+    fn ipc_sender_crosses_ipc() {
+        use ipc_channel::ipc;
+        let (sender, receiver) = ipc::channel().unwrap();
+        let (msg_sender, msg_receiver) = ipc::channel().unwrap();
+        msg_sender.send(MockVRControlMsg::GetDisplayData(sender.into())).unwrap();
+        match msg_receiver.recv().unwrap() {
+            MockVRControlMsg::GetDisplayData(sender) => sender.send(VRDisplayData::default()).unwrap(),
+            msg => panic!("Unexpected {:?}", msg),
+        }
+        assert_eq!(receiver.recv().unwrap().display_id, 0);
+    }
+
+    #[test]
+    fn symmetric_eyes_mirror_the_field_of_view() {
+        let (left, right) = symmetric_eyes(0.03, 100, 200, 1.0, 2.0, 3.0, 4.0);
+        assert_eq!(left.field_of_view.right_degrees, right.field_of_view.left_degrees);
+        assert_eq!(left.field_of_view.left_degrees, right.field_of_view.right_degrees);
+        assert_eq!(left.field_of_view.up_degrees, right.field_of_view.up_degrees);
+    }
 }
@@ -1,9 +1,13 @@
 #[cfg(feature = "utils")]
 
 use std::mem;
+use std::sync::{Arc, Condvar, Mutex};
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering::SeqCst;
+use std::thread;
+use std::time::Duration;
 use time;
+use VRFieldOfView;
 
 static DEVICE_ID_COUNTER: AtomicUsize  = AtomicUsize::new(0);
 
@@ -20,6 +24,67 @@ pub fn timestamp() -> f64 {
     timespec.sec as f64 * 1000.0 + (timespec.nsec as f64 * 1e-6)
 }
 
+// A source of time for VR backends, in milliseconds
+pub trait VRClock: Send + Sync {
+    // Returns the current time in milliseconds
+    fn now(&self) -> f64;
+
+    // Blocks until the clock has reached the given time
+    fn wait_until(&self, time: f64);
+}
+
+pub type VRClockPtr = Arc<dyn VRClock>;
+
+// The wall clock, as used by `timestamp`
+pub struct SystemClock;
+
+impl VRClock for SystemClock {
+    fn now(&self) -> f64 {
+        timestamp()
+    }
+
+    fn wait_until(&self, time: f64) {
+        let remaining = time - timestamp();
+        if remaining > 0.0 {
+            thread::sleep(Duration::from_micros((remaining * 1000.0) as u64));
+        }
+    }
+}
+
+// A clock which only moves when told to, so tests can be deterministic.
+// Waiting on a manual clock blocks until it is set or advanced past the deadline.
+#[derive(Clone)]
+pub struct ManualClock(Arc<(Mutex<f64>, Condvar)>);
+
+impl ManualClock {
+    pub fn new(time: f64) -> ManualClock {
+        ManualClock(Arc::new((Mutex::new(time), Condvar::new())))
+    }
+
+    pub fn set(&self, time: f64) {
+        *(self.0).0.lock().unwrap() = time;
+        (self.0).1.notify_all();
+    }
+
+    pub fn advance(&self, delta: f64) {
+        *(self.0).0.lock().unwrap() += delta;
+        (self.0).1.notify_all();
+    }
+}
+
+impl VRClock for ManualClock {
+    fn now(&self) -> f64 {
+        *(self.0).0.lock().unwrap()
+    }
+
+    fn wait_until(&self, time: f64) {
+        let mut now = (self.0).0.lock().unwrap();
+        while *now < time {
+            now = (self.0).1.wait(now).unwrap();
+        }
+    }
+}
+
 // Multiply 4x4 matrices
 #[allow(dead_code)]
 pub fn multiply_matrix(a: &[f32; 16], b: &[f32; 16], out: &mut [f32; 16]) {
@@ -48,6 +113,28 @@ pub fn multiply_matrix(a: &[f32; 16], b: &[f32; 16], out: &mut [f32; 16]) {
     *out = tmp;
 }
 
+// Builds a column major projection matrix from a field of view
+#[allow(dead_code)]
+pub fn fov_to_projection_matrix(fov: &VRFieldOfView, near: f64, far: f64) -> [f32; 16] {
+    let up_tan = fov.up_degrees.to_radians().tan();
+    let down_tan = fov.down_degrees.to_radians().tan();
+    let left_tan = fov.left_degrees.to_radians().tan();
+    let right_tan = fov.right_degrees.to_radians().tan();
+    let x_scale = 2.0 / (left_tan + right_tan);
+    let y_scale = 2.0 / (up_tan + down_tan);
+    let nf = 1.0 / (near - far);
+
+    let mut out = [0.0; 16];
+    out[0] = x_scale as f32;
+    out[5] = y_scale as f32;
+    out[8] = (-(left_tan - right_tan) * x_scale * 0.5) as f32;
+    out[9] = ((up_tan - down_tan) * y_scale * 0.5) as f32;
+    out[10] = ((far + near) * nf) as f32;
+    out[11] = -1.0;
+    out[14] = (2.0 * far * near * nf) as f32;
+    out
+}
+
 #[allow(dead_code)]
 pub fn inverse_matrix(m: &[f32; 16], out: &mut [f32; 16]) -> bool {
     adjoint_matrix(&m, out);
@@ -137,3 +224,98 @@ pub fn copysign(a: f32, b: f32) -> f32 {
         a.abs() * b.signum()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn system_clock_follows_the_timestamp() {
+        let before = timestamp();
+        let now = SystemClock.now();
+        assert!(now >= before && now <= timestamp());
+        SystemClock.wait_until(now + 10.0);
+        assert!(SystemClock.now() >= now + 10.0);
+        // Deadlines in the past don't wait.
+        SystemClock.wait_until(0.0);
+    }
+
+    #[test]
+    fn manual_clock_only_moves_when_told_to() {
+        let clock = ManualClock::new(5.0);
+        assert_eq!(clock.now(), 5.0);
+        clock.advance(2.5);
+        assert_eq!(clock.now(), 7.5);
+        clock.set(1.0);
+        assert_eq!(clock.now(), 1.0);
+        // Clones share the time.
+        clock.clone().set(3.0);
+        assert_eq!(clock.now(), 3.0);
+        clock.wait_until(2.0);
+        assert_eq!(clock.now(), 3.0);
+    }
+
+    #[test]
+    fn manual_clock_waits_until_it_is_advanced() {
+        let clock = ManualClock::new(0.0);
+        let (sender, waited) = channel();
+        let waiter = {
+            let clock = clock.clone();
+            thread::spawn(move || {
+                clock.wait_until(10.0);
+                sender.send(clock.now()).unwrap();
+            })
+        };
+        clock.advance(5.0);
+        assert!(waited.recv_timeout(Duration::from_millis(50)).is_err());
+        assert_eq!(clock.now(), 5.0);
+        clock.set(10.0);
+        assert_eq!(waited.recv_timeout(Duration::from_secs(10)).unwrap(), 10.0);
+        waiter.join().unwrap();
+    }
+
+    // Projects a view space point, and divides by w.
+    fn project(m: &[f32; 16], p: [f32; 3]) -> [f32; 3] {
+        let w = m[3] * p[0] + m[7] * p[1] + m[11] * p[2] + m[15];
+        [
+            (m[0] * p[0] + m[4] * p[1] + m[8] * p[2] + m[12]) / w,
+            (m[1] * p[0] + m[5] * p[1] + m[9] * p[2] + m[13]) / w,
+            (m[2] * p[0] + m[6] * p[1] + m[10] * p[2] + m[14]) / w,
+        ]
+    }
+
+    fn assert_near(actual: [f32; 3], expected: [f32; 3]) {
+        for i in 0..3 {
+            assert!((actual[i] - expected[i]).abs() < 1e-5, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn projection_maps_the_frustum_to_clip_space() {
+        let fov = VRFieldOfView {
+            up_degrees: 45.0,
+            right_degrees: 45.0,
+            down_degrees: 45.0,
+            left_degrees: 45.0,
+        };
+        let m = fov_to_projection_matrix(&fov, 1.0, 10.0);
+        assert_near(project(&m, [0.0, 0.0, -1.0]), [0.0, 0.0, -1.0]);
+        assert_near(project(&m, [1.0, 1.0, -1.0]), [1.0, 1.0, -1.0]);
+        assert_near(project(&m, [-10.0, -10.0, -10.0]), [-1.0, -1.0, 1.0]);
+    }
+
+    #[test]
+    fn projection_handles_asymmetric_fields_of_view() {
+        let fov = VRFieldOfView {
+            up_degrees: 20.0,
+            right_degrees: 60.0,
+            down_degrees: 40.0,
+            left_degrees: 30.0,
+        };
+        let m = fov_to_projection_matrix(&fov, 0.1, 100.0);
+        let tan = |degrees: f64| degrees.to_radians().tan() as f32;
+        assert_near(project(&m, [-tan(30.0), tan(20.0), -1.0]), [-1.0, 1.0, project(&m, [0.0, 0.0, -1.0])[2]]);
+        assert_near(project(&m, [tan(60.0), -tan(40.0), -1.0]), [1.0, -1.0, project(&m, [0.0, 0.0, -1.0])[2]]);
+    }
+}
@@ -0,0 +1,262 @@
+use {VREye, VRFrameData, VRLayer, VRCpuBuffer, VRQuadLayer, VRTexture};
+use utils::{inverse_matrix, multiply_matrix};
+
+/// Composites layers held in CPU buffers into per-eye images, without a GL context.
+/// The projection layer is copied as is if it has a `VRTexture::Cpu` texture,
+/// and quad layers are then blended over it in order.
+pub struct VRCompositor {
+    width: u32,
+    height: u32,
+}
+
+impl VRCompositor {
+    /// The width and height are the size of each eye image.
+    pub fn new(width: u32, height: u32) -> VRCompositor {
+        VRCompositor {
+            width,
+            height,
+        }
+    }
+
+    /// Returns the left and right eye images.
+    pub fn compose(&self, frame_data: &VRFrameData, layer: Option<&VRLayer>, quads: &[VRQuadLayer])
+                   -> (VRCpuBuffer, VRCpuBuffer) {
+        (self.compose_eye(VREye::Left, frame_data, layer, quads),
+         self.compose_eye(VREye::Right, frame_data, layer, quads))
+    }
+
+    pub fn compose_eye(&self, eye: VREye, frame_data: &VRFrameData, layer: Option<&VRLayer>, quads: &[VRQuadLayer])
+                       -> VRCpuBuffer {
+        let mut image = VRCpuBuffer::new(self.width, self.height);
+
+        if let Some(layer) = layer {
+            if let VRTexture::Cpu(ref buffer) = layer.texture {
+                let bounds = match eye {
+                    VREye::Left => &layer.left_bounds,
+                    VREye::Right => &layer.right_bounds,
+                };
+                self.draw_projection(&mut image, buffer, bounds);
+            }
+        }
+
+        self.compose_quads(eye, frame_data, &mut image, quads);
+        image
+    }
+
+    /// Blends quad layers over an existing eye image, which may be any size.
+    pub fn compose_quads(&self, eye: VREye, frame_data: &VRFrameData, image: &mut VRCpuBuffer, quads: &[VRQuadLayer]) {
+        if quads.is_empty() {
+            return;
+        }
+
+        let (projection, view) = match eye {
+            VREye::Left => (&frame_data.left_projection_matrix, &frame_data.left_view_matrix),
+            VREye::Right => (&frame_data.right_projection_matrix, &frame_data.right_view_matrix),
+        };
+
+        let mut view_projection = [0.0; 16];
+        let mut clip_to_view = [0.0; 16];
+        multiply_matrix(projection, view, &mut view_projection);
+        // Quads can't be placed without an invertible view projection, so leave them out.
+        if !inverse_matrix(&view_projection, &mut clip_to_view) {
+            return;
+        }
+        for quad in quads {
+            draw_quad(image, quad, &clip_to_view);
+        }
+    }
+
+    fn draw_projection(&self, image: &mut VRCpuBuffer, buffer: &VRCpuBuffer, bounds: &[f32; 4]) {
+        for y in 0..self.height {
+            let v = bounds[1] + (y as f32 + 0.5) / self.height as f32 * bounds[3];
+            for x in 0..self.width {
+                let u = bounds[0] + (x as f32 + 0.5) / self.width as f32 * bounds[2];
+                image.set_pixel(x, y, buffer.sample(u, v));
+            }
+        }
+    }
+}
+
+// Casts a ray through each pixel, and finds where it crosses the plane of the quad.
+fn draw_quad(image: &mut VRCpuBuffer, quad: &VRQuadLayer, clip_to_view: &[f32; 16]) {
+    let mut view_to_quad = [0.0; 16];
+    let mut clip_to_quad = [0.0; 16];
+    if !inverse_matrix(&quad.transform, &mut view_to_quad) {
+        return;
+    }
+    multiply_matrix(&view_to_quad, clip_to_view, &mut clip_to_quad);
+
+    let (half_width, half_height) = (quad.size[0] / 2.0, quad.size[1] / 2.0);
+    let bounds = &quad.bounds;
+
+    let (width, height) = (image.width, image.height);
+    for y in 0..height {
+        // Images start at the top, clip space starts at the bottom.
+        let clip_y = 1.0 - (y as f32 + 0.5) / height as f32 * 2.0;
+        for x in 0..width {
+            let clip_x = (x as f32 + 0.5) / width as f32 * 2.0 - 1.0;
+            let near = transform_point(&clip_to_quad, [clip_x, clip_y, -1.0]);
+            let far = transform_point(&clip_to_quad, [clip_x, clip_y, 1.0]);
+            let dz = far[2] - near[2];
+            if dz == 0.0 {
+                continue;
+            }
+            let t = -near[2] / dz;
+            if !(0.0..=1.0).contains(&t) {
+                continue;
+            }
+            let px = near[0] + (far[0] - near[0]) * t;
+            let py = near[1] + (far[1] - near[1]) * t;
+            if px.abs() > half_width || py.abs() > half_height {
+                continue;
+            }
+            let u = bounds[0] + (px / quad.size[0] + 0.5) * bounds[2];
+            let v = bounds[1] + (0.5 - py / quad.size[1]) * bounds[3];
+            let color = blend(quad.buffer.sample(u, v), image.pixel(x, y));
+            image.set_pixel(x, y, color);
+        }
+    }
+}
+
+fn transform_point(m: &[f32; 16], p: [f32; 3]) -> [f32; 3] {
+    let x = m[0] * p[0] + m[4] * p[1] + m[8] * p[2] + m[12];
+    let y = m[1] * p[0] + m[5] * p[1] + m[9] * p[2] + m[13];
+    let z = m[2] * p[0] + m[6] * p[1] + m[10] * p[2] + m[14];
+    let w = m[3] * p[0] + m[7] * p[1] + m[11] * p[2] + m[15];
+    [x / w, y / w, z / w]
+}
+
+// Source over blending, with straight (not premultiplied) alpha.
+fn blend(src: [u8; 4], dst: [u8; 4]) -> [u8; 4] {
+    let src_a = src[3] as f32 / 255.0;
+    let dst_a = dst[3] as f32 / 255.0;
+    let out_a = src_a + dst_a * (1.0 - src_a);
+    if out_a == 0.0 {
+        return [0, 0, 0, 0];
+    }
+    let channel = |i: usize| {
+        let c = (src[i] as f32 * src_a + dst[i] as f32 * dst_a * (1.0 - src_a)) / out_a;
+        c.round() as u8
+    };
+    [channel(0), channel(1), channel(2), (out_a * 255.0).round() as u8]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::fov_to_projection_matrix;
+    use VRFieldOfView;
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+    const WHITE: [u8; 4] = [255, 255, 255, 255];
+    const CLEAR: [u8; 4] = [0, 0, 0, 0];
+
+    // Both eyes look down -Z from the origin, seeing one meter either side at one meter away.
+    fn frame_data() -> VRFrameData {
+        let fov = VRFieldOfView {
+            up_degrees: 45.0,
+            right_degrees: 45.0,
+            down_degrees: 45.0,
+            left_degrees: 45.0,
+        };
+        let projection = fov_to_projection_matrix(&fov, 0.1, 100.0);
+        VRFrameData {
+            left_projection_matrix: projection,
+            right_projection_matrix: projection,
+            ..VRFrameData::default()
+        }
+    }
+
+    fn solid(width: u32, height: u32, rgba: [u8; 4]) -> VRCpuBuffer {
+        let mut buffer = VRCpuBuffer::new(width, height);
+        for pixel in buffer.data.chunks_mut(4) {
+            pixel.copy_from_slice(&rgba);
+        }
+        buffer
+    }
+
+    // A one meter quad, the given offset from the viewer.
+    fn quad(rgba: [u8; 4], x: f32, z: f32) -> VRQuadLayer {
+        let mut quad = VRQuadLayer {
+            buffer: solid(1, 1, rgba),
+            ..VRQuadLayer::default()
+        };
+        quad.transform[12] = x;
+        quad.transform[14] = z;
+        quad
+    }
+
+    #[test]
+    fn projection_layer_is_split_by_bounds() {
+        let mut buffer = VRCpuBuffer::new(2, 1);
+        buffer.set_pixel(0, 0, RED);
+        buffer.set_pixel(1, 0, BLUE);
+        let layer = VRLayer {
+            texture: VRTexture::Cpu(buffer),
+            ..VRLayer::default()
+        };
+        let (left, right) = VRCompositor::new(4, 4).compose(&frame_data(), Some(&layer), &[]);
+        assert_eq!((left.width, left.height), (4, 4));
+        assert!(left.data.chunks(4).all(|pixel| pixel == RED));
+        assert!(right.data.chunks(4).all(|pixel| pixel == BLUE));
+    }
+
+    #[test]
+    fn non_cpu_projection_layer_is_left_out() {
+        let (left, _) = VRCompositor::new(2, 2).compose(&frame_data(), Some(&VRLayer::default()), &[]);
+        assert!(left.data.chunks(4).all(|pixel| pixel == CLEAR));
+    }
+
+    #[test]
+    fn quad_is_placed_in_front_of_the_viewer() {
+        let (left, right) = VRCompositor::new(8, 8).compose(&frame_data(), None, &[quad(RED, 0.0, -1.0)]);
+        for image in &[left, right] {
+            // The quad covers the middle half of the view.
+            for y in 0..8 {
+                for x in 0..8 {
+                    let inside = (2..6).contains(&x) && (2..6).contains(&y);
+                    assert_eq!(image.pixel(x, y), if inside { RED } else { CLEAR }, "({}, {})", x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn quads_are_blended_over_the_layer() {
+        let layer = VRLayer {
+            texture: VRTexture::Cpu(solid(1, 1, WHITE)),
+            ..VRLayer::default()
+        };
+        let translucent = quad([255, 0, 0, 128], 0.0, -1.0);
+        let (left, _) = VRCompositor::new(8, 8).compose(&frame_data(), Some(&layer), &[translucent]);
+        assert_eq!(left.pixel(4, 4), [255, 127, 127, 255]);
+        assert_eq!(left.pixel(0, 0), WHITE);
+    }
+
+    #[test]
+    fn later_quads_are_drawn_on_top() {
+        let quads = [quad(RED, 0.0, -1.0), quad(BLUE, 0.0, -2.0)];
+        let (left, _) = VRCompositor::new(8, 8).compose(&frame_data(), None, &quads);
+        assert_eq!(left.pixel(4, 4), BLUE);
+        assert_eq!(left.pixel(2, 2), RED);
+    }
+
+    #[test]
+    fn quads_out_of_view_are_ignored() {
+        let quads = [quad(RED, 0.0, 1.0), quad(RED, 10.0, -1.0), quad(RED, 0.0, -1000.0)];
+        let (left, right) = VRCompositor::new(8, 8).compose(&frame_data(), None, &quads);
+        assert!(left.data.chunks(4).all(|pixel| pixel == CLEAR));
+        assert!(right.data.chunks(4).all(|pixel| pixel == CLEAR));
+    }
+
+    #[test]
+    fn quads_are_composed_over_images_of_any_size() {
+        let mut image = solid(16, 4, WHITE);
+        VRCompositor::new(8, 8).compose_quads(VREye::Left, &frame_data(), &mut image, &[quad(RED, 0.0, -1.0)]);
+        assert_eq!(image.pixel(4, 1), RED);
+        assert_eq!(image.pixel(11, 2), RED);
+        assert_eq!(image.pixel(3, 1), WHITE);
+        assert_eq!(image.pixel(12, 1), WHITE);
+    }
+}
@@ -1,4 +1,5 @@
-use {VRDisplayData, VRFramebuffer, VRFramebufferAttributes, VRFrameData, VRFutureFrameData, VRGamepadPtr, VRLayer};
+use {VRDisplayData, VRFramebuffer, VRFramebufferAttributes, VRFrameData, VRFutureFrameData, VRGamepadPtr, VRLayer, VRQuadLayer};
+use VRTextureKind;
 use gleam::gl::Gl;
 use std::sync::Arc;
 use std::cell::RefCell;
@@ -53,6 +54,21 @@ pub trait VRDisplay: Send + Sync {
     /// Must be called in the render thread, before doing any work
     fn get_framebuffers(&self) -> Vec<VRFramebuffer>;
 
+    /// Returns the kinds of VRLayer texture this display can present
+    fn supported_textures(&self) -> Vec<VRTextureKind> {
+        vec![VRTextureKind::Gl]
+    }
+
+    /// Returns whether this display composites the quad layers passed to `render_quad_layer`
+    fn supports_quad_layers(&self) -> bool {
+        false
+    }
+
+    /// Adds a quad layer to the next frame, which is composited over the frame's VRLayer
+    /// when the frame is submitted. Displays which don't support quad layers ignore it.
+    /// Must be called in the render thread
+    fn render_quad_layer(&mut self, _quad: &VRQuadLayer) {}
+
     /// Renders a VRLayer from a external texture
     /// Must be called in the render thread
     #[deprecated(since="0.10.3", note="please use `submit_layer` instead")]
@@ -1,10 +1,13 @@
+use {VRCpuBuffer, VRTexture};
+
 /// Data provided to a VRDisplay and presented in the HMD.
 #[derive(Debug, Clone)]
 #[cfg_attr(feature = "serde-serialization", derive(Deserialize, Serialize))]
 pub struct VRLayer {
     /// Source texture whose contents will be presented by the 
     /// VRDisplay when VRDisplay.submitFrame() is called.
-    pub texture_id: u32,
+    /// Check `VRDisplay::supported_textures` for the kinds of texture a display accepts.
+    pub texture: VRTexture,
 
     /// UVs defining the texture bounds to present to the eye in UV space: [x,y,w,h]
     /// Defaults to [0.0, 0.0, 0.5, 1.0]
@@ -21,10 +24,40 @@ pub struct VRLayer {
 impl Default for VRLayer {
     fn default() -> VRLayer {
         VRLayer {
-            texture_id: 0,
+            texture: VRTexture::Gl(0),
             left_bounds: [0.0, 0.0, 0.5, 1.0],
             right_bounds: [0.5, 0.0, 0.5, 1.0],
             texture_size : None
         }
     }
-}
\ No newline at end of file
+}
+
+/// A quad placed in the scene, composited on top of the projection layer.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde-serialization", derive(Deserialize, Serialize))]
+pub struct VRQuadLayer {
+    /// Source pixels of the quad.
+    pub buffer: VRCpuBuffer,
+
+    /// UVs defining the buffer bounds to show on the quad: [x,y,w,h]
+    /// Defaults to [0.0, 0.0, 1.0, 1.0]
+    pub bounds: [f32; 4],
+
+    /// Major order column matrix placing the quad in the space the view matrices transform from.
+    /// The quad is centered on the origin of the XY plane, facing +Z.
+    pub transform: [f32; 16],
+
+    /// Width and height of the quad in meters.
+    pub size: [f32; 2],
+}
+
+impl Default for VRQuadLayer {
+    fn default() -> VRQuadLayer {
+        VRQuadLayer {
+            buffer: VRCpuBuffer::new(0, 0),
+            bounds: [0.0, 0.0, 1.0, 1.0],
+            transform: identity_matrix!(),
+            size: [1.0, 1.0],
+        }
+    }
+}
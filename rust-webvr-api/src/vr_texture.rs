@@ -0,0 +1,142 @@
+use std::fmt;
+use std::os::raw::c_int;
+
+/// The source of the pixels presented in a VRLayer.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde-serialization", derive(Deserialize, Serialize))]
+pub enum VRTexture {
+    /// A GL_TEXTURE_2D texture name.
+    Gl(u32),
+
+    /// A GL_TEXTURE_EXTERNAL_OES texture name, as filled by video decoders and cameras.
+    GlExternalOes(u32),
+
+    /// One layer of a GL_TEXTURE_2D_ARRAY texture, as rendered with multiview.
+    GlArrayLayer {
+        texture_id: u32,
+        layer: u32,
+    },
+
+    /// A Linux dmabuf, as exported by Vulkan, EGL or V4L2.
+    Dmabuf(VRDmabuf),
+
+    /// The handle of an Android SurfaceTexture registered with Gecko.
+    GeckoSurfaceTexture(u64),
+
+    /// Pixels in CPU memory, for callers without a GL context.
+    Cpu(VRCpuBuffer),
+}
+
+impl VRTexture {
+    pub fn kind(&self) -> VRTextureKind {
+        match *self {
+            VRTexture::Gl(..) => VRTextureKind::Gl,
+            VRTexture::GlExternalOes(..) => VRTextureKind::GlExternalOes,
+            VRTexture::GlArrayLayer { .. } => VRTextureKind::GlArrayLayer,
+            VRTexture::Dmabuf(..) => VRTextureKind::Dmabuf,
+            VRTexture::GeckoSurfaceTexture(..) => VRTextureKind::GeckoSurfaceTexture,
+            VRTexture::Cpu(..) => VRTextureKind::Cpu,
+        }
+    }
+}
+
+/// The kinds of VRTexture, used by displays to advertise what they accept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde-serialization", derive(Deserialize, Serialize))]
+pub enum VRTextureKind {
+    Gl,
+    GlExternalOes,
+    GlArrayLayer,
+    Dmabuf,
+    GeckoSurfaceTexture,
+    Cpu,
+}
+
+/// A Linux dmabuf, described the way EGL_EXT_image_dma_buf_import expects it.
+/// The file descriptors are borrowed: they are not duplicated or closed by the VRTexture,
+/// and are only meaningful in the process which owns them.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde-serialization", derive(Deserialize, Serialize))]
+pub struct VRDmabuf {
+    pub width: u32,
+    pub height: u32,
+
+    /// DRM fourcc code of the pixel format, e.g. DRM_FORMAT_ABGR8888.
+    pub fourcc: u32,
+
+    /// DRM format modifier describing the tiling and compression of the buffer.
+    pub modifier: u64,
+
+    /// One entry per plane, at most 4.
+    pub planes: Vec<VRDmabufPlane>,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde-serialization", derive(Deserialize, Serialize))]
+pub struct VRDmabufPlane {
+    pub fd: c_int,
+    pub offset: u32,
+    pub stride: u32,
+}
+
+/// An RGBA image in CPU memory, 8 bits per channel, with the first row at the top.
+/// UV coordinates start at the top left.
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde-serialization", derive(Deserialize, Serialize))]
+pub struct VRCpuBuffer {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl VRCpuBuffer {
+    /// Creates a transparent black buffer.
+    pub fn new(width: u32, height: u32) -> VRCpuBuffer {
+        VRCpuBuffer {
+            width,
+            height,
+            data: vec![0; (width as usize) * (height as usize) * 4],
+        }
+    }
+
+    /// Wraps existing RGBA pixels, which must match the given size.
+    pub fn from_rgba(width: u32, height: u32, data: Vec<u8>) -> Result<VRCpuBuffer, String> {
+        let expected = (width as usize) * (height as usize) * 4;
+        if data.len() != expected {
+            return Err(format!("Expected {} bytes for a {}x{} buffer, got {}", expected, width, height, data.len()));
+        }
+        Ok(VRCpuBuffer { width, height, data })
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = self.offset(x, y);
+        [self.data[i], self.data[i + 1], self.data[i + 2], self.data[i + 3]]
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32, rgba: [u8; 4]) {
+        let i = self.offset(x, y);
+        self.data[i..i + 4].copy_from_slice(&rgba);
+    }
+
+    /// Returns the nearest pixel to the given UV coordinates, clamped to the edges.
+    /// An empty buffer samples as transparent black.
+    pub fn sample(&self, u: f32, v: f32) -> [u8; 4] {
+        if self.width == 0 || self.height == 0 {
+            return [0, 0, 0, 0];
+        }
+        let x = (u * self.width as f32).floor().max(0.0).min((self.width - 1) as f32) as u32;
+        let y = (v * self.height as f32).floor().max(0.0).min((self.height - 1) as f32) as u32;
+        self.pixel(x, y)
+    }
+
+    fn offset(&self, x: u32, y: u32) -> usize {
+        ((y as usize) * (self.width as usize) + (x as usize)) * 4
+    }
+}
+
+// The pixels are left out, they would swamp any debug output.
+impl fmt::Debug for VRCpuBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "VRCpuBuffer({}x{})", self.width, self.height)
+    }
+}
@@ -0,0 +1,170 @@
+// A reference implementation of the system side of the vrexternal protocol,
+// for testing the vrexternal backend on desktop Linux without a VR runtime.
+// It creates the shared memory, describes a headset which slowly turns around,
+// and acknowledges the frames the browser submits. There is a right hand controller
+// which clicks its trigger every second, and a left hand controller which comes and goes.
+//
+// Usage: cargo run --example vrexternal_producer -- [--replace] [NAME [SECONDS]]
+// then open NAME (by default /rust-webvr-external) with VRExternalShmemPtr::open.
+// With --replace, any existing shared memory with that name is replaced.
+
+extern crate rust_webvr;
+
+#[cfg(target_os = "linux")]
+fn main() {
+    linux::main()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn main() {
+    println!("The vrexternal producer only runs on Linux");
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use rust_webvr::api::mozgfx::*;
+    use rust_webvr::api::VRExternalShmemPtr;
+    use std::env;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    const DEFAULT_NAME: &str = "/rust-webvr-external";
+    const DISPLAY_NAME: &str = "rust-webvr reference producer";
+    const FRAME_TIME: Duration = Duration::from_millis(11);
+    const IPD: f32 = 0.064;
+    const EYE_HEIGHT: f32 = 1.6;
+    // How fast the headset turns, in radians per second.
+    const TURN_SPEED: f64 = 0.2;
+    // How long the left controller is connected, then disconnected, for.
+    const LEFT_CONTROLLER_PERIOD: f64 = 2.0;
+
+    pub fn main() {
+        let mut args = env::args().skip(1).peekable();
+        let replace = args.peek().is_some_and(|arg| arg == "--replace");
+        if replace {
+            args.next();
+        }
+        let name = args.next().unwrap_or_else(|| DEFAULT_NAME.to_owned());
+        let duration = args.next().map(|seconds| Duration::from_secs(seconds.parse().expect("Invalid duration")));
+
+        let shmem = if replace {
+            VRExternalShmemPtr::create_replacing(&name)
+        } else {
+            VRExternalShmemPtr::create(&name)
+        };
+        let shmem = shmem.unwrap_or_else(|err| panic!("{}", err));
+        println!("Created {}", name);
+
+        let mut state = VRSystemState::default();
+        init_display(&mut state.displayState);
+        state.enumerationCompleted = true;
+
+        let start = Instant::now();
+        let mut presenting = false;
+        while duration.is_none_or(|duration| start.elapsed() < duration) {
+            let elapsed = start.elapsed();
+            let seconds = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+            update_sensor(&mut state.sensorState, seconds);
+            update_controller(&mut state.controllerState[0], "Reference right controller", ControllerHand_Right, seconds);
+            if ((seconds / LEFT_CONTROLLER_PERIOD) as u64).is_multiple_of(2) {
+                update_controller(&mut state.controllerState[1], "Reference left controller", ControllerHand_Left, seconds);
+            } else {
+                state.controllerState[1] = VRControllerState::default();
+            }
+
+            let browser = shmem.pull_browser().unwrap_or_else(|err| panic!("{}", err));
+            let layer = browser.layerState[0];
+            if browser.presentationActive && layer.type_ == VRLayerType_LayerType_Stereo_Immersive {
+                let frame_id = unsafe { layer.__bindgen_anon_1.layer_stereo_immersive.frameId };
+                state.displayState.lastSubmittedFrameId = frame_id;
+                state.displayState.lastSubmittedFrameSuccessful = true;
+            }
+            if browser.presentationActive != presenting {
+                presenting = browser.presentationActive;
+                if !presenting {
+                    state.displayState.presentingGeneration += 1;
+                }
+                println!("Presentation {}", if presenting { "started" } else { "stopped" });
+            }
+
+            shmem.push_system(state).unwrap_or_else(|err| panic!("{}", err));
+            thread::sleep(FRAME_TIME);
+        }
+
+        // Let the browser know that we've gone.
+        state.displayState.isConnected = false;
+        state.displayState.shutdown = true;
+        let _ = shmem.push_system(state);
+        let _ = VRExternalShmemPtr::unlink(&name);
+        println!("Removed {}", name);
+    }
+
+    fn init_display(display: &mut VRDisplayState) {
+        for (dst, src) in display.displayName.iter_mut().zip(DISPLAY_NAME.bytes()) {
+            *dst = src as _;
+        }
+        display.capabilityFlags = VRDisplayCapabilityFlags_Cap_Position
+            | VRDisplayCapabilityFlags_Cap_Orientation
+            | VRDisplayCapabilityFlags_Cap_Present
+            | VRDisplayCapabilityFlags_Cap_External
+            | VRDisplayCapabilityFlags_Cap_MountDetection;
+        let fov = VRFieldOfView {
+            upDegrees: 45.0,
+            rightDegrees: 45.0,
+            downDegrees: 45.0,
+            leftDegrees: 45.0,
+        };
+        display.eyeFOV = [fov, fov];
+        display.eyeTranslation[VRDisplayState_Eye_Eye_Left as usize].x = -IPD / 2.0;
+        display.eyeTranslation[VRDisplayState_Eye_Eye_Right as usize].x = IPD / 2.0;
+        display.eyeResolution = IntSize_POD { width: 1024, height: 1024 };
+        display.isConnected = true;
+        display.isMounted = true;
+    }
+
+    // The headset stands still, turning around the y axis.
+    fn update_sensor(sensor: &mut VRHMDSensorState, seconds: f64) {
+        let yaw = (seconds * TURN_SPEED) as f32;
+        let (sin, cos) = (yaw.sin(), yaw.cos());
+        sensor.inputFrameID += 1;
+        sensor.timestamp = seconds * 1000.0;
+        sensor.flags = VRDisplayCapabilityFlags_Cap_Position | VRDisplayCapabilityFlags_Cap_Orientation;
+        sensor.pose.orientation = [0.0, (yaw / 2.0).sin(), 0.0, (yaw / 2.0).cos()];
+        sensor.pose.position = [0.0, EYE_HEIGHT, 0.0];
+
+        // The view matrix takes world space to eye space, undoing the position, the rotation, then the eye offset.
+        // Matrices are column major.
+        let view = |eye_x: f32| -> [f32; 16] {
+            [
+                cos, 0.0, sin, 0.0,
+                0.0, 1.0, 0.0, 0.0,
+                -sin, 0.0, cos, 0.0,
+                -eye_x, -EYE_HEIGHT, 0.0, 1.0,
+            ]
+        };
+        sensor.leftViewMatrix = view(-IPD / 2.0);
+        sensor.rightViewMatrix = view(IPD / 2.0);
+    }
+
+    // A controller held out in front of the headset, with a trigger and a thumbstick.
+    fn update_controller(controller: &mut VRControllerState, name: &str, hand: ControllerHand, seconds: f64) {
+        for (dst, src) in controller.controllerName.iter_mut().zip(name.bytes()) {
+            *dst = src as _;
+        }
+        controller.hand = hand;
+        controller.flags = ControllerCapabilityFlags_Cap_Position | ControllerCapabilityFlags_Cap_Orientation;
+        controller.numButtons = 2;
+        controller.numAxes = 2;
+        let trigger = seconds.fract() < 0.5;
+        controller.buttonPressed = trigger as u64;
+        controller.buttonTouched = controller.buttonPressed;
+        controller.triggerValue[0] = if trigger { 1.0 } else { 0.0 };
+        controller.axisValue[0] = seconds.sin() as f32;
+        controller.axisValue[1] = seconds.cos() as f32;
+        let x = if hand == ControllerHand_Left { -0.2 } else { 0.2 };
+        controller.pose.orientation = [0.0, 0.0, 0.0, 1.0];
+        controller.pose.position = [x, EYE_HEIGHT - 0.4, -0.3];
+        controller.isOrientationValid = true;
+        controller.isPositionValid = true;
+    }
+}
@@ -0,0 +1,226 @@
+use gleam::gl::Gl;
+use rust_webvr_api::utils;
+use rust_webvr_api::VRDisplay;
+use rust_webvr_api::VRDisplayData;
+use rust_webvr_api::VRFrameData;
+use rust_webvr_api::VRFramebuffer;
+use rust_webvr_api::VRFramebufferAttributes;
+use rust_webvr_api::VRFutureFrameData;
+use rust_webvr_api::VRGamepadPtr;
+use rust_webvr_api::VRLayer;
+use rust_webvr_api::VRTexture;
+use rust_webvr_api::VRTextureKind;
+use rust_webvr_api::VRViewport;
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
+use super::{DmabufVRRequest, DmabufVRResponse};
+use super::egl;
+use super::socket::{close_fds, DmabufVRSocket};
+
+pub type DmabufVRDisplayPtr = Arc<RefCell<DmabufVRDisplay>>;
+
+// Stands in for the display of the server process.
+pub struct DmabufVRDisplay {
+    id: u32,
+    socket: Mutex<DmabufVRSocket>,
+    // The display data last fetched from the server, which the framebuffers are sized by.
+    data: Mutex<Option<VRDisplayData>>,
+    pending_layer: Option<VRLayer>,
+}
+
+unsafe impl Sync for DmabufVRDisplay {}
+
+impl VRDisplay for DmabufVRDisplay {
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn data(&self) -> VRDisplayData {
+        match self.request(&DmabufVRRequest::GetDisplayData) {
+            Some(DmabufVRResponse::DisplayData(data)) => {
+                let data = VRDisplayData { display_id: self.id, ..data };
+                *self.data.lock().unwrap() = Some(data.clone());
+                data
+            },
+            _ => VRDisplayData { display_id: self.id, ..VRDisplayData::default() },
+        }
+    }
+
+    fn immediate_frame_data(&self, near: f64, far: f64) -> VRFrameData {
+        self.frame_data(&DmabufVRRequest::GetImmediateFrameData(near, far))
+    }
+
+    fn synced_frame_data(&self, near: f64, far: f64) -> VRFrameData {
+        self.frame_data(&DmabufVRRequest::GetFrameData(near, far))
+    }
+
+    // The server waits for the next frame before replying.
+    fn future_frame_data(&mut self, near: f64, far: f64) -> VRFutureFrameData {
+        VRFutureFrameData::resolved(self.frame_data(&DmabufVRRequest::GetFrameData(near, far)))
+    }
+
+    fn reset_pose(&mut self) {}
+
+    fn sync_poses(&mut self) {}
+
+    fn bind_framebuffer(&mut self, _eye_index: u32) {}
+
+    // Framebuffers are fetched every frame, so they don't wait on the server.
+    fn get_framebuffers(&self) -> Vec<VRFramebuffer> {
+        let cached = self.data.lock().unwrap().clone();
+        let data = cached.unwrap_or_else(|| self.data());
+        let left = &data.left_eye_parameters;
+        let right = &data.right_eye_parameters;
+        vec![
+            VRFramebuffer {
+                eye_index: 0,
+                attributes: VRFramebufferAttributes::default(),
+                viewport: VRViewport::new(0, 0, left.render_width as i32, left.render_height as i32),
+            },
+            VRFramebuffer {
+                eye_index: 1,
+                attributes: VRFramebufferAttributes::default(),
+                viewport: VRViewport::new(left.render_width as i32, 0,
+                                          right.render_width as i32, right.render_height as i32),
+            },
+        ]
+    }
+
+    fn supported_textures(&self) -> Vec<VRTextureKind> {
+        vec![VRTextureKind::Dmabuf, VRTextureKind::Gl]
+    }
+
+    // Without a GL context, only layers which are already dmabufs can be rendered.
+    fn render_layer(&mut self, layer: &VRLayer) {
+        if layer.texture.kind() != VRTextureKind::Dmabuf {
+            error!("GL layers must be submitted with submit_layer");
+            return;
+        }
+        self.pending_layer = Some(layer.clone());
+    }
+
+    fn submit_frame(&mut self) {
+        if let Some(layer) = self.pending_layer.take() {
+            self.send_layer(&layer);
+        }
+    }
+
+    fn submit_layer(&mut self, gl: &dyn Gl, layer: &VRLayer) {
+        // Make sure the rendering has been queued before the server samples the buffer.
+        gl.flush();
+        match layer.texture {
+            VRTexture::Dmabuf(..) => self.send_layer(layer),
+            VRTexture::Gl(texture_id) => {
+                if let Err(e) = self.send_gl_layer(texture_id, layer) {
+                    error!("Failed to export texture {} ({})", texture_id, e);
+                }
+            },
+            ref texture => error!("Can't share {:?} textures with another process", texture.kind()),
+        }
+    }
+
+    fn start_present(&mut self, attributes: Option<VRFramebufferAttributes>) {
+        self.send(&DmabufVRRequest::StartPresent(attributes), &[]);
+    }
+
+    fn stop_present(&mut self) {
+        self.send(&DmabufVRRequest::StopPresent, &[]);
+    }
+
+    fn fetch_gamepads(&mut self) -> Result<Vec<VRGamepadPtr>, String> {
+        Ok(vec![])
+    }
+}
+
+impl DmabufVRDisplay {
+    pub(crate) fn new(socket: DmabufVRSocket) -> DmabufVRDisplay {
+        DmabufVRDisplay {
+            id: utils::new_id(),
+            socket: Mutex::new(socket),
+            data: Mutex::new(None),
+            pending_layer: None,
+        }
+    }
+
+    fn send_layer(&self, layer: &VRLayer) {
+        let fds: Vec<_> = match layer.texture {
+            VRTexture::Dmabuf(ref dmabuf) => dmabuf.planes.iter().map(|plane| plane.fd).collect(),
+            _ => vec![],
+        };
+        self.send(&DmabufVRRequest::SubmitLayer(layer.clone()), &fds);
+    }
+
+    fn send_gl_layer(&self, texture_id: u32, layer: &VRLayer) -> Result<(), String> {
+        let (width, height) = layer.texture_size.ok_or_else(|| String::from("No texture size"))?;
+        let dmabuf = egl::export(texture_id, width, height)?;
+        let fds: Vec<_> = dmabuf.planes.iter().map(|plane| plane.fd).collect();
+        self.send_layer(&VRLayer { texture: VRTexture::Dmabuf(dmabuf), ..layer.clone() });
+        close_fds(&fds);
+        Ok(())
+    }
+
+    fn send(&self, request: &DmabufVRRequest, fds: &[i32]) {
+        if let Err(e) = self.socket.lock().unwrap().send(request, fds) {
+            error!("Failed to send to the dmabuf VR server ({})", e);
+        }
+    }
+
+    fn request(&self, request: &DmabufVRRequest) -> Option<DmabufVRResponse> {
+        let socket = self.socket.lock().unwrap();
+        let result = socket.send(request, &[]).and_then(|_| socket.recv());
+        match result {
+            Ok((response, fds)) => {
+                close_fds(&fds);
+                Some(response)
+            },
+            Err(e) => {
+                error!("Failed to talk to the dmabuf VR server ({})", e);
+                None
+            },
+        }
+    }
+
+    fn frame_data(&self, request: &DmabufVRRequest) -> VRFrameData {
+        match self.request(request) {
+            Some(DmabufVRResponse::FrameData(data)) => data,
+            _ => VRFrameData::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_webvr_api::VRDisplay;
+    use rust_webvr_api::VRDisplayData;
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+    use super::DmabufVRDisplay;
+    use super::super::{DmabufVRRequest, DmabufVRResponse};
+    use super::super::socket::DmabufVRSocket;
+
+    #[test]
+    fn framebuffers_use_the_cached_display_data() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let server = DmabufVRSocket::new(server);
+        // Answers requests until the display goes away, returning how many there were.
+        let requests = thread::spawn(move || {
+            let mut count = 0;
+            while let Ok((DmabufVRRequest::GetDisplayData, _)) = server.recv() {
+                let mut data = VRDisplayData::default();
+                data.left_eye_parameters.render_width = 10;
+                data.left_eye_parameters.render_height = 20;
+                server.send(&DmabufVRResponse::DisplayData(data), &[]).unwrap();
+                count += 1;
+            }
+            count
+        });
+        let display = DmabufVRDisplay::new(DmabufVRSocket::new(client));
+        for _ in 0..3 {
+            let framebuffers = display.get_framebuffers();
+            assert_eq!((framebuffers[0].viewport.width, framebuffers[0].viewport.height), (10, 20));
+            assert_eq!(framebuffers[1].viewport.x, 10);
+        }
+        drop(display);
+        assert_eq!(requests.join().unwrap(), 1);
+    }
+}
@@ -0,0 +1,185 @@
+// Imports and exports dmabufs as GL textures, with the EGL context which is current
+// on the calling thread. Only the handful of EGL entry points this needs are declared here.
+
+#![allow(non_camel_case_types)]
+
+use gleam::gl;
+use gleam::gl::Gl;
+use rust_webvr_api::{VRDmabuf, VRDmabufPlane};
+use std::ffi::CString;
+use std::mem;
+use std::os::raw::{c_char, c_int, c_uint, c_void};
+use std::ptr;
+
+type EGLBoolean = c_uint;
+type EGLint = i32;
+type EGLenum = c_uint;
+type EGLDisplay = *mut c_void;
+type EGLContext = *mut c_void;
+type EGLClientBuffer = *mut c_void;
+type EGLImageKHR = *mut c_void;
+
+const EGL_NONE: EGLint = 0x3038;
+const EGL_WIDTH: EGLint = 0x3057;
+const EGL_HEIGHT: EGLint = 0x3056;
+const EGL_GL_TEXTURE_2D_KHR: EGLenum = 0x30B1;
+const EGL_LINUX_DMA_BUF_EXT: EGLenum = 0x3270;
+const EGL_LINUX_DRM_FOURCC_EXT: EGLint = 0x3271;
+
+// Per plane: fd, offset, pitch, modifier low bits, modifier high bits.
+const EGL_DMA_BUF_PLANE_ATTRIBS: [[EGLint; 5]; 4] = [
+    [0x3272, 0x3273, 0x3274, 0x3443, 0x3444],
+    [0x3275, 0x3276, 0x3277, 0x3445, 0x3446],
+    [0x3278, 0x3279, 0x327A, 0x3447, 0x3448],
+    [0x3440, 0x3441, 0x3442, 0x3449, 0x344A],
+];
+
+// The modifier used when the buffer layout is implied by the driver.
+const DRM_FORMAT_MOD_INVALID: u64 = 0x00ff_ffff_ffff_ffff;
+
+#[link(name = "EGL")]
+extern "C" {
+    fn eglGetCurrentDisplay() -> EGLDisplay;
+    fn eglGetCurrentContext() -> EGLContext;
+    fn eglGetError() -> EGLint;
+    fn eglGetProcAddress(procname: *const c_char) -> *const c_void;
+}
+
+type CreateImageKHR = unsafe extern "C" fn(EGLDisplay, EGLContext, EGLenum, EGLClientBuffer, *const EGLint) -> EGLImageKHR;
+type DestroyImageKHR = unsafe extern "C" fn(EGLDisplay, EGLImageKHR) -> EGLBoolean;
+type ExportDMABUFImageQueryMESA = unsafe extern "C" fn(EGLDisplay, EGLImageKHR, *mut c_int, *mut c_int, *mut u64) -> EGLBoolean;
+type ExportDMABUFImageMESA = unsafe extern "C" fn(EGLDisplay, EGLImageKHR, *mut c_int, *mut EGLint, *mut EGLint) -> EGLBoolean;
+
+unsafe fn load<T>(name: &str) -> Result<T, String> {
+    let cname = CString::new(name).unwrap();
+    let address = eglGetProcAddress(cname.as_ptr());
+    if address.is_null() {
+        return Err(format!("{} is not supported", name));
+    }
+    Ok(mem::transmute_copy(&address))
+}
+
+fn current_display() -> Result<EGLDisplay, String> {
+    let display = unsafe { eglGetCurrentDisplay() };
+    if display.is_null() {
+        return Err(String::from("No current EGL display"));
+    }
+    Ok(display)
+}
+
+// A GL texture backed by an imported dmabuf. The texture and the EGL image
+// are deleted on drop, the dmabuf file descriptors are left alone.
+pub struct DmabufTexture {
+    pub texture_id: gl::GLuint,
+    display: EGLDisplay,
+    image: EGLImageKHR,
+    destroy_image: DestroyImageKHR,
+}
+
+impl DmabufTexture {
+    pub fn import(gl: &dyn Gl, dmabuf: &VRDmabuf) -> Result<DmabufTexture, String> {
+        if dmabuf.planes.is_empty() || dmabuf.planes.len() > EGL_DMA_BUF_PLANE_ATTRIBS.len() {
+            return Err(format!("Can't import a dmabuf with {} planes", dmabuf.planes.len()));
+        }
+
+        let mut attribs = vec![
+            EGL_WIDTH, dmabuf.width as EGLint,
+            EGL_HEIGHT, dmabuf.height as EGLint,
+            EGL_LINUX_DRM_FOURCC_EXT, dmabuf.fourcc as EGLint,
+        ];
+        for (plane, names) in dmabuf.planes.iter().zip(EGL_DMA_BUF_PLANE_ATTRIBS.iter()) {
+            attribs.extend_from_slice(&[
+                names[0], plane.fd,
+                names[1], plane.offset as EGLint,
+                names[2], plane.stride as EGLint,
+            ]);
+            if dmabuf.modifier != DRM_FORMAT_MOD_INVALID {
+                attribs.extend_from_slice(&[
+                    names[3], dmabuf.modifier as u32 as EGLint,
+                    names[4], (dmabuf.modifier >> 32) as u32 as EGLint,
+                ]);
+            }
+        }
+        attribs.push(EGL_NONE);
+
+        unsafe {
+            let display = current_display()?;
+            let create_image: CreateImageKHR = load("eglCreateImageKHR")?;
+            let destroy_image: DestroyImageKHR = load("eglDestroyImageKHR")?;
+            let image = create_image(display, ptr::null_mut(), EGL_LINUX_DMA_BUF_EXT, ptr::null_mut(), attribs.as_ptr());
+            if image.is_null() {
+                return Err(format!("eglCreateImageKHR failed ({:x})", eglGetError()));
+            }
+
+            let texture_id = gl.gen_textures(1)[0];
+            gl.bind_texture(gl::TEXTURE_2D, texture_id);
+            gl.egl_image_target_texture2d_oes(gl::TEXTURE_2D, image);
+            gl.bind_texture(gl::TEXTURE_2D, 0);
+
+            Ok(DmabufTexture { texture_id, display, image, destroy_image })
+        }
+    }
+
+    pub fn delete(self, gl: &dyn Gl) {
+        gl.delete_textures(&[self.texture_id]);
+    }
+}
+
+impl Drop for DmabufTexture {
+    fn drop(&mut self) {
+        unsafe { (self.destroy_image)(self.display, self.image) };
+    }
+}
+
+// Exports a GL texture as a dmabuf. The caller owns the returned file descriptors.
+pub fn export(texture_id: gl::GLuint, width: u32, height: u32) -> Result<VRDmabuf, String> {
+    unsafe {
+        let display = current_display()?;
+        let create_image: CreateImageKHR = load("eglCreateImageKHR")?;
+        let destroy_image: DestroyImageKHR = load("eglDestroyImageKHR")?;
+        let export_query: ExportDMABUFImageQueryMESA = load("eglExportDMABUFImageQueryMESA")?;
+        let export_image: ExportDMABUFImageMESA = load("eglExportDMABUFImageMESA")?;
+
+        let attribs = [EGL_NONE];
+        let buffer = texture_id as usize as EGLClientBuffer;
+        let image = create_image(display, eglGetCurrentContext(), EGL_GL_TEXTURE_2D_KHR, buffer, attribs.as_ptr());
+        if image.is_null() {
+            return Err(format!("eglCreateImageKHR failed ({:x})", eglGetError()));
+        }
+
+        let mut fourcc = 0;
+        let mut num_planes = 0;
+        let mut modifier = DRM_FORMAT_MOD_INVALID;
+        let mut fds = [-1; 4];
+        let mut strides = [0; 4];
+        let mut offsets = [0; 4];
+        let exported = export_query(display, image, &mut fourcc, &mut num_planes, &mut modifier) != 0 &&
+            (1..=4).contains(&num_planes) &&
+            export_image(display, image, fds.as_mut_ptr(), strides.as_mut_ptr(), offsets.as_mut_ptr()) != 0;
+        let error = eglGetError();
+        destroy_image(display, image);
+        if !exported {
+            return Err(format!("eglExportDMABUFImageMESA failed ({:x})", error));
+        }
+
+        // Planes which share the previous plane's buffer are given an fd of -1.
+        for i in 1..num_planes as usize {
+            if fds[i] < 0 {
+                fds[i] = fds[i - 1];
+            }
+        }
+        let planes = (0..num_planes as usize).map(|i| VRDmabufPlane {
+            fd: fds[i],
+            offset: offsets[i] as u32,
+            stride: strides[i] as u32,
+        }).collect();
+
+        Ok(VRDmabuf {
+            width,
+            height,
+            fourcc: fourcc as u32,
+            modifier,
+            planes,
+        })
+    }
+}
@@ -0,0 +1,33 @@
+//! Presents layers from another process without copying them, by passing dmabufs over
+//! a unix socket. The process which owns the VR display runs a `DmabufVRServer`,
+//! and the rendering process registers a `DmabufVRService` connected to it.
+
+mod display;
+mod egl;
+mod server;
+mod service;
+mod socket;
+
+pub use self::server::DmabufVRServer;
+pub use self::service::DmabufVRService;
+
+use rust_webvr_api::{VRDisplayData, VRFrameData, VRFramebufferAttributes, VRLayer};
+
+// Messages from the rendering process to the display process.
+// The file descriptors of a submitted dmabuf travel alongside the message, in plane order.
+#[derive(Serialize, Deserialize)]
+enum DmabufVRRequest {
+    GetDisplayData,
+    GetImmediateFrameData(f64, f64),
+    GetFrameData(f64, f64),
+    StartPresent(Option<VRFramebufferAttributes>),
+    StopPresent,
+    SubmitLayer(VRLayer),
+}
+
+// Replies from the display process.
+#[derive(Serialize, Deserialize)]
+enum DmabufVRResponse {
+    DisplayData(VRDisplayData),
+    FrameData(VRFrameData),
+}
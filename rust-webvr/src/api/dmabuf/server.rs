@@ -0,0 +1,267 @@
+use gleam::gl;
+use gleam::gl::Gl;
+use rust_webvr_api::VRDisplayPtr;
+use rust_webvr_api::VRLayer;
+use rust_webvr_api::VRMainThreadHeartbeat;
+use rust_webvr_api::VRTexture;
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::io::RawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+use super::{DmabufVRRequest, DmabufVRResponse};
+use super::egl::DmabufTexture;
+use super::socket::{close_fds, DmabufVRSocket};
+
+const TIMEOUT: Duration = Duration::from_millis(16);
+
+// The display side: accepts connections from rendering processes, and presents their
+// layers on a local display. The heartbeat must run on the thread where `gl` is current.
+pub struct DmabufVRServer {
+    display: VRDisplayPtr,
+    gl: Rc<dyn Gl>,
+    receiver: Receiver<(DmabufVRRequest, Vec<RawFd>, DmabufVRSocket)>,
+    presenting: bool,
+}
+
+impl VRMainThreadHeartbeat for DmabufVRServer {
+    fn heartbeat(&mut self) {
+        loop {
+            // While presenting, wait for the client to submit its frame.
+            let msg = if self.presenting {
+                self.receiver.recv_timeout(TIMEOUT).ok()
+            } else {
+                self.receiver.try_recv().ok()
+            };
+            match msg {
+                Some((request, fds, socket)) => if self.handle_request(request, fds, socket) { break; },
+                None => break,
+            }
+        }
+    }
+
+    fn heart_racing(&self) -> bool {
+        self.presenting
+    }
+}
+
+impl DmabufVRServer {
+    // Listens on the given socket path, replacing any stale socket left there.
+    pub fn new<P: AsRef<Path>>(path: P, display: VRDisplayPtr, gl: Rc<dyn Gl>) -> Result<DmabufVRServer, String> {
+        remove_stale_socket(path.as_ref())?;
+        let listener = UnixListener::bind(path.as_ref()).map_err(|e| e.to_string())?;
+        let (sender, receiver) = channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => DmabufVRServer::read_requests(DmabufVRSocket::new(stream), sender.clone()),
+                    Err(e) => error!("Failed to accept dmabuf VR client ({})", e),
+                }
+            }
+        });
+        Ok(DmabufVRServer {
+            display,
+            gl,
+            receiver,
+            presenting: false,
+        })
+    }
+
+    fn read_requests(socket: DmabufVRSocket, sender: Sender<(DmabufVRRequest, Vec<RawFd>, DmabufVRSocket)>) {
+        thread::spawn(move || {
+            let mut presenting = false;
+            while let Ok((request, fds)) = socket.recv() {
+                let reply = match socket.try_clone() {
+                    Ok(reply) => reply,
+                    Err(_) => {
+                        close_fds(&fds);
+                        break;
+                    }
+                };
+                match request {
+                    DmabufVRRequest::StartPresent(..) => presenting = true,
+                    DmabufVRRequest::StopPresent => presenting = false,
+                    _ => (),
+                }
+                if sender.send((request, fds, reply)).is_err() {
+                    return;
+                }
+            }
+            // A client which goes away mid-presentation would otherwise leave the display presenting.
+            if presenting {
+                let _ = sender.send((DmabufVRRequest::StopPresent, vec![], socket));
+            }
+        });
+    }
+
+    // Returns true at the end of a frame.
+    fn handle_request(&mut self, request: DmabufVRRequest, fds: Vec<RawFd>, socket: DmabufVRSocket) -> bool {
+        match request {
+            DmabufVRRequest::GetDisplayData => {
+                let data = self.display.borrow().data();
+                let _ = socket.send(&DmabufVRResponse::DisplayData(data), &[]);
+            },
+            DmabufVRRequest::GetImmediateFrameData(near, far) => {
+                let data = self.display.borrow().immediate_frame_data(near, far);
+                let _ = socket.send(&DmabufVRResponse::FrameData(data), &[]);
+            },
+            DmabufVRRequest::GetFrameData(near, far) => {
+                // The frame data may only resolve after another heartbeat, so wait for it elsewhere.
+                let future = self.display.borrow_mut().future_frame_data(near, far);
+                thread::spawn(move || {
+                    let _ = socket.send(&DmabufVRResponse::FrameData(future.block()), &[]);
+                });
+            },
+            DmabufVRRequest::StartPresent(attributes) => {
+                self.presenting = true;
+                self.display.borrow_mut().start_present(attributes);
+            },
+            DmabufVRRequest::StopPresent => {
+                self.presenting = false;
+                self.display.borrow_mut().stop_present();
+            },
+            DmabufVRRequest::SubmitLayer(layer) => {
+                if let Err(e) = self.submit_layer(layer, &fds) {
+                    error!("Failed to present dmabuf layer ({})", e);
+                }
+                close_fds(&fds);
+                return true;
+            },
+        }
+        close_fds(&fds);
+        false
+    }
+
+    fn submit_layer(&mut self, layer: VRLayer, fds: &[RawFd]) -> Result<(), String> {
+        let mut dmabuf = match layer.texture {
+            VRTexture::Dmabuf(ref dmabuf) => dmabuf.clone(),
+            ref texture => return Err(format!("Expected a dmabuf, got {:?}", texture.kind())),
+        };
+        if dmabuf.planes.len() != fds.len() {
+            return Err(format!("Expected {} file descriptors, got {}", dmabuf.planes.len(), fds.len()));
+        }
+        // The file descriptor numbers in the message are the client's.
+        for (plane, &fd) in dmabuf.planes.iter_mut().zip(fds) {
+            plane.fd = fd;
+        }
+
+        let gl = &*self.gl;
+        let texture = DmabufTexture::import(gl, &dmabuf)?;
+
+        // Some displays read from the bound framebuffer rather than the texture.
+        let mut bound_framebuffer = [0];
+        unsafe { gl.get_integer_v(gl::FRAMEBUFFER_BINDING, &mut bound_framebuffer) };
+        let framebuffer = gl.gen_framebuffers(1)[0];
+        gl.bind_framebuffer(gl::FRAMEBUFFER, framebuffer);
+        gl.framebuffer_texture_2d(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, texture.texture_id, 0);
+
+        let local_layer = VRLayer {
+            texture: VRTexture::Gl(texture.texture_id),
+            texture_size: Some((dmabuf.width, dmabuf.height)),
+            ..layer
+        };
+        self.display.borrow_mut().submit_layer(gl, &local_layer);
+
+        gl.bind_framebuffer(gl::FRAMEBUFFER, bound_framebuffer[0] as gl::GLuint);
+        gl.delete_framebuffers(&[framebuffer]);
+        texture.delete(gl);
+        Ok(())
+    }
+}
+
+// Removes a socket left behind by a server which has gone away.
+// Anything else at the path, including a socket which is still listening, is an error.
+fn remove_stale_socket(path: &Path) -> Result<(), String> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.to_string()),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(format!("{} exists and is not a socket", path.display()));
+    }
+    if UnixStream::connect(path).is_ok() {
+        return Err(format!("{} is in use by another server", path.display()));
+    }
+    fs::remove_file(path).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::os::unix::net::UnixListener;
+    use std::path::PathBuf;
+    use std::os::unix::net::UnixStream;
+    use std::process;
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+    use super::{remove_stale_socket, DmabufVRServer};
+    use super::super::DmabufVRRequest;
+    use super::super::socket::DmabufVRSocket;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("rust-webvr-dmabuf-{}-{}", process::id(), name))
+    }
+
+    #[test]
+    fn stale_sockets_are_removed() {
+        let path = temp_path("stale");
+        drop(UnixListener::bind(&path).unwrap());
+        remove_stale_socket(&path).unwrap();
+        assert!(!path.exists());
+        remove_stale_socket(&path).unwrap();
+    }
+
+    #[test]
+    fn live_sockets_are_kept() {
+        let path = temp_path("live");
+        let _listener = UnixListener::bind(&path).unwrap();
+        assert!(remove_stale_socket(&path).is_err());
+        assert!(path.exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn other_files_are_kept() {
+        let path = temp_path("file");
+        fs::write(&path, b"not a socket").unwrap();
+        assert!(remove_stale_socket(&path).is_err());
+        assert!(path.exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+    // The requests the server sees from a client which sends the given requests then hangs up.
+    fn requests_from(requests: &[DmabufVRRequest]) -> Vec<String> {
+        let (client, server) = UnixStream::pair().unwrap();
+        let (sender, receiver) = channel();
+        DmabufVRServer::read_requests(DmabufVRSocket::new(server), sender);
+        let client = DmabufVRSocket::new(client);
+        for request in requests {
+            client.send(request, &[]).unwrap();
+        }
+        drop(client);
+        let mut names = vec![];
+        while let Ok((request, _, _)) = receiver.recv_timeout(Duration::from_secs(10)) {
+            names.push(String::from(match request {
+                DmabufVRRequest::StartPresent(..) => "start",
+                DmabufVRRequest::StopPresent => "stop",
+                _ => "other",
+            }));
+        }
+        names
+    }
+
+    #[test]
+    fn disconnecting_mid_presentation_stops_presenting() {
+        assert_eq!(requests_from(&[DmabufVRRequest::StartPresent(None)]), vec!["start", "stop"]);
+        assert_eq!(requests_from(&[DmabufVRRequest::GetDisplayData]), vec!["other"]);
+        let requests = [DmabufVRRequest::StartPresent(None), DmabufVRRequest::StopPresent];
+        assert_eq!(requests_from(&requests), vec!["start", "stop"]);
+    }
+}
@@ -0,0 +1,56 @@
+use rust_webvr_api::VRDisplayPtr;
+use rust_webvr_api::VREvent;
+use rust_webvr_api::VRGamepadPtr;
+use rust_webvr_api::VRService;
+use std::cell::RefCell;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use super::display::{DmabufVRDisplay, DmabufVRDisplayPtr};
+use super::socket::DmabufVRSocket;
+
+// The rendering side: a single display which forwards everything to a `DmabufVRServer`.
+pub struct DmabufVRService {
+    path: PathBuf,
+    display: Option<DmabufVRDisplayPtr>,
+}
+
+unsafe impl Send for DmabufVRService {}
+
+impl VRService for DmabufVRService {
+    fn initialize(&mut self) -> Result<(), String> {
+        if self.display.is_none() {
+            let stream = UnixStream::connect(&self.path).map_err(|e| e.to_string())?;
+            let display = DmabufVRDisplay::new(DmabufVRSocket::new(stream));
+            self.display = Some(Arc::new(RefCell::new(display)));
+        }
+        Ok(())
+    }
+
+    fn fetch_displays(&mut self) -> Result<Vec<VRDisplayPtr>, String> {
+        self.initialize()?;
+        Ok(self.display.iter().map(|display| display.clone() as VRDisplayPtr).collect())
+    }
+
+    fn fetch_gamepads(&mut self) -> Result<Vec<VRGamepadPtr>, String> {
+        Ok(vec![])
+    }
+
+    fn is_available(&self) -> bool {
+        self.display.is_some() || self.path.exists()
+    }
+
+    fn poll_events(&self) -> Vec<VREvent> {
+        vec![]
+    }
+}
+
+impl DmabufVRService {
+    // Connects lazily to the server listening on the given socket path.
+    pub fn new<P: AsRef<Path>>(path: P) -> DmabufVRService {
+        DmabufVRService {
+            path: path.as_ref().to_path_buf(),
+            display: None,
+        }
+    }
+}
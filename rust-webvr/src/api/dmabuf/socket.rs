@@ -0,0 +1,223 @@
+// A unix socket carrying JSON messages, each of which may come with file descriptors.
+// Messages are framed by a 4 byte little endian length, and the file descriptors
+// are attached to the length with SCM_RIGHTS.
+
+use libc;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+use std::io::{Read, Write};
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::ptr;
+
+// A dmabuf has at most 4 planes.
+const MAX_FDS: usize = 4;
+
+// The messages are small, anything bigger comes from a confused or hostile peer.
+const MAX_MESSAGE_LEN: usize = 1 << 20;
+
+pub struct DmabufVRSocket(UnixStream);
+
+impl DmabufVRSocket {
+    pub fn new(stream: UnixStream) -> DmabufVRSocket {
+        DmabufVRSocket(stream)
+    }
+
+    pub fn try_clone(&self) -> Result<DmabufVRSocket, String> {
+        self.0.try_clone().map(DmabufVRSocket).map_err(|e| e.to_string())
+    }
+
+    // The file descriptors are duplicated into the receiving process, the caller keeps its own.
+    pub fn send<T: Serialize>(&self, msg: &T, fds: &[RawFd]) -> Result<(), String> {
+        if fds.len() > MAX_FDS {
+            return Err(format!("Can't send {} file descriptors", fds.len()));
+        }
+        let payload = serde_json::to_vec(msg).map_err(|e| e.to_string())?;
+        if payload.len() > MAX_MESSAGE_LEN {
+            return Err(format!("Can't send a {} byte message", payload.len()));
+        }
+        let header = (payload.len() as u32).to_le_bytes();
+
+        unsafe {
+            let mut iov = libc::iovec {
+                iov_base: header.as_ptr() as *mut libc::c_void,
+                iov_len: header.len(),
+            };
+            let mut cmsg_buffer = cmsg_buffer(fds.len());
+            let mut msg: libc::msghdr = mem::zeroed();
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            if !fds.is_empty() {
+                let fds_len = mem::size_of_val(fds) as libc::c_uint;
+                msg.msg_control = cmsg_buffer.as_mut_ptr() as *mut libc::c_void;
+                msg.msg_controllen = libc::CMSG_SPACE(fds_len) as _;
+                let cmsg = libc::CMSG_FIRSTHDR(&msg);
+                (*cmsg).cmsg_level = libc::SOL_SOCKET;
+                (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+                (*cmsg).cmsg_len = libc::CMSG_LEN(fds_len) as _;
+                ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(cmsg) as *mut RawFd, fds.len());
+            }
+            if libc::sendmsg(self.0.as_raw_fd(), &msg, libc::MSG_NOSIGNAL) != header.len() as isize {
+                return Err(format!("Failed to send message header ({})", ::std::io::Error::last_os_error()));
+            }
+        }
+
+        (&self.0).write_all(&payload).map_err(|e| e.to_string())
+    }
+
+    // The caller owns the received file descriptors, and should close them.
+    pub fn recv<T: DeserializeOwned>(&self) -> Result<(T, Vec<RawFd>), String> {
+        let mut header = [0u8; 4];
+        let mut fds = Vec::new();
+
+        let received = unsafe {
+            let mut iov = libc::iovec {
+                iov_base: header.as_mut_ptr() as *mut libc::c_void,
+                iov_len: header.len(),
+            };
+            let mut cmsg_buffer = cmsg_buffer(MAX_FDS);
+            let mut msg: libc::msghdr = mem::zeroed();
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = cmsg_buffer.as_mut_ptr() as *mut libc::c_void;
+            msg.msg_controllen = mem::size_of_val(&cmsg_buffer[..]) as _;
+            let received = libc::recvmsg(self.0.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC);
+            if received <= 0 {
+                return Err(String::from("Connection closed"));
+            }
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                    let data_len = (*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize;
+                    let data = libc::CMSG_DATA(cmsg) as *const RawFd;
+                    for i in 0..data_len / mem::size_of::<RawFd>() {
+                        fds.push(ptr::read_unaligned(data.add(i)));
+                    }
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+            // Any file descriptors which didn't fit were closed by the kernel.
+            if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+                close_fds(&fds);
+                return Err(format!("Received more than {} file descriptors", MAX_FDS));
+            }
+            received as usize
+        };
+
+        let result = (&self.0).read_exact(&mut header[received..])
+            .map_err(|e| e.to_string())
+            .and_then(|_| {
+                let len = u32::from_le_bytes(header) as usize;
+                if len > MAX_MESSAGE_LEN {
+                    return Err(format!("Received a {} byte message header", len));
+                }
+                let mut payload = vec![0; len];
+                (&self.0).read_exact(&mut payload).map_err(|e| e.to_string())?;
+                serde_json::from_slice(&payload).map_err(|e| e.to_string())
+            });
+        match result {
+            Ok(msg) => Ok((msg, fds)),
+            Err(e) => {
+                close_fds(&fds);
+                Err(e)
+            }
+        }
+    }
+}
+
+// Control message storage with room for the given number of file descriptors.
+// The buffer is u64s so that the cmsghdr at its start is suitably aligned.
+fn cmsg_buffer(fd_count: usize) -> Vec<u64> {
+    let fds_len = (fd_count * mem::size_of::<RawFd>()) as libc::c_uint;
+    let len = unsafe { libc::CMSG_SPACE(fds_len) } as usize;
+    vec![0; len.div_ceil(mem::size_of::<u64>())]
+}
+
+// Closes each file descriptor once, even if it appears more than once.
+pub fn close_fds(fds: &[RawFd]) {
+    let mut closed: Vec<RawFd> = Vec::new();
+    for &fd in fds {
+        if fd >= 0 && !closed.contains(&fd) {
+            unsafe { libc::close(fd) };
+            closed.push(fd);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libc;
+    use std::io::Write;
+    use std::os::unix::io::RawFd;
+    use std::os::unix::net::UnixStream;
+    use super::{close_fds, DmabufVRSocket, MAX_MESSAGE_LEN};
+
+    fn pair() -> (DmabufVRSocket, UnixStream, DmabufVRSocket) {
+        let (a, b) = UnixStream::pair().unwrap();
+        let raw = a.try_clone().unwrap();
+        (DmabufVRSocket::new(a), raw, DmabufVRSocket::new(b))
+    }
+
+    fn pipe() -> (RawFd, RawFd) {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        (fds[0], fds[1])
+    }
+
+    #[test]
+    fn messages_and_file_descriptors_round_trip() {
+        let (sender, _, receiver) = pair();
+        let (read_fd, write_fd) = pipe();
+
+        sender.send(&(String::from("hello"), 42), &[write_fd]).unwrap();
+        sender.send(&(String::from("again"), 7), &[]).unwrap();
+        let (msg, fds): ((String, u32), _) = receiver.recv().unwrap();
+        assert_eq!(msg, (String::from("hello"), 42));
+        assert_eq!(fds.len(), 1);
+        let (msg, no_fds): ((String, u32), _) = receiver.recv().unwrap();
+        assert_eq!(msg, (String::from("again"), 7));
+        assert!(no_fds.is_empty());
+
+        // The received descriptor is a new one for the same pipe.
+        assert_ne!(fds[0], write_fd);
+        assert_eq!(unsafe { libc::write(fds[0], b"x".as_ptr() as *const libc::c_void, 1) }, 1);
+        let mut byte = [0u8; 1];
+        assert_eq!(unsafe { libc::read(read_fd, byte.as_mut_ptr() as *mut libc::c_void, 1) }, 1);
+        assert_eq!(&byte, b"x");
+
+        close_fds(&[read_fd, write_fd, fds[0]]);
+    }
+
+    #[test]
+    fn oversized_headers_are_rejected() {
+        let (_, mut raw, receiver) = pair();
+        raw.write_all(&(MAX_MESSAGE_LEN as u32 + 1).to_le_bytes()).unwrap();
+        assert!(receiver.recv::<u32>().is_err());
+    }
+
+    #[test]
+    fn truncated_messages_are_rejected() {
+        let (sender, mut raw, receiver) = pair();
+        raw.write_all(&100u32.to_le_bytes()).unwrap();
+        raw.write_all(b"[1, 2, 3]").unwrap();
+        drop(raw);
+        drop(sender);
+        assert!(receiver.recv::<Vec<u32>>().is_err());
+    }
+
+    #[test]
+    fn oversized_messages_are_not_sent() {
+        let (sender, _, _) = pair();
+        assert!(sender.send(&vec![0u8; MAX_MESSAGE_LEN], &[]).is_err());
+    }
+
+    #[test]
+    fn closed_connections_are_reported() {
+        let (sender, raw, receiver) = pair();
+        drop(raw);
+        drop(sender);
+        assert!(receiver.recv::<u32>().is_err());
+    }
+}
@@ -2,12 +2,14 @@ use euclid::Angle;
 use euclid::RigidTransform3D;
 use euclid::Trig;
 use euclid::Vector3D;
-use gleam::gl;
 use gleam::gl::Gl;
 use rust_webvr_api::utils;
+use rust_webvr_api::VRCompositor;
+use rust_webvr_api::VRCpuBuffer;
 use rust_webvr_api::VRDisplay;
 use rust_webvr_api::VRDisplayCapabilities;
 use rust_webvr_api::VRDisplayData;
+use rust_webvr_api::VREye;
 use rust_webvr_api::VREyeParameters;
 use rust_webvr_api::VRFieldOfView;
 use rust_webvr_api::VRFrameData;
@@ -16,24 +18,70 @@ use rust_webvr_api::VRFramebuffer;
 use rust_webvr_api::VRFramebufferAttributes;
 use rust_webvr_api::VRGamepadPtr;
 use rust_webvr_api::VRLayer;
+use rust_webvr_api::VRPose;
+use rust_webvr_api::VRQuadLayer;
+use rust_webvr_api::VRTexture;
+use rust_webvr_api::VRTextureKind;
 use rust_webvr_api::VRViewport;
 use std::cell::RefCell;
-use std::sync::Arc;
+use std::mem;
+use std::sync::{Arc, Mutex};
 use std::sync::mpsc::Sender;
 use super::heartbeat::GlWindowVRMessage;
+use super::readback::PixelReadback;
 use glutin::dpi::PhysicalSize;
 
-// Fake a display with a distance between eyes of 5cm.
-const EYE_DISTANCE: f32 = 0.05;
+// The stereo parameters of a display, set with GlWindowVRServiceBuilder.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct GlWindowVRParameters {
+    // The distance between the eyes, in meters.
+    pub(crate) ipd: f32,
+    // The vertical field of view, or None to derive it from the window size.
+    pub(crate) vertical_fov: Option<Angle<f64>>,
+    // How far above the floor the viewer's eyes start, in meters.
+    pub(crate) eye_height: f32,
+    // Whether the pose has a position (6DoF), or just an orientation (3DoF).
+    pub(crate) positional_tracking: bool,
+}
+
+impl Default for GlWindowVRParameters {
+    fn default() -> GlWindowVRParameters {
+        // Fake a display with a distance between eyes of 5cm.
+        GlWindowVRParameters {
+            ipd: 0.05,
+            vertical_fov: None,
+            eye_height: 0.0,
+            positional_tracking: true,
+        }
+    }
+}
+
+impl GlWindowVRParameters {
+    // The pose of the viewer before they have moved.
+    pub(crate) fn initial_pose(&self) -> RigidTransform3D<f32> {
+        if self.positional_tracking {
+            RigidTransform3D::from_translation(Vector3D::new(0.0, self.eye_height, 0.0))
+        } else {
+            RigidTransform3D::identity()
+        }
+    }
+}
 
 pub type GlWindowVRDisplayPtr = Arc<RefCell<GlWindowVRDisplay>>;
 
 pub struct GlWindowVRDisplay {
     id: u32,
     name: String,
-    size: PhysicalSize,
+    size: Arc<Mutex<PhysicalSize>>,
+    pose: Arc<Mutex<RigidTransform3D<f32>>>,
+    parameters: GlWindowVRParameters,
     sender: Sender<GlWindowVRMessage>,
     pool: ArcPool<Vec<u8>>,
+    readback: PixelReadback,
+    pending_layer: Option<VRLayer>,
+    pending_quads: Vec<VRQuadLayer>,
+    // Whether we've already complained about a layer that needs submit_layer.
+    warned: bool,
 }
 
 unsafe impl Sync for GlWindowVRDisplay {}
@@ -51,16 +99,17 @@ impl VRDisplay for GlWindowVRDisplay {
 
     fn data(&self) -> VRDisplayData {
         let capabilities = VRDisplayCapabilities {
-            has_position: false,
-            has_orientation: false,
+            has_position: self.parameters.positional_tracking,
+            has_orientation: true,
             has_external_display: true,
             can_present: true,
             presented_by_browser: false,
             max_layers: 1,
         };
 
-        let fov_right = GlWindowVRDisplay::fov_right(self.size).to_degrees();
-        let fov_up = GlWindowVRDisplay::fov_up(self.size).to_degrees();
+        let size = self.size();
+        let fov_right = GlWindowVRDisplay::fov_right(size, &self.parameters).to_degrees();
+        let fov_up = GlWindowVRDisplay::fov_up(size, &self.parameters).to_degrees();
 
         let field_of_view = VRFieldOfView {
             down_degrees: fov_up,
@@ -70,14 +119,14 @@ impl VRDisplay for GlWindowVRDisplay {
         };
 
         let left_eye_parameters = VREyeParameters {
-            offset: [-EYE_DISTANCE / 2.0, 0.0, 0.0],
-            render_width: self.size.width as u32 / 2,
-            render_height: self.size.height as u32,
+            offset: [-self.parameters.ipd / 2.0, 0.0, 0.0],
+            render_width: size.width as u32 / 2,
+            render_height: size.height as u32,
             field_of_view: field_of_view,
         };
 
         let right_eye_parameters = VREyeParameters {
-            offset: [EYE_DISTANCE / 2.0, 0.0, 0.0],
+            offset: [self.parameters.ipd / 2.0, 0.0, 0.0],
             ..left_eye_parameters.clone()
         };
 
@@ -93,7 +142,8 @@ impl VRDisplay for GlWindowVRDisplay {
     }
 
     fn immediate_frame_data(&self, near: f64, far: f64) -> VRFrameData {
-        GlWindowVRDisplay::frame_data(0.0, self.size, near, far, RigidTransform3D::identity())
+        let pose = self.parameters.initial_pose();
+        GlWindowVRDisplay::frame_data(0.0, self.size(), &self.parameters, near, far, pose)
     }
 
     fn synced_frame_data(&self, near: f64, far: f64) -> VRFrameData {
@@ -113,15 +163,16 @@ impl VRDisplay for GlWindowVRDisplay {
     fn bind_framebuffer(&mut self, _eye_index: u32) {}
 
     fn get_framebuffers(&self) -> Vec<VRFramebuffer> {
+        let size = self.size();
         let left_viewport = VRViewport {
             x: 0,
             y: 0,
-            width: (self.size.width as i32) / 2,
-            height: self.size.height as i32,
+            width: (size.width as i32) / 2,
+            height: size.height as i32,
         };
 
         let right_viewport = VRViewport {
-            x: self.size.width as i32 - left_viewport.width,
+            x: size.width as i32 - left_viewport.width,
             ..left_viewport
         };
 
@@ -139,32 +190,60 @@ impl VRDisplay for GlWindowVRDisplay {
         ]
     }
 
-    fn render_layer(&mut self, _layer: &VRLayer) {
-        unreachable!()
+    fn supported_textures(&self) -> Vec<VRTextureKind> {
+        vec![VRTextureKind::Gl, VRTextureKind::Cpu]
+    }
+
+    fn supports_quad_layers(&self) -> bool {
+        true
+    }
+
+    fn render_quad_layer(&mut self, quad: &VRQuadLayer) {
+        self.pending_quads.push(quad.clone());
+    }
+
+    // Without a GL context, only layers with a CPU buffer can be rendered.
+    fn render_layer(&mut self, layer: &VRLayer) {
+        if layer.texture.kind() != VRTextureKind::Cpu {
+            if !self.warned {
+                error!("GlWindow VR display needs submit_layer to read back {:?}", layer.texture);
+                self.warned = true;
+            }
+            return;
+        }
+        self.pending_layer = Some(layer.clone());
     }
 
     fn submit_frame(&mut self) {
-        unreachable!()
+        if let Some(layer) = self.pending_layer.take() {
+            self.submit_cpu_layer(&layer);
+        }
     }
 
     fn submit_layer(&mut self, gl: &Gl, layer: &VRLayer) {
+        if let VRTexture::Cpu(..) = layer.texture {
+            self.submit_cpu_layer(layer);
+            return;
+        }
+        let quads = mem::take(&mut self.pending_quads);
         // TODO: this assumes that the current GL framebuffer contains the texture
         // TODO: what to do if the layer has no texture_size?
         if let Some((width, height)) = layer.texture_size {
-            let num_bytes = (width as usize) * (height as usize) * 4;
-            let mut buffer = self.pool.remove().unwrap_or_else(Vec::new);
-            buffer.resize(num_bytes, 0);
-            gl.read_pixels_into_buffer(
-                0,
-                0,
-                width as gl::GLsizei,
-                height as gl::GLsizei,
-                gl::RGBA,
-                gl::UNSIGNED_BYTE,
-                &mut buffer[..],
-            );
-            let buffer = self.pool.add(buffer);
-            let _ = self.sender.send(GlWindowVRMessage::StopFrame(width, height, buffer));
+            // The pixels arrive a frame or two later, unless PBOs aren't supported.
+            if let Some((width, height, buffer)) = self.readback.read(gl, width, height, &mut self.pool) {
+                let buffer = if quads.is_empty() {
+                    buffer
+                } else {
+                    // The quads are this frame's, so they may lead the read back pixels by a frame or two.
+                    let (mut left, mut right) = unpack_eyes(width, height, &buffer);
+                    let frame_data = self.submitted_frame_data();
+                    let compositor = VRCompositor::new(width / 2, height);
+                    compositor.compose_quads(VREye::Left, &frame_data, &mut left, &quads);
+                    compositor.compose_quads(VREye::Right, &frame_data, &mut right, &quads);
+                    self.pack_eyes(width, height, &left, &right)
+                };
+                let _ = self.sender.send(GlWindowVRMessage::StopFrame(width, height, buffer));
+            }
         }
     }
 
@@ -173,6 +252,7 @@ impl VRDisplay for GlWindowVRDisplay {
     }
 
     fn stop_present(&mut self) {
+        self.readback.stop();
         let _ = self.sender.send(GlWindowVRMessage::StopPresenting);
     }
 
@@ -184,37 +264,90 @@ impl VRDisplay for GlWindowVRDisplay {
 impl GlWindowVRDisplay {
     pub(crate) fn new(
         name: String,
-        size: PhysicalSize,
+        size: Arc<Mutex<PhysicalSize>>,
+        pose: Arc<Mutex<RigidTransform3D<f32>>>,
+        parameters: GlWindowVRParameters,
         sender: Sender<GlWindowVRMessage>
     ) -> GlWindowVRDisplay {
         GlWindowVRDisplay {
             id: utils::new_id(),
             name: name,
             size: size,
+            pose,
+            parameters,
             sender: sender,
             pool: ArcPool::new(),
+            readback: PixelReadback::new(),
+            pending_layer: None,
+            pending_quads: Vec::new(),
+            warned: false,
         }
     }
 
-    fn fov_up(size: PhysicalSize) -> Angle<f64> {
-        Angle::radians(f64::fast_atan2(
-            2.0 * size.height as f64,
-            size.width as f64,
-        ))
+    // The current size of the window, which is kept up to date by the heartbeat.
+    fn size(&self) -> PhysicalSize {
+        *self.size.lock().unwrap()
     }
 
-    fn fov_right(size: PhysicalSize) -> Angle<f64> {
-        Angle::radians(f64::fast_atan2(
-            2.0 * size.width as f64,
-            size.height as f64,
-        ))
+    // The frame data of the latest frame, which the layers being submitted were rendered for,
+    // so quads stay put as the viewer moves.
+    fn submitted_frame_data(&self) -> VRFrameData {
+        let pose = *self.pose.lock().unwrap();
+        GlWindowVRDisplay::frame_data(0.0, self.size(), &self.parameters, 0.1, 1000.0, pose)
     }
 
-    fn perspective(size: PhysicalSize, near: f64, far: f64) -> [f32; 16] {
+    // Composites the layer on the CPU, and sends it to the window side by side.
+    fn submit_cpu_layer(&mut self, layer: &VRLayer) {
+        let size = self.size();
+        let width = size.width as u32;
+        let height = size.height as u32;
+        let eye_width = width / 2;
+        let compositor = VRCompositor::new(eye_width, height);
+        let quads = mem::take(&mut self.pending_quads);
+        let (left, right) = compositor.compose(&self.submitted_frame_data(), Some(layer), &quads);
+        let buffer = self.pack_eyes(width, height, &left, &right);
+        let _ = self.sender.send(GlWindowVRMessage::StopFrame(width, height, buffer));
+    }
+
+    // Puts the eye images side by side, in the GL row order the window expects, bottom row first.
+    fn pack_eyes(&mut self, width: u32, height: u32, left: &VRCpuBuffer, right: &VRCpuBuffer) -> Arc<Vec<u8>> {
+        let mut buffer = self.pool.remove().unwrap_or_default();
+        buffer.clear();
+        buffer.resize((width as usize) * (height as usize) * 4, 0);
+        let row_bytes = (width as usize) * 4;
+        let eye_row_bytes = (left.width as usize) * 4;
+        for (row, pixels) in buffer.chunks_mut(row_bytes).enumerate() {
+            let start = (height as usize - 1 - row) * eye_row_bytes;
+            let end = start + eye_row_bytes;
+            pixels[..eye_row_bytes].copy_from_slice(&left.data[start..end]);
+            pixels[row_bytes - eye_row_bytes..].copy_from_slice(&right.data[start..end]);
+        }
+        self.pool.add(buffer)
+    }
+
+    // Half the vertical field of view.
+    fn fov_up(size: PhysicalSize, parameters: &GlWindowVRParameters) -> Angle<f64> {
+        match parameters.vertical_fov {
+            Some(fov) => fov / 2.0,
+            None => Angle::radians(f64::fast_atan2(
+                2.0 * size.height as f64,
+                size.width as f64,
+            )),
+        }
+    }
+
+    // Half the horizontal field of view of each eye, which fills its half of the window.
+    fn fov_right(size: PhysicalSize, parameters: &GlWindowVRParameters) -> Angle<f64> {
+        let aspect = (size.width / 2.0) / size.height;
+        let fov_up = GlWindowVRDisplay::fov_up(size, parameters);
+        Angle::radians((fov_up.radians.tan() * aspect).atan())
+    }
+
+    pub(crate) fn perspective(size: PhysicalSize, parameters: &GlWindowVRParameters, near: f64, far: f64) -> [f32; 16] {
         // https://github.com/toji/gl-matrix/blob/bd3307196563fbb331b40fc6ebecbbfcc2a4722c/src/mat4.js#L1271
         let near = near as f32;
         let far = far as f32;
-        let f = 1.0 / GlWindowVRDisplay::fov_up(size).radians.tan() as f32;
+        let f = 1.0 / GlWindowVRDisplay::fov_up(size, parameters).radians.tan() as f32;
         let nf = 1.0 / (near - far);
         let aspect = ((size.width / 2.0) as f32) / (size.height as f32);
 
@@ -229,12 +362,22 @@ impl GlWindowVRDisplay {
         }
     }
 
-    pub(crate) fn frame_data(timestamp: f64, size: PhysicalSize, near: f64, far: f64, view: RigidTransform3D<f32>) -> VRFrameData {
-        let left_projection_matrix = GlWindowVRDisplay::perspective(size, near, far);
+    // The frame data for a viewer with the given pose, i.e. the transform from the viewer's space to world space.
+    pub(crate) fn frame_data(
+        timestamp: f64,
+        size: PhysicalSize,
+        parameters: &GlWindowVRParameters,
+        near: f64,
+        far: f64,
+        pose: RigidTransform3D<f32>,
+    ) -> VRFrameData {
+        let left_projection_matrix = GlWindowVRDisplay::perspective(size, parameters, near, far);
         let right_projection_matrix = left_projection_matrix.clone();
 
-        let left_offset = RigidTransform3D::from_translation(Vector3D::new(EYE_DISTANCE / 2.0, 0.0, 0.0));
-        let right_offset = RigidTransform3D::from_translation(Vector3D::new(-EYE_DISTANCE / 2.0, 0.0, 0.0));
+        let left_offset = RigidTransform3D::from_translation(Vector3D::new(parameters.ipd / 2.0, 0.0, 0.0));
+        let right_offset = RigidTransform3D::from_translation(Vector3D::new(-parameters.ipd / 2.0, 0.0, 0.0));
+
+        let view = pose.inverse();
 
         let left_view_matrix = view
             .post_mul(&left_offset)
@@ -246,37 +389,143 @@ impl GlWindowVRDisplay {
             .to_transform()
             .to_row_major_array();
 
+        let rotation = pose.rotation;
+        let position = if parameters.positional_tracking {
+            Some(pose.translation.to_array())
+        } else {
+            None
+        };
+
         VRFrameData {
             timestamp,
             left_projection_matrix,
             right_projection_matrix,
             left_view_matrix,
             right_view_matrix,
-            ..VRFrameData::default()
+            pose: VRPose {
+                position,
+                orientation: Some([rotation.i, rotation.j, rotation.k, rotation.r]),
+                ..VRPose::default()
+            },
         }
     }
 }
 
+// Splits side by side pixels in GL row order into top-down eye images, undoing pack_eyes.
+fn unpack_eyes(width: u32, height: u32, buffer: &[u8]) -> (VRCpuBuffer, VRCpuBuffer) {
+    let eye_width = width / 2;
+    let mut left = VRCpuBuffer::new(eye_width, height);
+    let mut right = VRCpuBuffer::new(eye_width, height);
+    let row_bytes = (width as usize) * 4;
+    let eye_row_bytes = (eye_width as usize) * 4;
+    for (row, pixels) in buffer.chunks(row_bytes).take(height as usize).enumerate() {
+        let start = (height as usize - 1 - row) * eye_row_bytes;
+        let end = start + eye_row_bytes;
+        left.data[start..end].copy_from_slice(&pixels[..eye_row_bytes]);
+        right.data[start..end].copy_from_slice(&pixels[row_bytes - eye_row_bytes..]);
+    }
+    (left, right)
+}
+
 // A pool of Arc<T>'s.
 // You can add a T into the pool, and get back an Arc<T>.
 // You can request a T from the pool, if there's an Arc<T> with no other owners,
 // it will be removed from the pool, unwrapped and returned.
 
-struct ArcPool<T>(Vec<Arc<T>>);
+pub(crate) struct ArcPool<T>(Vec<Arc<T>>);
 
 impl<T> ArcPool<T> {
-    fn new() -> ArcPool<T> {
+    pub(crate) fn new() -> ArcPool<T> {
         ArcPool(Vec::new())
     }
 
-    fn add(&mut self, val: T) -> Arc<T> {
+    pub(crate) fn add(&mut self, val: T) -> Arc<T> {
         let result = Arc::new(val);
         self.0.push(result.clone());
         result
     }
 
-    fn remove(&mut self) -> Option<T> {
+    pub(crate) fn remove(&mut self) -> Option<T> {
         let i = self.0.iter().position(|arc| Arc::strong_count(arc) == 1);
         i.and_then(|i| Arc::try_unwrap(self.0.swap_remove(i)).ok())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use euclid::RigidTransform3D;
+    use euclid::Vector3D;
+    use glutin::dpi::PhysicalSize;
+    use rust_webvr_api::VRCpuBuffer;
+    use rust_webvr_api::VRDisplay;
+    use rust_webvr_api::VRLayer;
+    use rust_webvr_api::VRQuadLayer;
+    use rust_webvr_api::VRTexture;
+    use rust_webvr_api::VRTextureKind;
+    use std::sync::{Arc, Mutex};
+    use std::sync::mpsc::{channel, Receiver};
+    use super::super::heartbeat::GlWindowVRMessage;
+    use super::{GlWindowVRDisplay, GlWindowVRParameters};
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+
+    // A 16x8 window, so each eye is 8x8 and sees 2m across at 1m.
+    fn display(pose: RigidTransform3D<f32>) -> (GlWindowVRDisplay, Receiver<GlWindowVRMessage>) {
+        let (sender, receiver) = channel();
+        let size = Arc::new(Mutex::new(PhysicalSize::new(16.0, 8.0)));
+        let pose = Arc::new(Mutex::new(pose));
+        let display = GlWindowVRDisplay::new("test".into(), size, pose, GlWindowVRParameters::default(), sender);
+        (display, receiver)
+    }
+
+    // The columns of the left eye which a half meter red quad, 1m in front of the origin, lands on.
+    fn quad_columns(pose: RigidTransform3D<f32>) -> Vec<u32> {
+        let (mut display, receiver) = display(pose);
+        let mut quad = VRQuadLayer {
+            buffer: VRCpuBuffer::new(1, 1),
+            size: [0.5, 0.5],
+            ..VRQuadLayer::default()
+        };
+        quad.buffer.set_pixel(0, 0, RED);
+        quad.transform[14] = -1.0;
+        let layer = VRLayer {
+            texture: VRTexture::Cpu(VRCpuBuffer::new(2, 1)),
+            ..VRLayer::default()
+        };
+        display.render_quad_layer(&quad);
+        display.submit_cpu_layer(&layer);
+        let buffer = match receiver.try_recv() {
+            Ok(GlWindowVRMessage::StopFrame(16, 8, buffer)) => buffer,
+            _ => panic!("Expected a 16x8 frame"),
+        };
+        let row = 4 * 16 * 4;
+        (0..8).filter(|x| buffer[row + (*x as usize) * 4..][..4] == RED).collect()
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn only_cpu_layers_are_rendered_without_gl() {
+        let (mut display, receiver) = display(RigidTransform3D::identity());
+        assert_eq!(display.supported_textures(), vec![VRTextureKind::Gl, VRTextureKind::Cpu]);
+        for _ in 0..2 {
+            display.render_layer(&VRLayer { texture: VRTexture::Gl(1), ..VRLayer::default() });
+            display.submit_frame();
+        }
+        assert!(receiver.try_recv().is_err());
+        assert!(display.warned);
+        display.render_layer(&VRLayer { texture: VRTexture::Cpu(VRCpuBuffer::new(2, 1)), ..VRLayer::default() });
+        display.submit_frame();
+        match receiver.try_recv() {
+            Ok(GlWindowVRMessage::StopFrame(16, 8, _)) => (),
+            _ => panic!("Expected a 16x8 frame"),
+        }
+    }
+
+    #[test]
+    fn quads_are_composited_with_the_viewer_pose() {
+        assert_eq!(quad_columns(RigidTransform3D::identity()), vec![3, 4]);
+        // Stepping right moves the quad left in view.
+        let moved = RigidTransform3D::from_translation(Vector3D::new(0.5, 0.0, 0.0));
+        assert_eq!(quad_columns(moved), vec![1, 2]);
+    }
+}
@@ -0,0 +1,212 @@
+use euclid::Angle;
+use euclid::Point3D;
+use euclid::RigidTransform3D;
+use euclid::Rotation3D;
+use euclid::Vector3D;
+use glutin::dpi::PhysicalPosition;
+use glutin::dpi::PhysicalSize;
+use rust_webvr_api::VRGamepad;
+use rust_webvr_api::VRGamepadButton;
+use rust_webvr_api::VRGamepadData;
+use rust_webvr_api::VRGamepadHand;
+use rust_webvr_api::VRGamepadState;
+use rust_webvr_api::VREye;
+use rust_webvr_api::VRPose;
+use rust_webvr_api::utils;
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
+use super::display::GlWindowVRDisplay;
+use super::display::GlWindowVRParameters;
+use super::preview::GlWindowVRPreview;
+
+// The buttons of an emulated controller, in order.
+pub(crate) const TRIGGER: usize = 0;
+pub(crate) const GRIP: usize = 1;
+pub(crate) const MENU: usize = 2;
+pub(crate) const BUTTON_COUNT: usize = 3;
+
+// How far along the cursor ray the controller is held, in meters.
+const REACH: f32 = 0.3;
+
+pub type GlWindowVRGamepadPtr = Arc<RefCell<GlWindowVRGamepad>>;
+
+// An emulated controller, whose state is updated by the heartbeat.
+pub struct GlWindowVRGamepad {
+    data: VRGamepadData,
+    index: usize,
+    states: Arc<Mutex<Vec<VRGamepadState>>>,
+}
+
+impl GlWindowVRGamepad {
+    pub(crate) fn new(
+        data: VRGamepadData,
+        index: usize,
+        states: Arc<Mutex<Vec<VRGamepadState>>>,
+    ) -> GlWindowVRGamepadPtr {
+        Arc::new(RefCell::new(GlWindowVRGamepad {
+            data,
+            index,
+            states,
+        }))
+    }
+}
+
+impl VRGamepad for GlWindowVRGamepad {
+    fn id(&self) -> u32 {
+        self.states.lock().unwrap()[self.index].gamepad_id
+    }
+
+    fn data(&self) -> VRGamepadData {
+        self.data.clone()
+    }
+
+    fn state(&self) -> VRGamepadState {
+        self.states.lock().unwrap()[self.index].clone()
+    }
+}
+
+pub(crate) fn new_state() -> VRGamepadState {
+    VRGamepadState {
+        gamepad_id: utils::new_id(),
+        connected: true,
+        buttons: vec![VRGamepadButton::new(false); BUTTON_COUNT],
+        ..VRGamepadState::default()
+    }
+}
+
+pub(crate) fn new_data(display_id: u32, display_name: &str, hand: VRGamepadHand) -> VRGamepadData {
+    VRGamepadData {
+        display_id,
+        name: format!("{} {:?} controller", display_name, hand),
+        hand,
+    }
+}
+
+// The pose of a controller held out along the ray through the cursor,
+// pointing the way the ray goes. The ray starts at whichever eye the preview
+// shows under the cursor. Returns None if the cursor isn't over an eye.
+pub(crate) fn cursor_pose(
+    viewer: &RigidTransform3D<f32>,
+    cursor: PhysicalPosition,
+    size: PhysicalSize,
+    parameters: &GlWindowVRParameters,
+    preview: GlWindowVRPreview,
+) -> Option<VRPose> {
+    let (eye, [x, y]) = preview.eye_at(cursor.x, cursor.y, size.width as u32, size.height as u32)?;
+    let eye_x = match eye {
+        VREye::Left => -parameters.ipd / 2.0,
+        VREye::Right => parameters.ipd / 2.0,
+    };
+
+    // Undo the projection to get the direction of the ray in the eye's space.
+    let projection = GlWindowVRDisplay::perspective(size, parameters, 0.1, 1000.0);
+    let direction = Vector3D::new(x / projection[0], y / projection[5], -1.0);
+    let direction = viewer.rotation.rotate_vector3d(&direction).normalize();
+    let eye = viewer.to_transform()
+        .transform_point3d(&Point3D::new(eye_x, 0.0, 0.0))
+        .unwrap_or_else(Point3D::origin);
+    let position = eye + direction * REACH;
+
+    // Turn the controller's -z axis to point along the ray.
+    let yaw = (-direction.x).atan2(-direction.z);
+    let pitch = direction.y.asin();
+    let rotation = Rotation3D::around_x(Angle::radians(pitch))
+        .post_rotate(&Rotation3D::around_y(Angle::radians(yaw)));
+
+    Some(VRPose {
+        position: Some(position.to_array()),
+        orientation: Some([rotation.i, rotation.j, rotation.k, rotation.r]),
+        ..VRPose::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::RigidTransform3D;
+    use euclid::Vector3D;
+    use glutin::dpi::PhysicalPosition;
+    use glutin::dpi::PhysicalSize;
+    use rust_webvr_api::VRPose;
+    use super::super::display::GlWindowVRParameters;
+    use super::super::preview::GlWindowVRPreview;
+    use super::{cursor_pose, new_state, BUTTON_COUNT, REACH};
+
+    fn pose_in(preview: GlWindowVRPreview, viewer: &RigidTransform3D<f32>, x: f64, y: f64) -> Option<VRPose> {
+        let parameters = GlWindowVRParameters::default();
+        cursor_pose(viewer, PhysicalPosition::new(x, y), PhysicalSize::new(800.0, 600.0), &parameters, preview)
+    }
+
+    fn pose_at(viewer: &RigidTransform3D<f32>, x: f64, y: f64) -> VRPose {
+        pose_in(GlWindowVRPreview::SideBySide, viewer, x, y).unwrap()
+    }
+
+    fn assert_near(actual: [f32; 3], expected: [f32; 3]) {
+        for i in 0..3 {
+            assert!((actual[i] - expected[i]).abs() < 1e-5, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn controllers_start_connected_with_released_buttons() {
+        let state = new_state();
+        assert!(state.connected);
+        assert_eq!(state.buttons.len(), BUTTON_COUNT);
+        assert!(state.buttons.iter().all(|button| !button.pressed));
+    }
+
+    #[test]
+    fn cursor_in_the_middle_of_an_eye_points_straight_ahead() {
+        let ipd = GlWindowVRParameters::default().ipd;
+        let viewer = RigidTransform3D::identity();
+
+        let left = pose_at(&viewer, 200.0, 300.0);
+        assert_near(left.position.unwrap(), [-ipd / 2.0, 0.0, -REACH]);
+        let orientation = left.orientation.unwrap();
+        assert!((orientation[3].abs() - 1.0).abs() < 1e-5, "{:?}", orientation);
+
+        let right = pose_at(&viewer, 600.0, 300.0);
+        assert_near(right.position.unwrap(), [ipd / 2.0, 0.0, -REACH]);
+    }
+
+    #[test]
+    fn controller_points_towards_the_cursor() {
+        let viewer = RigidTransform3D::identity();
+        let position = pose_at(&viewer, 300.0, 100.0).position.unwrap();
+        let ipd = GlWindowVRParameters::default().ipd;
+        assert!(position[0] > -ipd / 2.0, "{:?}", position);
+        assert!(position[1] > 0.0, "{:?}", position);
+        assert!(position[2] < 0.0 && position[2] > -REACH, "{:?}", position);
+    }
+
+    #[test]
+    fn controller_moves_with_the_viewer() {
+        let ipd = GlWindowVRParameters::default().ipd;
+        let viewer = RigidTransform3D::from_translation(Vector3D::new(1.0, 1.5, 2.0));
+        let position = pose_at(&viewer, 200.0, 300.0).position.unwrap();
+        assert_near(position, [1.0 - ipd / 2.0, 1.5, 2.0 - REACH]);
+    }
+
+    #[test]
+    fn the_ray_starts_at_the_eye_the_preview_shows() {
+        let ipd = GlWindowVRParameters::default().ipd;
+        let viewer = RigidTransform3D::identity();
+        let left = [-ipd / 2.0, 0.0, -REACH];
+        let right = [ipd / 2.0, 0.0, -REACH];
+
+        // The single eye previews fill the middle of the window.
+        assert_near(pose_in(GlWindowVRPreview::LeftEye, &viewer, 400.0, 300.0).unwrap().position.unwrap(), left);
+        assert_near(pose_in(GlWindowVRPreview::RightEye, &viewer, 400.0, 300.0).unwrap().position.unwrap(), right);
+        assert_near(pose_in(GlWindowVRPreview::Anaglyph, &viewer, 400.0, 300.0).unwrap().position.unwrap(), left);
+
+        // Cross-eyed swaps the eyes over.
+        assert_near(pose_in(GlWindowVRPreview::CrossEyed, &viewer, 200.0, 300.0).unwrap().position.unwrap(), right);
+        assert_near(pose_in(GlWindowVRPreview::CrossEyed, &viewer, 600.0, 300.0).unwrap().position.unwrap(), left);
+    }
+
+    #[test]
+    fn no_pose_outside_the_eyes() {
+        let viewer = RigidTransform3D::identity();
+        assert!(pose_in(GlWindowVRPreview::LeftEye, &viewer, 100.0, 300.0).is_none());
+        assert!(pose_in(GlWindowVRPreview::SideBySide, &viewer, 900.0, 300.0).is_none());
+    }
+}
@@ -1,61 +1,83 @@
-use euclid::Angle;
 use euclid::RigidTransform3D;
-use euclid::Rotation3D;
-use euclid::Vector3D;
 use gleam::gl;
 use gleam::gl::Gl;
 use glutin::{WindowedContext, NotCurrent};
 use glutin::EventsLoop;
 use glutin::Event;
-use glutin::VirtualKeyCode;
 use glutin::WindowEvent;
+use glutin::WindowId;
+use glutin::dpi::PhysicalSize;
+use rust_webvr_api::VRGamepadButton;
+use rust_webvr_api::VRGamepadHand;
+use rust_webvr_api::VRGamepadState;
 use rust_webvr_api::VRResolveFrameData;
 use rust_webvr_api::VRMainThreadHeartbeat;
 use std::rc::Rc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use super::display::GlWindowVRDisplay;
+use super::display::GlWindowVRParameters;
+use super::gamepad;
+use super::input::GlWindowVRBindings;
+use super::input::GlWindowVRInput;
+use super::preview;
+use super::preview::GlWindowVRPreview;
 use super::service::EventsLoopFactory;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::sync::mpsc::Receiver;
 
 const TIMEOUT: Duration = Duration::from_millis(16);
-const DELTA: f32 = 0.05;
-const ANGLE: Angle<f32> = Angle { radians: 0.1 };
 
 pub struct GlWindowVRMainThreadHeartbeat {
-    receiver: Receiver<GlWindowVRMessage>,
-    gl_context: Option<WindowedContext<NotCurrent>>,
     events_loop_factory: EventsLoopFactory,
     events_loop: Option<EventsLoop>,
     gl: Rc<dyn Gl>,
+    windows: Vec<GlWindowVRWindow>,
+}
+
+// One of the heartbeat's windows, each of which shows a different display.
+struct GlWindowVRWindow {
+    receiver: Receiver<GlWindowVRMessage>,
+    gl_context: Option<WindowedContext<NotCurrent>>,
+    window_id: WindowId,
+    gl: Rc<dyn Gl>,
     presenting: bool,
     timestamp: f64,
     texture_id: gl::GLuint,
     framebuffer_id: gl::GLuint,
-    view: RigidTransform3D<f32>,
+    input: GlWindowVRInput,
+    shared: GlWindowVRShared,
+    parameters: GlWindowVRParameters,
+    minimized: bool,
+    // The frame after any CPU processing needed by the preview.
+    preview_buffer: Vec<u8>,
 }
 
 impl VRMainThreadHeartbeat for GlWindowVRMainThreadHeartbeat {
     fn heartbeat(&mut self) {
        debug!("VR heartbeat start");
-       loop {
-           // If we are presenting, we block the main thread on the VR thread.
-           let msg = if self.presenting {
-               self.receiver.recv_timeout(TIMEOUT).ok()
-           } else {
-               self.receiver.try_recv().ok()
-           };
+       // All the windows share an events loop, so this handles events for every window.
+       self.handle_window_events();
+       // The presenting windows share one timeout, so more windows don't slow the heartbeat down.
+       let deadline = Instant::now() + TIMEOUT;
+       for index in 0..self.windows.len() {
+           loop {
+               // If we are presenting, we block the main thread on the VR thread.
+               let msg = {
+                   let window = &self.windows[index];
+                   recv_until(&window.receiver, if window.presenting { Some(deadline) } else { None })
+               };
 
-           match msg {
-               Some(msg) => if self.handle_msg(msg) { break; },
-               None => break,
-           };
+               match msg {
+                   Some(msg) => if self.windows[index].handle_msg(msg) { break; },
+                   None => break,
+               };
+           }
         }
         debug!("VR heartbeat stop");
     }
 
     fn heart_racing(&self) -> bool {
-        self.presenting
+        self.windows.iter().any(|window| window.presenting)
     }
 }
 
@@ -65,50 +87,105 @@ impl GlWindowVRMainThreadHeartbeat {
         gl_context: WindowedContext<NotCurrent>,
         events_loop_factory: EventsLoopFactory,
         gl: Rc<Gl>,
+        shared: GlWindowVRShared,
+        parameters: GlWindowVRParameters,
     ) -> GlWindowVRMainThreadHeartbeat {
         debug!("Creating VR heartbeat");
-        GlWindowVRMainThreadHeartbeat {
-            receiver: receiver,
-            gl_context: Some(gl_context),
+        // The events loop is made straight away, so that window events are handled
+        // even before the window presents.
+        let events_loop = events_loop_factory().ok();
+        let mut heartbeat = GlWindowVRMainThreadHeartbeat {
             events_loop_factory: events_loop_factory,
-            events_loop: None,
+            events_loop: events_loop,
             gl: gl,
+            windows: Vec::new(),
+        };
+        heartbeat.add_window(receiver, gl_context, shared, parameters);
+        heartbeat
+    }
+
+    pub(crate) fn add_window(
+        &mut self,
+        receiver: Receiver<GlWindowVRMessage>,
+        gl_context: WindowedContext<NotCurrent>,
+        shared: GlWindowVRShared,
+        parameters: GlWindowVRParameters,
+    ) {
+        let window_id = gl_context.window().id();
+        self.windows.push(GlWindowVRWindow {
+            receiver,
+            gl_context: Some(gl_context),
+            window_id,
+            gl: self.gl.clone(),
             presenting: false,
             timestamp: 0.0,
             texture_id: 0,
             framebuffer_id: 0,
-            view: RigidTransform3D::identity(),
+            input: GlWindowVRInput::new(&parameters),
+            shared,
+            parameters,
+            minimized: false,
+            preview_buffer: Vec::new(),
+        });
+    }
+
+    /// Changes how keyboard and mouse input moves the viewer, in every window.
+    pub fn set_bindings(&mut self, bindings: GlWindowVRBindings) {
+        for window in &mut self.windows {
+            window.input.set_bindings(bindings.clone());
         }
     }
 
+    /// Changes how the eyes are shown, in every window.
+    pub fn set_preview(&mut self, preview: GlWindowVRPreview) {
+        for window in &mut self.windows {
+            window.input.set_preview(preview);
+        }
+    }
+
+    fn handle_window_events(&mut self) {
+        // If the events loop couldn't be made earlier, try again.
+        if self.events_loop.is_none() {
+            self.events_loop = (self.events_loop_factory)().ok();
+        }
+        let windows = &mut self.windows;
+        if let Some(ref mut events_loop) = self.events_loop {
+            events_loop.poll_events(|event| {
+                let (window_id, event) = match event {
+                    Event::WindowEvent { window_id, event } => (window_id, event),
+                    _ => return,
+                };
+                if let Some(window) = windows.iter_mut().find(|window| window.window_id == window_id) {
+                    window.handle_window_event(event);
+                }
+            })
+        }
+    }
+}
+
+impl GlWindowVRWindow {
     fn handle_msg(&mut self, msg: GlWindowVRMessage) -> bool {
            match msg {
                GlWindowVRMessage::StartPresenting => {
                     debug!("VR starting");
                     self.gl_context.as_ref().unwrap().window().show();
                     self.presenting = true;
-		    if self.events_loop.is_none() {
-                        self.events_loop = (self.events_loop_factory)().ok();
-		    }
                     true
                },
                GlWindowVRMessage::StartFrame(near, far, mut resolver) => {
                    debug!("VR start frame");
-                   self.handle_window_events();
+                   self.update_controllers();
                    let timestamp = self.timestamp;
-                   let window = self.gl_context.as_ref().unwrap().window();
-                   let size = window.get_inner_size().expect("No window size");
-                   let hidpi = window.get_hidpi_factor();
-                   let size = size.to_physical(hidpi);
-                   let view = self.view;
-                   let data = GlWindowVRDisplay::frame_data(timestamp, size, near, far, view);
+                   let size = *self.shared.size.lock().unwrap();
+                   let pose = self.input.pose();
+                   *self.shared.pose.lock().unwrap() = pose;
+                   let data = GlWindowVRDisplay::frame_data(timestamp, size, &self.parameters, near, far, pose);
                    let _ = resolver.resolve(data);
                    self.timestamp = self.timestamp + 1.0;
                    false
                },
                GlWindowVRMessage::StopFrame(width, height, buffer) => {
                    debug!("VR stop frame {}x{} ({})", width, height, buffer.len());
-                   // TODO: render the buffer contents
                    let context = self.gl_context.take().expect("Context was current");
                    let context = match unsafe { context.make_current() } {
                        Err(err) => {
@@ -129,6 +206,13 @@ impl GlWindowVRMainThreadHeartbeat {
                    self.gl.clear_color(0.2, 0.3, 0.3, 1.0);
                    self.gl.clear(gl::COLOR_BUFFER_BIT);
 
+                   let preview = self.input.preview();
+                   let (frame_width, frame_height) = (width, height);
+                   let (width, height, pixels) = match preview.process(width, height, &buffer, &mut self.preview_buffer) {
+                       Some((width, height)) => (width, height, &self.preview_buffer[..]),
+                       None => (width, height, &buffer[..]),
+                   };
+
                    self.gl.bind_texture(gl::TEXTURE_2D, self.texture_id);
                    self.gl.tex_image_2d(
                        gl::TEXTURE_2D,
@@ -139,7 +223,7 @@ impl GlWindowVRMainThreadHeartbeat {
                        0,
                        gl::RGBA,
                        gl::UNSIGNED_BYTE,
-                       Some(&buffer[..]),
+                       Some(pixels),
                    );
                    self.gl.bind_texture(gl::TEXTURE_2D, 0);
 
@@ -151,15 +235,26 @@ impl GlWindowVRMainThreadHeartbeat {
                        self.texture_id,
                        0
                    );
-                   self.gl.viewport(
-                       0, 0, width as gl::GLsizei, height as gl::GLsizei,
-                   );
-                   self.gl.blit_framebuffer(
-                       0, 0, width as gl::GLsizei, height as gl::GLsizei,
-                       0, 0, width as gl::GLsizei, height as gl::GLsizei,
-                       gl::COLOR_BUFFER_BIT,
-                       gl::NEAREST,
-                   );
+                   // The window may have been resized since the frame was rendered, so until
+                   // a frame of the new size arrives, the old one is letterboxed rather than stretched.
+                   let size = *self.shared.size.lock().unwrap();
+                   let window_width = size.width as u32;
+                   let window_height = size.height as u32;
+                   let area = if (frame_width, frame_height) == (window_width, window_height) {
+                       [0, 0, window_width as i32, window_height as i32]
+                   } else {
+                       preview::fit(frame_width as i32, frame_height as i32, window_width as i32, window_height as i32)
+                   };
+                   self.gl.viewport(0, 0, window_width as gl::GLsizei, window_height as gl::GLsizei);
+                   let (area_width, area_height) = ((area[2] - area[0]) as u32, (area[3] - area[1]) as u32);
+                   for (src, dst) in preview.blits(width, height, area_width, area_height) {
+                       self.gl.blit_framebuffer(
+                           src[0], src[1], src[2], src[3],
+                           dst[0] + area[0], dst[1] + area[1], dst[2] + area[0], dst[3] + area[1],
+                           gl::COLOR_BUFFER_BIT,
+                           gl::LINEAR,
+                       );
+                   }
                    self.gl.bind_framebuffer(gl::READ_FRAMEBUFFER, 0);
 
                    let _ = context.swap_buffers();
@@ -190,34 +285,161 @@ impl GlWindowVRMainThreadHeartbeat {
            }
     }
 
-    fn handle_window_events(&mut self) {
-        let view = &mut self.view;
-        if let Some(ref mut events_loop) = self.events_loop {
-            events_loop.poll_events(|event| {
-                if let Event::WindowEvent { event: WindowEvent::KeyboardInput { input, .. }, .. } = event {
-                    if let Some(key_code) = input.virtual_keycode {
-                        let delta = match key_code {
-                            VirtualKeyCode::Up => RigidTransform3D::from_translation(Vector3D::new(0.0, 0.0, DELTA)),
-                            VirtualKeyCode::Down => RigidTransform3D::from_translation(Vector3D::new(0.0, 0.0, -DELTA)),
-                            VirtualKeyCode::Left => RigidTransform3D::from_translation(Vector3D::new(-DELTA, 0.0, 0.0)),
-                            VirtualKeyCode::Right => RigidTransform3D::from_translation(Vector3D::new(DELTA, 0.0, 0.0)),
-                            VirtualKeyCode::W => RigidTransform3D::from_rotation(Rotation3D::around_x(ANGLE)),
-                            VirtualKeyCode::S => RigidTransform3D::from_rotation(Rotation3D::around_x(-ANGLE)),
-                            VirtualKeyCode::A => RigidTransform3D::from_rotation(Rotation3D::around_y(ANGLE)),
-                            VirtualKeyCode::D => RigidTransform3D::from_rotation(Rotation3D::around_y(-ANGLE)),
-                            _ => RigidTransform3D::identity(),
-                        };
-                        *view = view.post_mul(&delta);
-                    }
+    // The active controller follows the cursor, and gets the button presses.
+    fn update_controllers(&mut self) {
+        let mut states = self.shared.controllers.lock().unwrap();
+        let active = self.input.controller(states.len());
+        let buttons = self.input.controller_buttons();
+        let size = *self.shared.size.lock().unwrap();
+        let hidpi = self.gl_context.as_ref().unwrap().window().get_hidpi_factor();
+        let viewer = self.input.pose();
+        let parameters = &self.parameters;
+        let preview = self.input.preview();
+        let pose = self.input.cursor().and_then(|cursor| {
+            gamepad::cursor_pose(&viewer, cursor.to_physical(hidpi), size, parameters, preview)
+        });
+        for (index, state) in states.iter_mut().enumerate() {
+            state.timestamp = self.timestamp;
+            if index != active {
+                state.buttons = vec![VRGamepadButton::new(false); buttons.len()];
+                continue;
+            }
+            if let Some(pose) = pose {
+                state.pose = pose;
+            }
+            state.buttons = buttons.iter().map(|&pressed| VRGamepadButton::new(pressed)).collect();
+        }
+    }
+
+    fn handle_window_event(&mut self, event: WindowEvent) {
+        if self.input.handle_event(&event) {
+            return;
+        }
+        let window = self.gl_context.as_ref().unwrap().window();
+        let current_size = &self.shared.size;
+        let window_events = &self.shared.window_events;
+        let minimized = &mut self.minimized;
+        match event {
+            WindowEvent::Focused(focused) => {
+                window_events.lock().unwrap().push(GlWindowVRWindowEvent::Focused(focused));
+            },
+            WindowEvent::CloseRequested => {
+                window_events.lock().unwrap().push(GlWindowVRWindowEvent::CloseRequested);
+            },
+            WindowEvent::Resized(size) => {
+                let size = size.to_physical(window.get_hidpi_factor());
+                resized(size, minimized, &mut current_size.lock().unwrap(), &mut window_events.lock().unwrap());
+            },
+            WindowEvent::HiDpiFactorChanged(hidpi) => {
+                if let Some(size) = window.get_inner_size() {
+                    resized(size.to_physical(hidpi), minimized, &mut current_size.lock().unwrap(), &mut window_events.lock().unwrap());
                 }
-            })
+            },
+            _ => {},
         }
     }
 }
 
+// Waits for a message until the deadline, or just checks for one without a deadline.
+fn recv_until<T>(receiver: &Receiver<T>, deadline: Option<Instant>) -> Option<T> {
+    match deadline {
+        Some(deadline) => receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())).ok(),
+        None => receiver.try_recv().ok(),
+    }
+}
+
+// glutin has no minimize event, but on Windows minimized windows are resized to nothing.
+// Other platforms keep the window size when minimizing, so there we never report it,
+// and the display is neither paused nor resumed; focus changes still arrive as Blur and Focus.
+// The last non-empty size is kept, so that the display still has sensible eye parameters.
+fn resized(
+    size: PhysicalSize,
+    minimized: &mut bool,
+    current_size: &mut PhysicalSize,
+    events: &mut Vec<GlWindowVRWindowEvent>,
+) {
+    let empty = size.width < 1.0 || size.height < 1.0;
+    if empty != *minimized {
+        *minimized = empty;
+        events.push(GlWindowVRWindowEvent::Minimized(empty));
+    }
+    if !empty && size != *current_size {
+        *current_size = size;
+        events.push(GlWindowVRWindowEvent::Resized);
+    }
+}
+
 pub(crate) enum GlWindowVRMessage {
     StartPresenting,
     StartFrame(f64, f64, VRResolveFrameData),
     StopFrame(u32, u32, Arc<Vec<u8>>),
     StopPresenting,
 }
+
+// The state which the heartbeat shares with the service and its display.
+#[derive(Clone)]
+pub(crate) struct GlWindowVRShared {
+    // The current size of the window.
+    pub(crate) size: Arc<Mutex<PhysicalSize>>,
+    // The viewer pose of the latest frame, which is the one the application is rendering.
+    pub(crate) pose: Arc<Mutex<RigidTransform3D<f32>>>,
+    pub(crate) window_events: Arc<Mutex<Vec<GlWindowVRWindowEvent>>>,
+    // The state of each emulated controller.
+    pub(crate) controllers: Arc<Mutex<Vec<VRGamepadState>>>,
+}
+
+impl GlWindowVRShared {
+    pub(crate) fn new(
+        gl_context: &WindowedContext<NotCurrent>,
+        hands: &[VRGamepadHand],
+        parameters: &GlWindowVRParameters,
+    ) -> GlWindowVRShared {
+        let size = gl_context.window().get_inner_size().expect("No window size");
+        let hidpi = gl_context.window().get_hidpi_factor();
+        GlWindowVRShared {
+            size: Arc::new(Mutex::new(size.to_physical(hidpi))),
+            pose: Arc::new(Mutex::new(parameters.initial_pose())),
+            window_events: Arc::new(Mutex::new(Vec::new())),
+            controllers: Arc::new(Mutex::new(hands.iter().map(|_| gamepad::new_state()).collect())),
+        }
+    }
+}
+
+// Window events, which are queued by the heartbeat and turned into
+// VREvents when the service is polled.
+pub(crate) enum GlWindowVRWindowEvent {
+    Focused(bool),
+    CloseRequested,
+    // Only reported on Windows, see `resized`.
+    Minimized(bool),
+    Resized,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+    use std::time::Instant;
+    use super::{recv_until, TIMEOUT};
+
+    #[test]
+    fn windows_share_the_timeout() {
+        let start = Instant::now();
+        let deadline = start + TIMEOUT;
+        let receivers: Vec<_> = (0..4).map(|_| channel::<()>()).collect();
+        for &(_, ref receiver) in &receivers {
+            assert!(recv_until(receiver, Some(deadline)).is_none());
+        }
+        // Waiting on each window in turn would take four timeouts.
+        assert!(start.elapsed() < TIMEOUT * 3, "{:?}", start.elapsed());
+    }
+
+    #[test]
+    fn messages_arrive_before_the_deadline() {
+        let (sender, receiver) = channel();
+        assert_eq!(recv_until(&receiver, None), None);
+        sender.send(1).unwrap();
+        assert_eq!(recv_until(&receiver, None), Some(1));
+        sender.send(2).unwrap();
+        assert_eq!(recv_until(&receiver, Some(Instant::now())), Some(2));
+    }
+}
@@ -0,0 +1,498 @@
+use euclid::Angle;
+use euclid::RigidTransform3D;
+use euclid::Rotation3D;
+use euclid::Vector3D;
+use glutin::ElementState;
+use glutin::MouseButton;
+use glutin::MouseScrollDelta;
+use glutin::VirtualKeyCode;
+use glutin::WindowEvent;
+use glutin::dpi::LogicalPosition;
+use std::f32::consts::FRAC_PI_2;
+use super::display::GlWindowVRParameters;
+use super::gamepad::{BUTTON_COUNT, GRIP, MENU, TRIGGER};
+use super::preview::GlWindowVRPreview;
+
+// Scrolling by pixels (e.g. on a touchpad) is converted to lines at this rate.
+const PIXELS_PER_LINE: f64 = 16.0;
+
+// Stop just short of looking straight up or down, where yaw is ambiguous.
+const MAX_PITCH: f32 = FRAC_PI_2 - 0.01;
+
+/// Something the viewer can do in a glwindow display.
+/// Movement is relative to the direction the viewer is looking in.
+/// The trigger, grip and menu buttons are held down for as long as their key or button is,
+/// and belong to whichever emulated controller is following the cursor.
+/// `NextPreview` switches to the next way of showing the eyes in the window.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GlWindowVRAction {
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    TurnLeft,
+    TurnRight,
+    LookUp,
+    LookDown,
+    ResetView,
+    Trigger,
+    Grip,
+    Menu,
+    SwitchController,
+    NextPreview,
+}
+
+/// How keyboard and mouse input moves the viewer in a glwindow display.
+#[derive(Clone, Debug)]
+pub struct GlWindowVRBindings {
+    /// The action performed by each key, when it is pressed.
+    pub keys: Vec<(VirtualKeyCode, GlWindowVRAction)>,
+    /// The action performed by each mouse button, when it is pressed.
+    pub buttons: Vec<(MouseButton, GlWindowVRAction)>,
+    /// Dragging with this button looks around. `None` disables mouse-look.
+    pub look_button: Option<MouseButton>,
+    /// Distance moved per key press, in meters.
+    pub move_speed: f32,
+    /// Angle turned per key press, in radians.
+    pub turn_speed: f32,
+    /// Angle turned per pixel the mouse is dragged, in radians.
+    pub look_speed: f32,
+    /// Distance moved forward per line scrolled, in meters.
+    pub scroll_speed: f32,
+}
+
+impl Default for GlWindowVRBindings {
+    fn default() -> GlWindowVRBindings {
+        GlWindowVRBindings {
+            keys: vec![
+                (VirtualKeyCode::Up, GlWindowVRAction::MoveForward),
+                (VirtualKeyCode::Down, GlWindowVRAction::MoveBackward),
+                (VirtualKeyCode::Left, GlWindowVRAction::MoveLeft),
+                (VirtualKeyCode::Right, GlWindowVRAction::MoveRight),
+                (VirtualKeyCode::PageUp, GlWindowVRAction::MoveUp),
+                (VirtualKeyCode::PageDown, GlWindowVRAction::MoveDown),
+                (VirtualKeyCode::A, GlWindowVRAction::TurnLeft),
+                (VirtualKeyCode::D, GlWindowVRAction::TurnRight),
+                (VirtualKeyCode::W, GlWindowVRAction::LookUp),
+                (VirtualKeyCode::S, GlWindowVRAction::LookDown),
+                (VirtualKeyCode::R, GlWindowVRAction::ResetView),
+                (VirtualKeyCode::Space, GlWindowVRAction::Trigger),
+                (VirtualKeyCode::G, GlWindowVRAction::Grip),
+                (VirtualKeyCode::M, GlWindowVRAction::Menu),
+                (VirtualKeyCode::Tab, GlWindowVRAction::SwitchController),
+                (VirtualKeyCode::P, GlWindowVRAction::NextPreview),
+            ],
+            buttons: vec![
+                (MouseButton::Right, GlWindowVRAction::Trigger),
+                (MouseButton::Middle, GlWindowVRAction::Grip),
+            ],
+            look_button: Some(MouseButton::Left),
+            move_speed: 0.05,
+            turn_speed: 0.1,
+            look_speed: 0.005,
+            scroll_speed: 0.1,
+        }
+    }
+}
+
+impl GlWindowVRBindings {
+    fn key_action(&self, key_code: VirtualKeyCode) -> Option<GlWindowVRAction> {
+        self.keys.iter().find(|&&(key, _)| key == key_code).map(|&(_, action)| action)
+    }
+
+    fn button_action(&self, mouse_button: MouseButton) -> Option<GlWindowVRAction> {
+        self.buttons.iter().find(|&&(button, _)| button == mouse_button).map(|&(_, action)| action)
+    }
+}
+
+// Moves the viewer around in response to window events.
+// The viewer looks down the -z axis, turned by yaw around the y axis then by pitch around the x axis.
+// Without positional tracking, the viewer can look around but not move.
+pub(crate) struct GlWindowVRInput {
+    bindings: GlWindowVRBindings,
+    home: Vector3D<f32>,
+    positional_tracking: bool,
+    position: Vector3D<f32>,
+    yaw: f32,
+    pitch: f32,
+    looking: bool,
+    cursor: Option<LogicalPosition>,
+    controller: usize,
+    controller_buttons: [bool; BUTTON_COUNT],
+    preview: GlWindowVRPreview,
+}
+
+impl GlWindowVRInput {
+    pub(crate) fn new(parameters: &GlWindowVRParameters) -> GlWindowVRInput {
+        let home = parameters.initial_pose().translation;
+        GlWindowVRInput {
+            bindings: GlWindowVRBindings::default(),
+            home,
+            positional_tracking: parameters.positional_tracking,
+            position: home,
+            yaw: 0.0,
+            pitch: 0.0,
+            looking: false,
+            cursor: None,
+            controller: 0,
+            controller_buttons: [false; BUTTON_COUNT],
+            preview: GlWindowVRPreview::default(),
+        }
+    }
+
+    pub(crate) fn set_bindings(&mut self, bindings: GlWindowVRBindings) {
+        self.looking = false;
+        self.controller_buttons = [false; BUTTON_COUNT];
+        self.bindings = bindings;
+    }
+
+    pub(crate) fn preview(&self) -> GlWindowVRPreview {
+        self.preview
+    }
+
+    pub(crate) fn set_preview(&mut self, preview: GlWindowVRPreview) {
+        self.preview = preview;
+    }
+
+    // The position of the cursor, if it is in the window.
+    pub(crate) fn cursor(&self) -> Option<LogicalPosition> {
+        self.cursor
+    }
+
+    // Which of the emulated controllers is following the cursor.
+    pub(crate) fn controller(&self, count: usize) -> usize {
+        self.controller % count.max(1)
+    }
+
+    pub(crate) fn controller_buttons(&self) -> [bool; BUTTON_COUNT] {
+        self.controller_buttons
+    }
+
+    // Returns true if the event was used to move the viewer.
+    pub(crate) fn handle_event(&mut self, event: &WindowEvent) -> bool {
+        match *event {
+            WindowEvent::KeyboardInput { input, .. } => {
+                let pressed = input.state == ElementState::Pressed;
+                match input.virtual_keycode.and_then(|key_code| self.bindings.key_action(key_code)) {
+                    Some(action) => self.perform(action, pressed),
+                    None => return false,
+                }
+            },
+            WindowEvent::MouseInput { state, button, .. } => {
+                let pressed = state == ElementState::Pressed;
+                if Some(button) == self.bindings.look_button {
+                    self.looking = pressed;
+                    return true;
+                }
+                match self.bindings.button_action(button) {
+                    Some(action) => self.perform(action, pressed),
+                    None => return false,
+                }
+            },
+            WindowEvent::CursorMoved { position, .. } => {
+                let previous = self.cursor.replace(position);
+                match previous {
+                    Some(previous) if self.looking => {
+                        // Dragging right turns right, dragging up looks up.
+                        let speed = self.bindings.look_speed;
+                        let yaw = self.yaw - (position.x - previous.x) as f32 * speed;
+                        let pitch = self.pitch - (position.y - previous.y) as f32 * speed;
+                        self.look(yaw, pitch);
+                    },
+                    _ => return false,
+                }
+            },
+            WindowEvent::CursorLeft { .. } => {
+                self.cursor = None;
+                return false;
+            },
+            WindowEvent::Focused(false) => {
+                self.looking = false;
+                self.controller_buttons = [false; BUTTON_COUNT];
+                return false;
+            },
+            WindowEvent::MouseWheel { delta, .. } => {
+                let lines = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => y,
+                    MouseScrollDelta::PixelDelta(position) => (position.y / PIXELS_PER_LINE) as f32,
+                };
+                let forward = self.forward();
+                self.move_by(forward * lines * self.bindings.scroll_speed);
+            },
+            _ => return false,
+        }
+        true
+    }
+
+    // The transform from the viewer's space to world space.
+    pub(crate) fn pose(&self) -> RigidTransform3D<f32> {
+        RigidTransform3D::from_rotation(self.orientation())
+            .post_mul(&RigidTransform3D::from_translation(self.position))
+    }
+
+    fn perform(&mut self, action: GlWindowVRAction, pressed: bool) {
+        let button = match action {
+            GlWindowVRAction::Trigger => Some(TRIGGER),
+            GlWindowVRAction::Grip => Some(GRIP),
+            GlWindowVRAction::Menu => Some(MENU),
+            _ => None,
+        };
+        if let Some(button) = button {
+            self.controller_buttons[button] = pressed;
+            return;
+        }
+        if !pressed {
+            return;
+        }
+
+        let distance = self.bindings.move_speed;
+        let angle = self.bindings.turn_speed;
+        let (yaw, pitch) = (self.yaw, self.pitch);
+        let up = Vector3D::new(0.0, 1.0, 0.0);
+        match action {
+            GlWindowVRAction::MoveForward => self.move_by(self.forward() * distance),
+            GlWindowVRAction::MoveBackward => self.move_by(self.forward() * -distance),
+            GlWindowVRAction::MoveLeft => self.move_by(self.right() * -distance),
+            GlWindowVRAction::MoveRight => self.move_by(self.right() * distance),
+            GlWindowVRAction::MoveUp => self.move_by(up * distance),
+            GlWindowVRAction::MoveDown => self.move_by(up * -distance),
+            GlWindowVRAction::TurnLeft => self.look(yaw + angle, pitch),
+            GlWindowVRAction::TurnRight => self.look(yaw - angle, pitch),
+            GlWindowVRAction::LookUp => self.look(yaw, pitch + angle),
+            GlWindowVRAction::LookDown => self.look(yaw, pitch - angle),
+            GlWindowVRAction::ResetView => {
+                self.position = self.home;
+                self.look(0.0, 0.0);
+            },
+            GlWindowVRAction::SwitchController => {
+                self.controller = self.controller.wrapping_add(1);
+                self.controller_buttons = [false; BUTTON_COUNT];
+            },
+            GlWindowVRAction::NextPreview => self.preview = self.preview.next(),
+            GlWindowVRAction::Trigger | GlWindowVRAction::Grip | GlWindowVRAction::Menu => {},
+        }
+    }
+
+    fn move_by(&mut self, delta: Vector3D<f32>) {
+        if self.positional_tracking {
+            self.position += delta;
+        }
+    }
+
+    fn look(&mut self, yaw: f32, pitch: f32) {
+        self.yaw = yaw;
+        self.pitch = pitch.clamp(-MAX_PITCH, MAX_PITCH);
+    }
+
+    fn orientation(&self) -> Rotation3D<f32> {
+        Rotation3D::around_x(Angle::radians(self.pitch))
+            .post_rotate(&Rotation3D::around_y(Angle::radians(self.yaw)))
+    }
+
+    fn forward(&self) -> Vector3D<f32> {
+        self.orientation().rotate_vector3d(&Vector3D::new(0.0, 0.0, -1.0))
+    }
+
+    fn right(&self) -> Vector3D<f32> {
+        self.orientation().rotate_vector3d(&Vector3D::new(1.0, 0.0, 0.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glutin::DeviceId;
+    use glutin::ElementState;
+    use glutin::KeyboardInput;
+    use glutin::ModifiersState;
+    use glutin::MouseButton;
+    use glutin::MouseScrollDelta;
+    use glutin::TouchPhase;
+    use glutin::VirtualKeyCode;
+    use glutin::WindowEvent;
+    use glutin::dpi::LogicalPosition;
+    use super::super::display::GlWindowVRParameters;
+    use super::super::gamepad::{GRIP, TRIGGER};
+    use super::super::preview::GlWindowVRPreview;
+    use super::{GlWindowVRAction, GlWindowVRBindings, GlWindowVRInput, MAX_PITCH};
+
+    fn input() -> GlWindowVRInput {
+        GlWindowVRInput::new(&GlWindowVRParameters::default())
+    }
+
+    fn device_id() -> DeviceId {
+        unsafe { DeviceId::dummy() }
+    }
+
+    fn key(key_code: VirtualKeyCode, state: ElementState) -> WindowEvent {
+        WindowEvent::KeyboardInput {
+            device_id: device_id(),
+            input: KeyboardInput {
+                scancode: 0,
+                state,
+                virtual_keycode: Some(key_code),
+                modifiers: ModifiersState::default(),
+            },
+        }
+    }
+
+    fn tap(input: &mut GlWindowVRInput, key_code: VirtualKeyCode) {
+        assert!(input.handle_event(&key(key_code, ElementState::Pressed)));
+        assert!(input.handle_event(&key(key_code, ElementState::Released)));
+    }
+
+    fn button(button: MouseButton, state: ElementState) -> WindowEvent {
+        WindowEvent::MouseInput { device_id: device_id(), state, button, modifiers: ModifiersState::default() }
+    }
+
+    fn cursor(x: f64, y: f64) -> WindowEvent {
+        WindowEvent::CursorMoved {
+            device_id: device_id(),
+            position: LogicalPosition::new(x, y),
+            modifiers: ModifiersState::default(),
+        }
+    }
+
+    fn assert_near(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-5, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn keys_move_the_viewer() {
+        let mut input = input();
+        tap(&mut input, VirtualKeyCode::Up);
+        tap(&mut input, VirtualKeyCode::Right);
+        tap(&mut input, VirtualKeyCode::PageUp);
+        let position = input.pose().translation;
+        assert_near(position.x, 0.05);
+        assert_near(position.y, 0.05);
+        assert_near(position.z, -0.05);
+    }
+
+    #[test]
+    fn movement_is_relative_to_where_the_viewer_looks() {
+        let mut input = input();
+        tap(&mut input, VirtualKeyCode::A);
+        tap(&mut input, VirtualKeyCode::Up);
+        let position = input.pose().translation;
+        assert_near(position.x, -0.05 * 0.1f32.sin());
+        assert_near(position.z, -0.05 * 0.1f32.cos());
+    }
+
+    #[test]
+    fn pitch_stops_short_of_straight_up() {
+        let mut input = input();
+        for _ in 0..100 {
+            tap(&mut input, VirtualKeyCode::W);
+        }
+        assert_eq!(input.pitch, MAX_PITCH);
+    }
+
+    #[test]
+    fn reset_view_returns_home() {
+        let mut input = GlWindowVRInput::new(&GlWindowVRParameters { eye_height: 1.5, ..GlWindowVRParameters::default() });
+        tap(&mut input, VirtualKeyCode::Up);
+        tap(&mut input, VirtualKeyCode::D);
+        tap(&mut input, VirtualKeyCode::R);
+        let position = input.pose().translation;
+        assert_eq!((position.x, position.y, position.z), (0.0, 1.5, 0.0));
+        assert_eq!((input.yaw, input.pitch), (0.0, 0.0));
+    }
+
+    #[test]
+    fn viewer_only_turns_without_positional_tracking() {
+        let parameters = GlWindowVRParameters { positional_tracking: false, ..GlWindowVRParameters::default() };
+        let mut input = GlWindowVRInput::new(&parameters);
+        tap(&mut input, VirtualKeyCode::Up);
+        tap(&mut input, VirtualKeyCode::A);
+        let position = input.pose().translation;
+        assert_eq!((position.x, position.y, position.z), (0.0, 0.0, 0.0));
+        assert_near(input.yaw, 0.1);
+    }
+
+    #[test]
+    fn dragging_looks_around() {
+        let mut input = input();
+        assert!(!input.handle_event(&cursor(10.0, 10.0)));
+        assert!(!input.handle_event(&cursor(20.0, 10.0)));
+        assert_eq!(input.yaw, 0.0);
+
+        assert!(input.handle_event(&button(MouseButton::Left, ElementState::Pressed)));
+        assert!(input.handle_event(&cursor(30.0, 0.0)));
+        assert_near(input.yaw, -10.0 * 0.005);
+        assert_near(input.pitch, 10.0 * 0.005);
+
+        assert!(input.handle_event(&button(MouseButton::Left, ElementState::Released)));
+        assert!(!input.handle_event(&cursor(40.0, 0.0)));
+        assert_near(input.yaw, -10.0 * 0.005);
+        assert_eq!(input.cursor(), Some(LogicalPosition::new(40.0, 0.0)));
+
+        input.handle_event(&WindowEvent::CursorLeft { device_id: device_id() });
+        assert_eq!(input.cursor(), None);
+    }
+
+    #[test]
+    fn scrolling_moves_forward() {
+        let mut input = input();
+        let scroll = |delta| WindowEvent::MouseWheel {
+            device_id: device_id(),
+            delta,
+            phase: TouchPhase::Moved,
+            modifiers: ModifiersState::default(),
+        };
+        assert!(input.handle_event(&scroll(MouseScrollDelta::LineDelta(0.0, 2.0))));
+        assert!(input.handle_event(&scroll(MouseScrollDelta::PixelDelta(LogicalPosition::new(0.0, -16.0)))));
+        assert_near(input.pose().translation.z, -0.1);
+    }
+
+    #[test]
+    fn controller_buttons_are_held_until_released_or_unfocused() {
+        let mut input = input();
+        input.handle_event(&key(VirtualKeyCode::Space, ElementState::Pressed));
+        input.handle_event(&button(MouseButton::Middle, ElementState::Pressed));
+        assert!(input.controller_buttons()[TRIGGER]);
+        assert!(input.controller_buttons()[GRIP]);
+
+        input.handle_event(&key(VirtualKeyCode::Space, ElementState::Released));
+        assert!(!input.controller_buttons()[TRIGGER]);
+        assert!(input.controller_buttons()[GRIP]);
+
+        input.handle_event(&WindowEvent::Focused(false));
+        assert!(!input.controller_buttons()[GRIP]);
+    }
+
+    #[test]
+    fn switching_controllers_wraps_around() {
+        let mut input = input();
+        assert_eq!(input.controller(2), 0);
+        tap(&mut input, VirtualKeyCode::Tab);
+        assert_eq!(input.controller(2), 1);
+        tap(&mut input, VirtualKeyCode::Tab);
+        assert_eq!(input.controller(2), 0);
+        assert_eq!(input.controller(0), 0);
+    }
+
+    #[test]
+    fn next_preview_cycles_the_previews() {
+        let mut input = input();
+        tap(&mut input, VirtualKeyCode::P);
+        assert_eq!(input.preview(), GlWindowVRPreview::LeftEye);
+    }
+
+
+    #[test]
+    fn bindings_can_be_replaced() {
+        let mut input = input();
+        input.set_bindings(GlWindowVRBindings {
+            keys: vec![(VirtualKeyCode::K, GlWindowVRAction::MoveBackward)],
+            buttons: vec![],
+            look_button: None,
+            ..GlWindowVRBindings::default()
+        });
+        assert!(!input.handle_event(&key(VirtualKeyCode::Up, ElementState::Pressed)));
+        assert!(!input.handle_event(&button(MouseButton::Left, ElementState::Pressed)));
+        tap(&mut input, VirtualKeyCode::K);
+        assert_near(input.pose().translation.z, 0.05);
+    }
+}
@@ -1,6 +1,12 @@
 mod display;
+mod gamepad;
 mod service;
 mod heartbeat;
+mod input;
+mod preview;
+mod readback;
 
-pub use self::service::GlWindowVRService;
+pub use self::service::{EventsLoopFactory, GlWindowVRService, GlWindowVRServiceBuilder};
+pub use self::input::{GlWindowVRAction, GlWindowVRBindings};
+pub use self::preview::GlWindowVRPreview;
 pub use self::heartbeat::GlWindowVRMainThreadHeartbeat;
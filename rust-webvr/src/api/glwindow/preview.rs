@@ -0,0 +1,278 @@
+use rust_webvr_api::VREye;
+
+// Lens distortion coefficients, roughly those of a phone-based headset.
+const K1: f32 = 0.22;
+const K2: f32 = 0.24;
+
+/// How a glwindow display shows the frame in its window.
+/// The `NextPreview` action cycles through these in order.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum GlWindowVRPreview {
+    /// The left eye on the left, and the right eye on the right.
+    #[default]
+    SideBySide,
+    /// Just the left eye, filling the window.
+    LeftEye,
+    /// Just the right eye, filling the window.
+    RightEye,
+    /// Both eyes on top of each other, the left in red and the right in cyan.
+    Anaglyph,
+    /// The right eye on the left, and the left eye on the right.
+    CrossEyed,
+    /// Side by side, with the barrel distortion of a headset's lenses.
+    Distorted,
+}
+
+impl GlWindowVRPreview {
+    pub(crate) fn next(self) -> GlWindowVRPreview {
+        match self {
+            GlWindowVRPreview::SideBySide => GlWindowVRPreview::LeftEye,
+            GlWindowVRPreview::LeftEye => GlWindowVRPreview::RightEye,
+            GlWindowVRPreview::RightEye => GlWindowVRPreview::Anaglyph,
+            GlWindowVRPreview::Anaglyph => GlWindowVRPreview::CrossEyed,
+            GlWindowVRPreview::CrossEyed => GlWindowVRPreview::Distorted,
+            GlWindowVRPreview::Distorted => GlWindowVRPreview::SideBySide,
+        }
+    }
+
+    // Some previews can't be done with blits, so the side-by-side RGBA pixels are
+    // processed on the CPU first. Returns the size of the processed image in output,
+    // or None if the pixels can be used as they are.
+    pub(crate) fn process(
+        self,
+        width: u32,
+        height: u32,
+        pixels: &[u8],
+        output: &mut Vec<u8>,
+    ) -> Option<(u32, u32)> {
+        if pixels.len() != (width as usize) * (height as usize) * 4 {
+            error!("Expected {}x{} pixels for the VR preview, got {} bytes", width, height, pixels.len());
+            return None;
+        }
+        let eye_width = width / 2;
+        match self {
+            GlWindowVRPreview::Anaglyph => {
+                output.clear();
+                output.reserve((eye_width * height * 4) as usize);
+                for row in pixels.chunks(width as usize * 4) {
+                    let (left, right) = row.split_at(eye_width as usize * 4);
+                    for (left, right) in left.chunks(4).zip(right.chunks(4)) {
+                        output.extend_from_slice(&[left[0], right[1], right[2], 255]);
+                    }
+                }
+                Some((eye_width, height))
+            },
+            GlWindowVRPreview::Distorted => {
+                output.clear();
+                output.resize(pixels.len(), 0);
+                distort(eye_width, height, width, 0, pixels, output);
+                distort(eye_width, height, width, eye_width, pixels, output);
+                Some((width, height))
+            },
+            _ => None,
+        }
+    }
+
+    // The blits from an image of the given size (as returned by process) to the window,
+    // as pairs of source and destination rectangles [x0, y0, x1, y1].
+    pub(crate) fn blits(self, width: u32, height: u32, window_width: u32, window_height: u32) -> Vec<([i32; 4], [i32; 4])> {
+        let (width, height) = (width as i32, height as i32);
+        let (window_width, window_height) = (window_width as i32, window_height as i32);
+        let eye_width = width / 2;
+        let window = [0, 0, window_width, window_height];
+        let left = [0, 0, eye_width, height];
+        let right = [eye_width, 0, width, height];
+        match self {
+            GlWindowVRPreview::SideBySide | GlWindowVRPreview::Distorted => {
+                vec![([0, 0, width, height], window)]
+            },
+            GlWindowVRPreview::LeftEye => {
+                vec![(left, fit(eye_width, height, window_width, window_height))]
+            },
+            GlWindowVRPreview::RightEye => {
+                vec![(right, fit(eye_width, height, window_width, window_height))]
+            },
+            GlWindowVRPreview::Anaglyph => {
+                vec![([0, 0, width, height], fit(width, height, window_width, window_height))]
+            },
+            GlWindowVRPreview::CrossEyed => {
+                let window_eye_width = window_width / 2;
+                vec![
+                    (right, [0, 0, window_eye_width, window_height]),
+                    (left, [window_eye_width, 0, window_width, window_height]),
+                ]
+            },
+        }
+    }
+
+    // Finds the eye shown at a point in the window, for a frame rendered at the window's size.
+    // This undoes the blits, and returns the point in the eye's normalized device coordinates,
+    // or None if no eye is shown there. The distortion of the Distorted preview is ignored.
+    pub(crate) fn eye_at(self, x: f64, y: f64, window_width: u32, window_height: u32) -> Option<(VREye, [f32; 2])> {
+        let (window_width, window_height) = (window_width as i32, window_height as i32);
+        let eye_width = window_width / 2;
+        let left = [0, 0, eye_width, window_height];
+        let right = [eye_width, 0, window_width, window_height];
+        let fitted = fit(eye_width, window_height, window_width, window_height);
+        let eyes = match self {
+            GlWindowVRPreview::SideBySide | GlWindowVRPreview::Distorted => vec![(VREye::Left, left), (VREye::Right, right)],
+            GlWindowVRPreview::LeftEye | GlWindowVRPreview::Anaglyph => vec![(VREye::Left, fitted)],
+            GlWindowVRPreview::RightEye => vec![(VREye::Right, fitted)],
+            GlWindowVRPreview::CrossEyed => vec![(VREye::Right, left), (VREye::Left, right)],
+        };
+        // The rectangles are centered vertically, so they are the same with the y axis pointing down.
+        eyes.into_iter()
+            .find(|&(_, rect)| x >= rect[0] as f64 && x < rect[2] as f64 && y >= rect[1] as f64 && y < rect[3] as f64)
+            .map(|(eye, rect)| {
+                let u = (x - rect[0] as f64) / (rect[2] - rect[0]) as f64;
+                let v = (y - rect[1] as f64) / (rect[3] - rect[1]) as f64;
+                (eye, [(2.0 * u - 1.0) as f32, (1.0 - 2.0 * v) as f32])
+            })
+    }
+}
+
+// The largest rectangle with the aspect ratio of the image that fits in the middle of the window.
+pub(crate) fn fit(width: i32, height: i32, window_width: i32, window_height: i32) -> [i32; 4] {
+    if width <= 0 || height <= 0 {
+        return [0, 0, window_width, window_height];
+    }
+    let scale = f64::min(
+        window_width as f64 / width as f64,
+        window_height as f64 / height as f64,
+    );
+    let fitted_width = (width as f64 * scale) as i32;
+    let fitted_height = (height as f64 * scale) as i32;
+    let x = (window_width - fitted_width) / 2;
+    let y = (window_height - fitted_height) / 2;
+    [x, y, x + fitted_width, y + fitted_height]
+}
+
+// Applies barrel distortion to one eye, which starts at column x0 of rows that are stride pixels wide.
+// Each output pixel samples the source further out from the center of the eye, the further it is
+// from the center. Pixels which sample outside the eye are left black.
+fn distort(eye_width: u32, height: u32, stride: u32, x0: u32, pixels: &[u8], output: &mut [u8]) {
+    let aspect = eye_width as f32 / height as f32;
+    for y in 0..height {
+        let v = 2.0 * (y as f32 + 0.5) / height as f32 - 1.0;
+        for x in 0..eye_width {
+            let u = 2.0 * (x as f32 + 0.5) / eye_width as f32 - 1.0;
+            let r2 = u * u * aspect * aspect + v * v;
+            let scale = 1.0 + K1 * r2 + K2 * r2 * r2;
+            let (su, sv) = (u * scale, v * scale);
+            let i = ((y * stride + x0 + x) * 4) as usize;
+            if su.abs() >= 1.0 || sv.abs() >= 1.0 {
+                output[i..i + 4].copy_from_slice(&[0, 0, 0, 255]);
+                continue;
+            }
+            let sx = ((su + 1.0) / 2.0 * eye_width as f32) as u32;
+            let sy = ((sv + 1.0) / 2.0 * height as f32) as u32;
+            let j = ((sy * stride + x0 + sx) * 4) as usize;
+            output[i..i + 4].copy_from_slice(&pixels[j..j + 4]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_webvr_api::VREye;
+    use super::{fit, GlWindowVRPreview};
+
+    // Side by side eyes, each a single color.
+    fn frame(eye_width: u32, height: u32, left: [u8; 4], right: [u8; 4]) -> Vec<u8> {
+        let mut pixels = Vec::new();
+        for _ in 0..height {
+            for _ in 0..eye_width {
+                pixels.extend_from_slice(&left);
+            }
+            for _ in 0..eye_width {
+                pixels.extend_from_slice(&right);
+            }
+        }
+        pixels
+    }
+
+    fn pixel(pixels: &[u8], width: u32, x: u32, y: u32) -> &[u8] {
+        let i = ((y * width + x) * 4) as usize;
+        &pixels[i..i + 4]
+    }
+
+    #[test]
+    fn fit_keeps_the_aspect_ratio() {
+        assert_eq!(fit(100, 100, 300, 200), [50, 0, 250, 200]);
+        assert_eq!(fit(200, 100, 200, 300), [0, 100, 200, 200]);
+        assert_eq!(fit(100, 50, 200, 100), [0, 0, 200, 100]);
+        assert_eq!(fit(0, 100, 200, 100), [0, 0, 200, 100]);
+    }
+
+    #[test]
+    fn previews_cycle() {
+        let mut preview = GlWindowVRPreview::default();
+        for _ in 0..6 {
+            preview = preview.next();
+        }
+        assert_eq!(preview, GlWindowVRPreview::SideBySide);
+        assert_eq!(preview.next(), GlWindowVRPreview::LeftEye);
+    }
+
+    #[test]
+    fn blitted_previews_are_not_processed() {
+        let pixels = frame(2, 2, [255, 0, 0, 255], [0, 0, 255, 255]);
+        let mut output = Vec::new();
+        assert_eq!(GlWindowVRPreview::SideBySide.process(4, 2, &pixels, &mut output), None);
+        assert_eq!(GlWindowVRPreview::CrossEyed.process(4, 2, &pixels, &mut output), None);
+    }
+
+    #[test]
+    fn anaglyph_mixes_the_eyes() {
+        let pixels = frame(2, 3, [200, 10, 20, 255], [30, 40, 50, 255]);
+        let mut output = Vec::new();
+        assert_eq!(GlWindowVRPreview::Anaglyph.process(4, 3, &pixels, &mut output), Some((2, 3)));
+        assert_eq!(output.len(), 2 * 3 * 4);
+        assert!(output.chunks(4).all(|pixel| pixel == [200, 40, 50, 255]));
+    }
+
+    #[test]
+    fn distortion_keeps_the_middle_and_blacks_out_the_corners() {
+        let pixels = frame(5, 5, [255, 0, 0, 255], [0, 0, 255, 255]);
+        let mut output = Vec::new();
+        assert_eq!(GlWindowVRPreview::Distorted.process(10, 5, &pixels, &mut output), Some((10, 5)));
+        assert_eq!(pixel(&output, 10, 2, 2), [255, 0, 0, 255]);
+        assert_eq!(pixel(&output, 10, 7, 2), [0, 0, 255, 255]);
+        assert_eq!(pixel(&output, 10, 0, 0), [0, 0, 0, 255]);
+        assert_eq!(pixel(&output, 10, 9, 4), [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn pixels_of_the_wrong_size_are_not_processed() {
+        let mut output = Vec::new();
+        for &preview in &[GlWindowVRPreview::Anaglyph, GlWindowVRPreview::Distorted] {
+            assert_eq!(preview.process(4, 2, &[0; 12], &mut output), None);
+            assert_eq!(preview.process(4, 2, &[0; 40], &mut output), None);
+        }
+    }
+
+    // VREye can't be compared, so the eyes are named.
+    fn eye_at(preview: GlWindowVRPreview, x: f64, y: f64) -> Option<(&'static str, [f32; 2])> {
+        preview.eye_at(x, y, 800, 600).map(|(eye, point)| match eye {
+            VREye::Left => ("left", point),
+            VREye::Right => ("right", point),
+        })
+    }
+
+    #[test]
+    fn eye_at_undoes_the_blits() {
+        let centre = [0.0, 0.0];
+        assert_eq!(eye_at(GlWindowVRPreview::SideBySide, 200.0, 300.0), Some(("left", centre)));
+        assert_eq!(eye_at(GlWindowVRPreview::SideBySide, 600.0, 300.0), Some(("right", centre)));
+        assert_eq!(eye_at(GlWindowVRPreview::CrossEyed, 200.0, 300.0), Some(("right", centre)));
+        assert_eq!(eye_at(GlWindowVRPreview::CrossEyed, 600.0, 300.0), Some(("left", centre)));
+        assert_eq!(eye_at(GlWindowVRPreview::LeftEye, 400.0, 300.0), Some(("left", centre)));
+        assert_eq!(eye_at(GlWindowVRPreview::RightEye, 400.0, 300.0), Some(("right", centre)));
+        assert_eq!(eye_at(GlWindowVRPreview::Anaglyph, 400.0, 300.0), Some(("left", centre)));
+
+        // The top left of the fitted eye, and the bars either side of it.
+        assert_eq!(eye_at(GlWindowVRPreview::LeftEye, 200.0, 0.0), Some(("left", [-1.0, 1.0])));
+        assert_eq!(eye_at(GlWindowVRPreview::LeftEye, 100.0, 300.0), None);
+        assert_eq!(eye_at(GlWindowVRPreview::RightEye, 700.0, 300.0), None);
+    }
+}
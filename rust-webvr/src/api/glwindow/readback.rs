@@ -0,0 +1,265 @@
+use gleam::gl;
+use gleam::gl::Gl;
+use std::collections::VecDeque;
+use std::ptr;
+use std::slice;
+use std::sync::Arc;
+use super::display::ArcPool;
+
+// How many frames a readback may stay in flight before we wait for it.
+const LATENCY: usize = 2;
+
+// How long to wait for a fence, in nanoseconds. gleam doesn't tell us whether
+// the wait timed out, but mapping the buffer will block until the read is done anyway.
+const FENCE_TIMEOUT: gl::GLuint64 = 1_000_000_000;
+
+// Reads the current framebuffer back into pixel buffer objects, fenced so that the
+// GPU can carry on rendering while the copy completes. Finished frames are handed
+// back LATENCY frames later. If PBOs or sync objects aren't available, pixels are
+// read back synchronously.
+//
+// The buffers belong to whichever GL context is current when read is called,
+// which is assumed to be the same context every frame.
+pub(crate) struct PixelReadback {
+    supported: Option<bool>,
+    free: Vec<PixelBuffer>,
+    in_flight: VecDeque<PixelBuffer>,
+    stopped: bool,
+}
+
+struct PixelBuffer {
+    pbo: gl::GLuint,
+    capacity: usize,
+    width: u32,
+    height: u32,
+    fence: gl::GLsync,
+}
+
+// The fence is only ever used with the GL context that created it.
+unsafe impl Send for PixelBuffer {}
+
+impl PixelReadback {
+    pub(crate) fn new() -> PixelReadback {
+        PixelReadback {
+            supported: None,
+            free: Vec::new(),
+            in_flight: VecDeque::new(),
+            stopped: false,
+        }
+    }
+
+    // Starts reading back a width x height frame from the bound read framebuffer,
+    // and returns the oldest finished frame (if any) in GL row order.
+    pub(crate) fn read(
+        &mut self,
+        gl: &dyn Gl,
+        width: u32,
+        height: u32,
+        pool: &mut ArcPool<Vec<u8>>,
+    ) -> Option<(u32, u32, Arc<Vec<u8>>)> {
+        if self.stopped {
+            self.stopped = false;
+            self.delete(gl);
+        }
+
+        if !*self.supported.get_or_insert_with(|| pbos_supported(gl)) {
+            return Some(read_sync(gl, width, height, pool));
+        }
+
+        self.start_read(gl, width, height);
+        if self.in_flight.len() > LATENCY {
+            self.finish_read(gl, pool)
+        } else {
+            None
+        }
+    }
+
+    // Frames which are still in flight when presentation stops are never shown.
+    // There's no GL context here, so the buffers are deleted on the next call to read,
+    // or by the GL implementation when their context is destroyed.
+    pub(crate) fn stop(&mut self) {
+        self.stopped = !self.free.is_empty() || !self.in_flight.is_empty();
+    }
+
+    // Deletes the buffers and fences, which must belong to the current GL context.
+    fn delete(&mut self, gl: &dyn Gl) {
+        self.discard_in_flight(gl);
+        let pbos: Vec<gl::GLuint> = self.free.drain(..).map(|buffer| buffer.pbo).collect();
+        if !pbos.is_empty() {
+            gl.delete_buffers(&pbos);
+        }
+    }
+
+    fn discard_in_flight(&mut self, gl: &dyn Gl) {
+        for buffer in self.in_flight.drain(..) {
+            gl.delete_sync(buffer.fence);
+            self.free.push(PixelBuffer { fence: ptr::null(), ..buffer });
+        }
+    }
+
+    fn start_read(&mut self, gl: &dyn Gl, width: u32, height: u32) {
+        let num_bytes = (width as usize) * (height as usize) * 4;
+        let mut buffer = self.free.pop().unwrap_or_else(|| PixelBuffer {
+            pbo: gl.gen_buffers(1)[0],
+            capacity: 0,
+            width: 0,
+            height: 0,
+            fence: ptr::null(),
+        });
+
+        let mut previous = [0];
+        unsafe { gl.get_integer_v(gl::PIXEL_PACK_BUFFER_BINDING, &mut previous) };
+
+        gl.bind_buffer(gl::PIXEL_PACK_BUFFER, buffer.pbo);
+        if buffer.capacity < num_bytes {
+            gl.buffer_data_untyped(
+                gl::PIXEL_PACK_BUFFER,
+                num_bytes as gl::GLsizeiptr,
+                ptr::null(),
+                gl::STREAM_READ,
+            );
+            buffer.capacity = num_bytes;
+        }
+        unsafe {
+            gl.read_pixels_into_pbo(
+                0,
+                0,
+                width as gl::GLsizei,
+                height as gl::GLsizei,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+            );
+        }
+        gl.bind_buffer(gl::PIXEL_PACK_BUFFER, previous[0] as gl::GLuint);
+
+        buffer.width = width;
+        buffer.height = height;
+        buffer.fence = gl.fence_sync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+        self.in_flight.push_back(buffer);
+    }
+
+    fn finish_read(&mut self, gl: &dyn Gl, pool: &mut ArcPool<Vec<u8>>) -> Option<(u32, u32, Arc<Vec<u8>>)> {
+        let mut buffer = self.in_flight.pop_front()?;
+        let num_bytes = (buffer.width as usize) * (buffer.height as usize) * 4;
+
+        gl.client_wait_sync(buffer.fence, gl::SYNC_FLUSH_COMMANDS_BIT, FENCE_TIMEOUT);
+        gl.delete_sync(buffer.fence);
+        buffer.fence = ptr::null();
+
+        let mut previous = [0];
+        unsafe { gl.get_integer_v(gl::PIXEL_PACK_BUFFER_BINDING, &mut previous) };
+
+        gl.bind_buffer(gl::PIXEL_PACK_BUFFER, buffer.pbo);
+        let mapped = gl.map_buffer_range(
+            gl::PIXEL_PACK_BUFFER,
+            0,
+            num_bytes as gl::GLsizeiptr,
+            gl::MAP_READ_BIT,
+        );
+        let result = if mapped.is_null() {
+            None
+        } else {
+            let mut pixels = pool.remove().unwrap_or_default();
+            pixels.clear();
+            pixels.extend_from_slice(unsafe { slice::from_raw_parts(mapped as *const u8, num_bytes) });
+            gl.unmap_buffer(gl::PIXEL_PACK_BUFFER);
+            Some((buffer.width, buffer.height, pool.add(pixels)))
+        };
+        gl.bind_buffer(gl::PIXEL_PACK_BUFFER, previous[0] as gl::GLuint);
+
+        if result.is_none() {
+            // Give up on PBOs, and read back synchronously from now on.
+            warn!("Failed to map pixel buffer, falling back to synchronous readback");
+            self.supported = Some(false);
+            self.free.push(buffer);
+            self.delete(gl);
+            return None;
+        }
+
+        self.free.push(buffer);
+        result
+    }
+}
+
+fn read_sync(gl: &dyn Gl, width: u32, height: u32, pool: &mut ArcPool<Vec<u8>>) -> (u32, u32, Arc<Vec<u8>>) {
+    let num_bytes = (width as usize) * (height as usize) * 4;
+    let mut buffer = pool.remove().unwrap_or_default();
+    buffer.resize(num_bytes, 0);
+    gl.read_pixels_into_buffer(
+        0,
+        0,
+        width as gl::GLsizei,
+        height as gl::GLsizei,
+        gl::RGBA,
+        gl::UNSIGNED_BYTE,
+        &mut buffer[..],
+    );
+    (width, height, pool.add(buffer))
+}
+
+fn pbos_supported(gl: &dyn Gl) -> bool {
+    let version = gl.get_string(gl::VERSION);
+    version_supports_pbos(gl.get_type(), &version, || has_extension(gl, "GL_ARB_sync"))
+}
+
+// PBOs need GL 2.1 or GLES 3.0, and fences need GL 3.2 (or ARB_sync) or GLES 3.0.
+// The extensions are only checked when the version isn't enough.
+fn version_supports_pbos(gl_type: gl::GlType, version: &str, has_arb_sync: impl FnOnce() -> bool) -> bool {
+    let (major, minor) = parse_version(version).unwrap_or((0, 0));
+    match gl_type {
+        gl::GlType::Gl => (major, minor) >= (3, 2) || ((major, minor) >= (2, 1) && has_arb_sync()),
+        gl::GlType::Gles => major >= 3,
+    }
+}
+
+// Finds the first "major.minor" in a GL_VERSION string,
+// e.g. "4.5 (Core Profile) Mesa 19.0.0" or "OpenGL ES 3.2 NVIDIA 418.56".
+fn parse_version(version: &str) -> Option<(u32, u32)> {
+    let start = version.find(|c: char| c.is_ascii_digit())?;
+    let mut numbers = version[start..]
+        .split(|c: char| !c.is_ascii_digit())
+        .map(|n| n.parse().ok());
+    Some((numbers.next()??, numbers.next()??))
+}
+
+fn has_extension(gl: &dyn Gl, name: &str) -> bool {
+    gl.get_string(gl::EXTENSIONS).split(' ').any(|extension| extension == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use gleam::gl::GlType;
+    use super::{parse_version, version_supports_pbos};
+
+    #[test]
+    fn versions_are_parsed_from_vendor_strings() {
+        assert_eq!(parse_version("4.5 (Core Profile) Mesa 19.0.0"), Some((4, 5)));
+        assert_eq!(parse_version("3.0 Mesa 19.0.0"), Some((3, 0)));
+        assert_eq!(parse_version("4.6.0 NVIDIA 418.56"), Some((4, 6)));
+        assert_eq!(parse_version("2.1 INTEL-12.10.12"), Some((2, 1)));
+        assert_eq!(parse_version("OpenGL ES 3.2 NVIDIA 418.56"), Some((3, 2)));
+        assert_eq!(parse_version("OpenGL ES 2.0 (ANGLE 2.1.0.8613f4946861)"), Some((2, 0)));
+        assert_eq!(parse_version("OpenGL ES 3"), None);
+        assert_eq!(parse_version("unknown"), None);
+    }
+
+    #[test]
+    fn pbos_need_a_recent_enough_version() {
+        let no_sync = || false;
+        assert!(version_supports_pbos(GlType::Gl, "4.5 (Core Profile) Mesa 19.0.0", no_sync));
+        assert!(version_supports_pbos(GlType::Gl, "3.2.0 NVIDIA 418.56", no_sync));
+        assert!(!version_supports_pbos(GlType::Gl, "3.1 Mesa 19.0.0", no_sync));
+        assert!(!version_supports_pbos(GlType::Gl, "2.0 INTEL-8.0", || true));
+        assert!(!version_supports_pbos(GlType::Gl, "unknown", || true));
+        assert!(version_supports_pbos(GlType::Gles, "OpenGL ES 3.0 Mesa 19.0.0", no_sync));
+        assert!(!version_supports_pbos(GlType::Gles, "OpenGL ES 2.0 (ANGLE 2.1.0.8613f4946861)", || true));
+    }
+
+    #[test]
+    fn older_gl_needs_arb_sync() {
+        assert!(version_supports_pbos(GlType::Gl, "2.1 INTEL-12.10.12", || true));
+        assert!(!version_supports_pbos(GlType::Gl, "2.1 INTEL-12.10.12", || false));
+        // Recent versions don't look at the extensions, which core profiles can't list.
+        assert!(version_supports_pbos(GlType::Gl, "4.1 ATI-3.10.19", || panic!("Checked extensions")));
+    }
+}
@@ -2,9 +2,14 @@ use gleam::gl::Gl;
 use glutin::{WindowedContext, NotCurrent};
 use glutin::EventsLoop;
 use glutin::EventsLoopClosed;
-use glutin::dpi::PhysicalSize;
+use euclid::Angle;
 use rust_webvr_api::VRDisplayPtr;
+use rust_webvr_api::VRDisplay;
+use rust_webvr_api::VRDisplayEvent;
+use rust_webvr_api::VRDisplayEventReason;
 use rust_webvr_api::VREvent;
+use rust_webvr_api::VRGamepadEvent;
+use rust_webvr_api::VRGamepadHand;
 use rust_webvr_api::VRGamepadPtr;
 use rust_webvr_api::VRService;
 use std::cell::RefCell;
@@ -14,14 +19,29 @@ use std::sync::mpsc::channel;
 use std::sync::mpsc::Sender;
 use super::display::GlWindowVRDisplay;
 use super::display::GlWindowVRDisplayPtr;
+use super::display::GlWindowVRParameters;
+use super::gamepad;
+use super::gamepad::GlWindowVRGamepad;
+use super::gamepad::GlWindowVRGamepadPtr;
 use super::heartbeat::GlWindowVRMainThreadHeartbeat;
 use super::heartbeat::GlWindowVRMessage;
+use super::heartbeat::GlWindowVRShared;
+use super::heartbeat::GlWindowVRWindowEvent;
 
 pub struct GlWindowVRService {
+    windows: Vec<GlWindowVRServiceWindow>,
+    hands: Vec<VRGamepadHand>,
+    events: RefCell<Vec<VREvent>>,
+}
+
+// One of the heartbeat's windows, as seen from the VR thread.
+struct GlWindowVRServiceWindow {
     name: String,
-    size: PhysicalSize,
+    shared: GlWindowVRShared,
+    parameters: GlWindowVRParameters,
     sender: Sender<GlWindowVRMessage>,
     display: Option<GlWindowVRDisplayPtr>,
+    gamepads: Option<Vec<GlWindowVRGamepadPtr>>,
 }
 
 // This is very very unsafe, but the API requires it.
@@ -29,16 +49,24 @@ unsafe impl Send for GlWindowVRService {}
 
 impl VRService for GlWindowVRService {
     fn initialize(&mut self) -> Result<(), String> {
-        self.get_display();
+        for window in &mut self.windows {
+            window.get_display();
+            window.get_gamepads(&self.hands, &self.events);
+        }
         Ok(())
     }
 
     fn fetch_displays(&mut self) -> Result<Vec<VRDisplayPtr>, String> {
-        Ok(vec![ self.get_display().clone() ])
+        Ok(self.windows.iter_mut().map(|window| window.get_display().clone() as VRDisplayPtr).collect())
     }
 
     fn fetch_gamepads(&mut self) -> Result<Vec<VRGamepadPtr>, String> {
-        Ok(vec![])
+        let hands = &self.hands;
+        let events = &self.events;
+        Ok(self.windows.iter_mut()
+            .flat_map(|window| window.get_gamepads(hands, events).iter())
+            .map(|gamepad| gamepad.clone() as VRGamepadPtr)
+            .collect())
     }
 
     fn is_available(&self) -> bool {
@@ -46,7 +74,11 @@ impl VRService for GlWindowVRService {
     }
 
     fn poll_events(&self) -> Vec<VREvent> {
-        vec![]
+        let mut events: Vec<VREvent> = self.events.borrow_mut().drain(..).collect();
+        for window in &self.windows {
+            window.poll_events(&mut events);
+        }
+        events
     }
 }
 
@@ -58,28 +90,355 @@ impl GlWindowVRService {
 	events_loop_factory: EventsLoopFactory,
         gl: Rc<dyn Gl>,
     ) -> (GlWindowVRService, GlWindowVRMainThreadHeartbeat) {
+        GlWindowVRService::builder(name).build(gl_context, events_loop_factory, gl)
+    }
+
+    /// Starts building a service whose stereo parameters differ from the defaults.
+    pub fn builder(name: String) -> GlWindowVRServiceBuilder {
+        GlWindowVRServiceBuilder {
+            name,
+            parameters: GlWindowVRParameters::default(),
+        }
+    }
+
+    /// Adds another window, shown as a separate display with the builder's name and parameters.
+    /// Its events are handled by the heartbeat that was built with this service.
+    /// This function should be called from the main thread.
+    pub fn add_display(
+        &mut self,
+        builder: GlWindowVRServiceBuilder,
+        gl_context: WindowedContext<NotCurrent>,
+        heartbeat: &mut GlWindowVRMainThreadHeartbeat,
+    ) {
         let (sender, receiver) = channel();
-        let size = gl_context.window().get_inner_size().expect("No window size");
-        let hidpi = gl_context.window().get_hidpi_factor();
-        let heartbeat = GlWindowVRMainThreadHeartbeat::new(receiver, gl_context, events_loop_factory, gl);
-        let service = GlWindowVRService {
-            name: name,
-            size: size.to_physical(hidpi),
-            sender: sender,
+        let shared = GlWindowVRShared::new(&gl_context, &self.hands, &builder.parameters);
+        heartbeat.add_window(receiver, gl_context, shared.clone(), builder.parameters);
+        self.windows.push(GlWindowVRServiceWindow::new(builder.name, shared, builder.parameters, sender));
+    }
+
+    /// Sets which hands the emulated controllers are held in, one controller per hand.
+    /// Every display gets its own controllers, which follow the cursor in its window.
+    /// The controller following the cursor is switched with the `SwitchController` action.
+    /// By default there is one controller, in the right hand.
+    /// This has no effect once the gamepads have been fetched.
+    pub fn set_controllers(&mut self, hands: Vec<VRGamepadHand>) {
+        if self.windows.iter().any(|window| window.gamepads.is_some()) {
+            warn!("Emulated controllers can't be changed once they have been fetched");
+            return;
+        }
+        for window in &self.windows {
+            *window.shared.controllers.lock().unwrap() = hands.iter().map(|_| gamepad::new_state()).collect();
+        }
+        self.hands = hands;
+    }
+}
+
+impl GlWindowVRServiceWindow {
+    fn new(
+        name: String,
+        shared: GlWindowVRShared,
+        parameters: GlWindowVRParameters,
+        sender: Sender<GlWindowVRMessage>,
+    ) -> GlWindowVRServiceWindow {
+        GlWindowVRServiceWindow {
+            name,
+            shared,
+            parameters,
+            sender,
             display: None,
-        };
-        (service, heartbeat)
+            gamepads: None,
+        }
     }
 
     fn get_display(&mut self) -> &mut GlWindowVRDisplayPtr {
         let name = &self.name;
         let sender = &self.sender;
-        let size = self.size;
+        let size = &self.shared.size;
+        let pose = &self.shared.pose;
+        let parameters = self.parameters;
         self.display.get_or_insert_with(|| {
-            let display = GlWindowVRDisplay::new(name.clone(), size, sender.clone());
+            let display = GlWindowVRDisplay::new(name.clone(), size.clone(), pose.clone(), parameters, sender.clone());
             Arc::new(RefCell::new(display))
         })
     }
+
+    fn get_gamepads(&mut self, hands: &[VRGamepadHand], events: &RefCell<Vec<VREvent>>) -> &mut Vec<GlWindowVRGamepadPtr> {
+        let display_id = self.get_display().borrow().id();
+        let name = &self.name;
+        let controllers = &self.shared.controllers;
+        self.gamepads.get_or_insert_with(|| {
+            hands.iter().enumerate().map(|(index, hand)| {
+                let data = gamepad::new_data(display_id, name, hand.clone());
+                let state = controllers.lock().unwrap()[index].clone();
+                events.borrow_mut().push(VRGamepadEvent::Connect(data.clone(), state).into());
+                GlWindowVRGamepad::new(data, index, controllers.clone())
+            }).collect()
+        })
+    }
+
+    fn poll_events(&self, events: &mut Vec<VREvent>) {
+        let window_events: Vec<_> = self.shared.window_events.lock().unwrap().drain(..).collect();
+        let display = match self.display {
+            Some(ref display) => display,
+            None => return,
+        };
+        for window_event in window_events {
+            let id = display.borrow().id();
+            let event = match window_event {
+                GlWindowVRWindowEvent::Focused(true) => VRDisplayEvent::Focus(display.borrow().data()),
+                GlWindowVRWindowEvent::Focused(false) => VRDisplayEvent::Blur(display.borrow().data()),
+                GlWindowVRWindowEvent::CloseRequested => {
+                    let data = display.borrow().data();
+                    events.push(VRDisplayEvent::Deactivate(data, VRDisplayEventReason::Unmounted).into());
+                    VRDisplayEvent::Exit(id)
+                },
+                GlWindowVRWindowEvent::Minimized(true) => VRDisplayEvent::Pause(id),
+                GlWindowVRWindowEvent::Minimized(false) => VRDisplayEvent::Resume(id),
+                GlWindowVRWindowEvent::Resized => VRDisplayEvent::Change(display.borrow().data()),
+            };
+            events.push(event.into());
+        }
+    }
+}
+
+/// Builds a `GlWindowVRService`, so that the preview can behave like a particular headset.
+/// Further displays can be added to the service with `GlWindowVRService::add_display`.
+pub struct GlWindowVRServiceBuilder {
+    name: String,
+    parameters: GlWindowVRParameters,
+}
+
+impl GlWindowVRServiceBuilder {
+    /// Sets the distance between the eyes, in meters. The default is 5cm.
+    pub fn with_ipd(mut self, ipd: f32) -> GlWindowVRServiceBuilder {
+        self.parameters.ipd = ipd;
+        self
+    }
+
+    /// Sets the vertical field of view, in degrees.
+    /// By default it is derived from the window size.
+    pub fn with_vertical_fov(mut self, degrees: f64) -> GlWindowVRServiceBuilder {
+        self.parameters.vertical_fov = Some(Angle::degrees(degrees));
+        self
+    }
+
+    /// Sets how far above the floor the viewer's eyes start, in meters. The default is 0.
+    /// This only applies with positional tracking.
+    pub fn with_eye_height(mut self, eye_height: f32) -> GlWindowVRServiceBuilder {
+        self.parameters.eye_height = eye_height;
+        self
+    }
+
+    /// Sets whether the pose has a position (6DoF), or just an orientation (3DoF).
+    /// Without positional tracking the viewer can look around but not move.
+    /// The default is to have positional tracking.
+    pub fn with_positional_tracking(mut self, positional_tracking: bool) -> GlWindowVRServiceBuilder {
+        self.parameters.positional_tracking = positional_tracking;
+        self
+    }
+
+    // This function should be called from the main thread.
+    pub fn build(
+        self,
+        gl_context: WindowedContext<NotCurrent>,
+        events_loop_factory: EventsLoopFactory,
+        gl: Rc<dyn Gl>,
+    ) -> (GlWindowVRService, GlWindowVRMainThreadHeartbeat) {
+        let (sender, receiver) = channel();
+        let hands = vec![VRGamepadHand::Right];
+        let shared = GlWindowVRShared::new(&gl_context, &hands, &self.parameters);
+        let heartbeat = GlWindowVRMainThreadHeartbeat::new(
+            receiver,
+            gl_context,
+            events_loop_factory,
+            gl,
+            shared.clone(),
+            self.parameters,
+        );
+        let window = GlWindowVRServiceWindow::new(self.name, shared, self.parameters, sender);
+        let service = GlWindowVRService {
+            windows: vec![window],
+            hands,
+            events: RefCell::new(Vec::new()),
+        };
+        (service, heartbeat)
+    }
 }
 
 pub type EventsLoopFactory = Box<Fn() -> Result<EventsLoop, EventsLoopClosed>>;
+
+#[cfg(test)]
+mod tests {
+    use euclid::RigidTransform3D;
+    use glutin::dpi::PhysicalSize;
+    use rust_webvr_api::VRDisplay;
+    use rust_webvr_api::VRDisplayData;
+    use rust_webvr_api::VRDisplayEvent;
+    use rust_webvr_api::VREvent;
+    use rust_webvr_api::VRFrameData;
+    use rust_webvr_api::VRGamepadEvent;
+    use rust_webvr_api::VRGamepadHand;
+    use rust_webvr_api::VRService;
+    use std::cell::RefCell;
+    use std::sync::{Arc, Mutex};
+    use std::sync::mpsc::channel;
+    use super::super::display::{GlWindowVRDisplay, GlWindowVRParameters};
+    use super::super::gamepad;
+    use super::super::heartbeat::{GlWindowVRShared, GlWindowVRWindowEvent};
+    use super::{GlWindowVRService, GlWindowVRServiceBuilder, GlWindowVRServiceWindow};
+
+    // The data and immediate frame data of an 800x600 window built with the given builder.
+    fn display_for(builder: GlWindowVRServiceBuilder) -> (VRDisplayData, VRFrameData) {
+        let parameters = builder.parameters;
+        let size = Arc::new(Mutex::new(PhysicalSize::new(800.0, 600.0)));
+        let pose = Arc::new(Mutex::new(parameters.initial_pose()));
+        let display = GlWindowVRDisplay::new(builder.name, size, pose, parameters, channel().0);
+        (display.data(), display.immediate_frame_data(0.1, 1000.0))
+    }
+
+    fn builder() -> GlWindowVRServiceBuilder {
+        GlWindowVRService::builder("test".into())
+    }
+
+    fn assert_near(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-3, "{} != {}", actual, expected);
+    }
+
+    // The translation of a row major view matrix.
+    fn translation(view_matrix: &[f32; 16]) -> [f32; 3] {
+        [view_matrix[12], view_matrix[13], view_matrix[14]]
+    }
+
+    #[test]
+    fn default_builder_fills_each_half_of_the_window() {
+        let (data, frame_data) = display_for(builder());
+        assert_eq!(data.display_name, "test");
+        assert!(data.capabilities.has_position);
+        let left = &data.left_eye_parameters;
+        assert_eq!((left.render_width, left.render_height), (400, 600));
+        // The vertical fov is derived from the window, with an approximate atan.
+        let fov = &left.field_of_view;
+        assert!((fov.up_degrees - 1.5f64.atan().to_degrees()).abs() < 0.1, "{}", fov.up_degrees);
+        assert_eq!(fov.down_degrees, fov.up_degrees);
+        // The horizontal fov follows from the aspect ratio of an eye, which is 400x600.
+        let right = (fov.up_degrees.to_radians().tan() * 2.0 / 3.0).atan().to_degrees();
+        assert_near(fov.right_degrees, right);
+        assert_eq!(fov.left_degrees, fov.right_degrees);
+        assert_eq!(left.offset, [-0.025, 0.0, 0.0]);
+        assert_eq!(data.right_eye_parameters.offset, [0.025, 0.0, 0.0]);
+        assert_eq!(translation(&frame_data.left_view_matrix), [0.025, 0.0, 0.0]);
+        assert_eq!(translation(&frame_data.right_view_matrix), [-0.025, 0.0, 0.0]);
+        assert_eq!(frame_data.pose.position, Some([0.0, 0.0, 0.0]));
+    }
+
+    #[test]
+    fn vertical_fov_and_ipd_are_used() {
+        let (data, frame_data) = display_for(builder().with_vertical_fov(90.0).with_ipd(0.064));
+        let fov = &data.right_eye_parameters.field_of_view;
+        assert_near(fov.up_degrees, 45.0);
+        // The horizontal fov follows from the aspect ratio of an eye.
+        assert_near(fov.right_degrees, (2.0f64 / 3.0).atan().to_degrees());
+        assert_eq!(data.left_eye_parameters.offset, [-0.032, 0.0, 0.0]);
+        assert_eq!(data.right_eye_parameters.offset, [0.032, 0.0, 0.0]);
+        assert_eq!(translation(&frame_data.left_view_matrix), [0.032, 0.0, 0.0]);
+        assert_eq!(translation(&frame_data.right_view_matrix), [-0.032, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn eye_height_raises_the_viewer() {
+        let (data, frame_data) = display_for(builder().with_eye_height(1.6));
+        assert!(data.capabilities.has_position);
+        assert_eq!(data.left_eye_parameters.offset, [-0.025, 0.0, 0.0]);
+        assert_eq!(frame_data.pose.position, Some([0.0, 1.6, 0.0]));
+        assert_eq!(translation(&frame_data.left_view_matrix), [0.025, -1.6, 0.0]);
+        assert_eq!(translation(&frame_data.right_view_matrix), [-0.025, -1.6, 0.0]);
+    }
+
+    #[test]
+    fn orientation_only_tracking_has_no_position() {
+        let builder = builder().with_positional_tracking(false).with_eye_height(1.6);
+        assert_eq!(builder.parameters.initial_pose(), RigidTransform3D::identity());
+        let (data, frame_data) = display_for(builder);
+        assert!(!data.capabilities.has_position);
+        assert!(data.capabilities.has_orientation);
+        assert_eq!(frame_data.pose.position, None);
+        assert_eq!(frame_data.pose.orientation, Some([0.0, 0.0, 0.0, 1.0]));
+        // The eye height only applies with positional tracking.
+        assert_eq!(translation(&frame_data.left_view_matrix), [0.025, 0.0, 0.0]);
+    }
+
+    // A service with a window for each name, as if they had been added with add_display.
+    fn service(names: &[&str]) -> (GlWindowVRService, Vec<GlWindowVRShared>) {
+        let hands = vec![VRGamepadHand::Right];
+        let parameters = GlWindowVRParameters::default();
+        let shared: Vec<_> = names.iter().map(|_| GlWindowVRShared {
+            size: Arc::new(Mutex::new(PhysicalSize::new(800.0, 600.0))),
+            pose: Arc::new(Mutex::new(parameters.initial_pose())),
+            window_events: Arc::new(Mutex::new(Vec::new())),
+            controllers: Arc::new(Mutex::new(hands.iter().map(|_| gamepad::new_state()).collect())),
+        }).collect();
+        let windows = names.iter().zip(&shared).map(|(name, shared)| {
+            GlWindowVRServiceWindow::new(name.to_string(), shared.clone(), parameters, channel().0)
+        }).collect();
+        let service = GlWindowVRService {
+            windows,
+            hands,
+            events: RefCell::new(Vec::new()),
+        };
+        (service, shared)
+    }
+
+    fn display_ids(service: &mut GlWindowVRService) -> Vec<u32> {
+        service.fetch_displays().unwrap().iter().map(|display| display.borrow().id()).collect()
+    }
+
+    #[test]
+    fn each_window_has_its_own_display() {
+        let (mut service, _) = service(&["first", "second"]);
+        let displays = service.fetch_displays().unwrap();
+        let names: Vec<_> = displays.iter().map(|display| display.borrow().data().display_name).collect();
+        assert_eq!(names, vec!["first", "second"]);
+        let ids = display_ids(&mut service);
+        assert_ne!(ids[0], ids[1]);
+        assert_eq!(display_ids(&mut service), ids);
+        for (display, id) in displays.iter().zip(&ids) {
+            assert_eq!(display.borrow().data().display_id, *id);
+        }
+    }
+
+    #[test]
+    fn window_events_go_to_their_display() {
+        let (mut service, shared) = service(&["first", "second"]);
+        let ids = display_ids(&mut service);
+        shared[1].window_events.lock().unwrap().push(GlWindowVRWindowEvent::Focused(true));
+        shared[0].window_events.lock().unwrap().push(GlWindowVRWindowEvent::Minimized(true));
+        let events: Vec<_> = service.poll_events().into_iter().map(|event| match event {
+            VREvent::Display(VRDisplayEvent::Focus(data)) => format!("focus {}", data.display_id),
+            VREvent::Display(VRDisplayEvent::Pause(id)) => format!("pause {}", id),
+            event => panic!("Unexpected event {:?}", event),
+        }).collect();
+        assert_eq!(events, vec![format!("pause {}", ids[0]), format!("focus {}", ids[1])]);
+        assert!(service.poll_events().is_empty());
+    }
+
+    #[test]
+    fn each_window_connects_its_gamepads_once() {
+        let (mut service, _) = service(&["first", "second"]);
+        service.set_controllers(vec![VRGamepadHand::Left, VRGamepadHand::Right]);
+        let ids = display_ids(&mut service);
+        assert_eq!(service.fetch_gamepads().unwrap().len(), 4);
+        let connected: Vec<_> = service.poll_events().into_iter().map(|event| match event {
+            VREvent::Gamepad(VRGamepadEvent::Connect(data, state)) => {
+                assert!(state.connected);
+                (data.display_id, format!("{:?}", data.hand))
+            },
+            event => panic!("Unexpected event {:?}", event),
+        }).collect();
+        let expected: Vec<_> = ids.iter()
+            .flat_map(|&id| vec![(id, String::from("Left")), (id, String::from("Right"))])
+            .collect();
+        assert_eq!(connected, expected);
+        assert_eq!(service.fetch_gamepads().unwrap().len(), 4);
+        assert!(service.poll_events().is_empty());
+    }
+}
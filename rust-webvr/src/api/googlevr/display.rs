@@ -1,6 +1,6 @@
 #![cfg(feature = "googlevr")]
 use {VRDisplay, VRDisplayData, VRDisplayCapabilities, VRFramebuffer, VRFramebufferAttributes,
-    VREvent, VRDisplayEvent, VREyeParameters, VRFrameData, VRLayer, VRViewport, VRGamepadPtr};
+    VREvent, VRDisplayEvent, VREyeParameters, VRFrameData, VRLayer, VRTexture, VRViewport, VRGamepadPtr};
 use super::service::GoogleVRService;
 use super::gamepad::{GoogleVRGamepad, GoogleVRGamepadPtr};
 use rust_webvr_api::utils;
@@ -197,19 +197,27 @@ impl VRDisplay for GoogleVRDisplay {
         }
         debug_assert!(self.fbo_id > 0);
 
+        let texture_id = match layer.texture {
+            VRTexture::Gl(texture_id) => texture_id,
+            ref texture => {
+                error!("GoogleVR can't present {:?} textures", texture.kind());
+                return;
+            }
+        };
+
         unsafe {
             // Save current fbo to restore it when the frame is submitted.
             let mut current_fbo = 0;
             gl::GetIntegerv(gl::FRAMEBUFFER_BINDING, &mut current_fbo);
 
-            if self.fbo_texture != layer.texture_id {
+            if self.fbo_texture != texture_id {
                 // Attach external texture to the used later in BlitFramebuffer.
                 gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo_id);
                 gl::FramebufferTexture2D(gl::FRAMEBUFFER,
                                         gl::COLOR_ATTACHMENT0,
                                         gl::TEXTURE_2D,
-                                        layer.texture_id, 0);
-                self.fbo_texture = layer.texture_id;
+                                        texture_id, 0);
+                self.fbo_texture = texture_id;
             }
 
             let texture_size = layer.texture_size.unwrap_or_else(|| {
@@ -0,0 +1,195 @@
+use png;
+use rust_webvr_api::VRCpuBuffer;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+// Keep a few seconds of frames by default, so a long running session doesn't fill memory.
+const DEFAULT_MAX_FRAMES: usize = 300;
+
+/// The per-eye images of a submitted frame.
+#[derive(Clone, Debug)]
+pub struct HeadlessVRFrame {
+    /// Counts the frames submitted to the display, starting at 0.
+    pub index: u64,
+    pub timestamp: f64,
+    pub left: VRCpuBuffer,
+    pub right: VRCpuBuffer,
+}
+
+/// A handle to the frames captured by a headless display.
+/// It can be cloned and used from any thread.
+#[derive(Clone)]
+pub struct HeadlessVRCapture(Arc<Mutex<HeadlessVRCaptureState>>);
+
+struct HeadlessVRCaptureState {
+    frames: VecDeque<HeadlessVRFrame>,
+    max_frames: usize,
+    png_directory: Option<PathBuf>,
+    frame_count: u64,
+}
+
+impl HeadlessVRCapture {
+    pub(crate) fn new() -> HeadlessVRCapture {
+        HeadlessVRCapture(Arc::new(Mutex::new(HeadlessVRCaptureState {
+            frames: VecDeque::new(),
+            max_frames: DEFAULT_MAX_FRAMES,
+            png_directory: None,
+            frame_count: 0,
+        })))
+    }
+
+    /// Sets how many frames are kept in memory, older frames are dropped first.
+    pub fn set_max_frames(&self, max_frames: usize) {
+        let mut state = self.0.lock().unwrap();
+        state.max_frames = max_frames;
+        while state.frames.len() > max_frames {
+            state.frames.pop_front();
+        }
+    }
+
+    /// Also writes every frame as `frame-<index>-left.png` and `frame-<index>-right.png`
+    /// into the given directory. Pass None to stop writing files.
+    pub fn set_png_directory(&self, directory: Option<PathBuf>) {
+        self.0.lock().unwrap().png_directory = directory;
+    }
+
+    /// The frames kept in memory, oldest first.
+    pub fn frames(&self) -> Vec<HeadlessVRFrame> {
+        self.0.lock().unwrap().frames.iter().cloned().collect()
+    }
+
+    pub fn latest(&self) -> Option<HeadlessVRFrame> {
+        self.0.lock().unwrap().frames.back().cloned()
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.0.lock().unwrap().frame_count
+    }
+
+    pub fn clear(&self) {
+        self.0.lock().unwrap().frames.clear();
+    }
+
+    pub(crate) fn add(&self, timestamp: f64, left: VRCpuBuffer, right: VRCpuBuffer) {
+        let (index, png_directory) = {
+            let mut state = self.0.lock().unwrap();
+            state.frame_count += 1;
+            (state.frame_count - 1, state.png_directory.clone())
+        };
+        let frame = HeadlessVRFrame {
+            index,
+            timestamp,
+            left,
+            right,
+        };
+
+        // Encoding and writing the files is slow, so don't hold up readers of the frames meanwhile.
+        if let Some(directory) = png_directory {
+            let left_path = directory.join(format!("frame-{:05}-left.png", index));
+            let right_path = directory.join(format!("frame-{:05}-right.png", index));
+            if let Err(e) = save_png(&frame.left, &left_path).and_then(|_| save_png(&frame.right, &right_path)) {
+                error!("Failed to write headless VR frame {}: {}", index, e);
+            }
+        }
+
+        let mut state = self.0.lock().unwrap();
+        if state.max_frames == 0 {
+            return;
+        }
+        if state.frames.len() == state.max_frames {
+            state.frames.pop_front();
+        }
+        state.frames.push_back(frame);
+    }
+}
+
+fn save_png(image: &VRCpuBuffer, path: &Path) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), image.width, image.height);
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(&image.data).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use png;
+    use rust_webvr_api::VRCpuBuffer;
+    use std::env;
+    use std::fs::{self, File};
+    use std::process;
+    use super::HeadlessVRCapture;
+
+    fn image(value: u8) -> VRCpuBuffer {
+        VRCpuBuffer::from_rgba(2, 1, vec![value, 0, 0, 255, 0, value, 0, 255]).unwrap()
+    }
+
+    fn indices(capture: &HeadlessVRCapture) -> Vec<u64> {
+        capture.frames().iter().map(|frame| frame.index).collect()
+    }
+
+    #[test]
+    fn frames_are_counted() {
+        let capture = HeadlessVRCapture::new();
+        for i in 0..3 {
+            capture.add(i as f64 * 10.0, image(i), image(i));
+        }
+        assert_eq!(indices(&capture), vec![0, 1, 2]);
+        let latest = capture.latest().unwrap();
+        assert_eq!((latest.index, latest.timestamp), (2, 20.0));
+        assert_eq!(latest.left.pixel(0, 0), [2, 0, 0, 255]);
+
+        // Clearing the frames doesn't restart the count.
+        capture.clear();
+        capture.add(30.0, image(3), image(3));
+        assert_eq!(indices(&capture), vec![3]);
+        assert_eq!(capture.frame_count(), 4);
+    }
+
+    #[test]
+    fn oldest_frames_are_dropped() {
+        let capture = HeadlessVRCapture::new();
+        for i in 0..3 {
+            capture.add(0.0, image(i), image(i));
+        }
+        capture.set_max_frames(2);
+        assert_eq!(indices(&capture), vec![1, 2]);
+        capture.add(0.0, image(3), image(3));
+        assert_eq!(indices(&capture), vec![2, 3]);
+
+        capture.set_max_frames(0);
+        capture.add(0.0, image(4), image(4));
+        assert!(capture.frames().is_empty());
+        assert_eq!(capture.frame_count(), 5);
+    }
+
+    #[test]
+    fn frames_are_written_as_png() {
+        let directory = env::temp_dir().join(format!("rust-webvr-test-{}-headless", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let capture = HeadlessVRCapture::new();
+        capture.add(0.0, image(1), image(1));
+        capture.set_png_directory(Some(directory.clone()));
+        capture.add(0.0, image(7), image(9));
+        capture.set_png_directory(None);
+        capture.add(0.0, image(1), image(1));
+
+        let mut files: Vec<_> = fs::read_dir(&directory).unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(files, vec!["frame-00001-left.png", "frame-00001-right.png"]);
+
+        let decoder = png::Decoder::new(File::open(directory.join("frame-00001-right.png")).unwrap());
+        let (info, mut reader) = decoder.read_info().unwrap();
+        assert_eq!((info.width, info.height), (2, 1));
+        let mut data = vec![0; info.buffer_size()];
+        reader.next_frame(&mut data).unwrap();
+        assert_eq!(data, image(9).data);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,347 @@
+use gleam::gl;
+use gleam::gl::Gl;
+use rust_webvr_api::utils;
+use rust_webvr_api::VRCpuBuffer;
+use rust_webvr_api::VRCompositor;
+use rust_webvr_api::VRDisplay;
+use rust_webvr_api::VRDisplayCapabilities;
+use rust_webvr_api::VRDisplayData;
+use rust_webvr_api::VREye;
+use rust_webvr_api::VREyeParameters;
+use rust_webvr_api::VRFieldOfView;
+use rust_webvr_api::VRFrameData;
+use rust_webvr_api::VRFramebuffer;
+use rust_webvr_api::VRFramebufferAttributes;
+use rust_webvr_api::VRGamepadPtr;
+use rust_webvr_api::VRLayer;
+use rust_webvr_api::VRQuadLayer;
+use rust_webvr_api::VRTexture;
+use rust_webvr_api::VRTextureKind;
+use rust_webvr_api::VRViewport;
+use std::cell::RefCell;
+use std::mem;
+use std::sync::Arc;
+use super::capture::HeadlessVRCapture;
+
+// Fake a display with a distance between eyes of 64mm.
+const EYE_DISTANCE: f32 = 0.064;
+
+// Half of the field of view in each direction.
+const FOV_DEGREES: f64 = 45.0;
+
+// Depth range used when compositing CPU layers.
+const NEAR: f64 = 0.1;
+const FAR: f64 = 1000.0;
+
+// Timestamps advance as if frames were shown at 60Hz, so captures are reproducible.
+const FRAME_DURATION: f64 = 1000.0 / 60.0;
+
+pub type HeadlessVRDisplayPtr = Arc<RefCell<HeadlessVRDisplay>>;
+
+pub struct HeadlessVRDisplay {
+    id: u32,
+    name: String,
+    width: u32,
+    height: u32,
+    frame_index: u64,
+    capture: HeadlessVRCapture,
+    compositor: VRCompositor,
+    pending_layer: Option<VRLayer>,
+    pending_quads: Vec<VRQuadLayer>,
+}
+
+unsafe impl Sync for HeadlessVRDisplay {}
+
+impl VRDisplay for HeadlessVRDisplay {
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn data(&self) -> VRDisplayData {
+        let capabilities = VRDisplayCapabilities {
+            has_position: false,
+            has_orientation: false,
+            has_external_display: true,
+            can_present: true,
+            max_layers: 1,
+            ..VRDisplayCapabilities::default()
+        };
+
+        let left_eye_parameters = VREyeParameters {
+            offset: [-EYE_DISTANCE / 2.0, 0.0, 0.0],
+            render_width: self.width,
+            render_height: self.height,
+            field_of_view: HeadlessVRDisplay::field_of_view(),
+        };
+
+        let right_eye_parameters = VREyeParameters {
+            offset: [EYE_DISTANCE / 2.0, 0.0, 0.0],
+            ..left_eye_parameters.clone()
+        };
+
+        VRDisplayData {
+            display_id: self.id,
+            display_name: self.name.clone(),
+            connected: true,
+            capabilities,
+            stage_parameters: None,
+            left_eye_parameters,
+            right_eye_parameters,
+        }
+    }
+
+    fn immediate_frame_data(&self, near: f64, far: f64) -> VRFrameData {
+        let projection_matrix = utils::fov_to_projection_matrix(&HeadlessVRDisplay::field_of_view(), near, far);
+        let view_matrix = |offset: f32| -> [f32; 16] {
+            [1.0, 0.0, 0.0, 0.0,
+             0.0, 1.0, 0.0, 0.0,
+             0.0, 0.0, 1.0, 0.0,
+             -offset, 0.0, 0.0, 1.0]
+        };
+
+        VRFrameData {
+            timestamp: self.timestamp(),
+            left_projection_matrix: projection_matrix,
+            right_projection_matrix: projection_matrix,
+            left_view_matrix: view_matrix(-EYE_DISTANCE / 2.0),
+            right_view_matrix: view_matrix(EYE_DISTANCE / 2.0),
+            ..VRFrameData::default()
+        }
+    }
+
+    fn synced_frame_data(&self, near: f64, far: f64) -> VRFrameData {
+        self.immediate_frame_data(near, far)
+    }
+
+    fn reset_pose(&mut self) {}
+
+    // There is no vsync to wait for, the next frame starts straight away.
+    fn sync_poses(&mut self) {
+        self.frame_index += 1;
+    }
+
+    fn bind_framebuffer(&mut self, _eye_index: u32) {}
+
+    fn get_framebuffers(&self) -> Vec<VRFramebuffer> {
+        let left_viewport = VRViewport {
+            x: 0,
+            y: 0,
+            width: self.width as i32,
+            height: self.height as i32,
+        };
+
+        let right_viewport = VRViewport {
+            x: self.width as i32,
+            ..left_viewport
+        };
+
+        vec![
+            VRFramebuffer {
+                eye_index: 0,
+                attributes: VRFramebufferAttributes::default(),
+                viewport: left_viewport,
+            },
+            VRFramebuffer {
+                eye_index: 1,
+                attributes: VRFramebufferAttributes::default(),
+                viewport: right_viewport,
+            },
+        ]
+    }
+
+    fn supported_textures(&self) -> Vec<VRTextureKind> {
+        vec![VRTextureKind::Gl, VRTextureKind::GlArrayLayer, VRTextureKind::Cpu]
+    }
+
+    fn supports_quad_layers(&self) -> bool {
+        true
+    }
+
+    fn render_quad_layer(&mut self, quad: &VRQuadLayer) {
+        self.pending_quads.push(quad.clone());
+    }
+
+    // Without a GL context, only layers with a CPU buffer can be rendered.
+    fn render_layer(&mut self, layer: &VRLayer) {
+        if layer.texture.kind() != VRTextureKind::Cpu {
+            error!("Headless VR display needs submit_layer to read back {:?}", layer.texture);
+            return;
+        }
+        self.pending_layer = Some(layer.clone());
+    }
+
+    fn submit_frame(&mut self) {
+        if let Some(layer) = self.pending_layer.take() {
+            self.submit_cpu_layer(&layer);
+        }
+    }
+
+    fn submit_layer(&mut self, gl: &dyn Gl, layer: &VRLayer) {
+        let attachment = match layer.texture {
+            VRTexture::Cpu(..) => {
+                self.submit_cpu_layer(layer);
+                return;
+            }
+            VRTexture::Gl(texture_id) => Attachment::Texture2D(texture_id),
+            VRTexture::GlArrayLayer { texture_id, layer } => Attachment::TextureLayer(texture_id, layer),
+            ref texture => {
+                error!("Headless VR display can't read back {:?}", texture.kind());
+                return;
+            }
+        };
+        // Without a size hint, assume the eyes were rendered side by side at the recommended size.
+        let size = layer.texture_size.unwrap_or((self.width * 2, self.height));
+        let mut left = read_texture(gl, attachment, size, &layer.left_bounds);
+        let mut right = read_texture(gl, attachment, size, &layer.right_bounds);
+        self.compose_quads(&mut left, &mut right);
+        self.capture.add(self.timestamp(), left, right);
+    }
+
+    fn fetch_gamepads(&mut self) -> Result<Vec<VRGamepadPtr>, String> {
+        Ok(vec![])
+    }
+}
+
+impl HeadlessVRDisplay {
+    pub(crate) fn new(name: String, width: u32, height: u32, capture: HeadlessVRCapture) -> HeadlessVRDisplay {
+        HeadlessVRDisplay {
+            id: utils::new_id(),
+            name,
+            width,
+            height,
+            frame_index: 0,
+            capture,
+            compositor: VRCompositor::new(width, height),
+            pending_layer: None,
+            pending_quads: Vec::new(),
+        }
+    }
+
+    fn field_of_view() -> VRFieldOfView {
+        VRFieldOfView {
+            up_degrees: FOV_DEGREES,
+            right_degrees: FOV_DEGREES,
+            down_degrees: FOV_DEGREES,
+            left_degrees: FOV_DEGREES,
+        }
+    }
+
+    fn submit_cpu_layer(&mut self, layer: &VRLayer) {
+        let frame_data = self.immediate_frame_data(NEAR, FAR);
+        let quads = mem::take(&mut self.pending_quads);
+        let (left, right) = self.compositor.compose(&frame_data, Some(layer), &quads);
+        self.capture.add(self.timestamp(), left, right);
+    }
+
+    // Blends the pending quad layers over the images read back from GL.
+    fn compose_quads(&mut self, left: &mut VRCpuBuffer, right: &mut VRCpuBuffer) {
+        if self.pending_quads.is_empty() {
+            return;
+        }
+        let frame_data = self.immediate_frame_data(NEAR, FAR);
+        let quads = mem::take(&mut self.pending_quads);
+        self.compositor.compose_quads(VREye::Left, &frame_data, left, &quads);
+        self.compositor.compose_quads(VREye::Right, &frame_data, right, &quads);
+    }
+
+    fn timestamp(&self) -> f64 {
+        self.frame_index as f64 * FRAME_DURATION
+    }
+}
+
+// The GL textures that can be attached to a framebuffer for reading back.
+#[derive(Clone, Copy, Debug)]
+enum Attachment {
+    Texture2D(u32),
+    TextureLayer(u32, u32),
+}
+
+// Reads back the part of a texture inside the given UV bounds.
+// The current framebuffer binding is restored afterwards.
+fn read_texture(gl: &dyn Gl, attachment: Attachment, size: (u32, u32), bounds: &[f32; 4]) -> VRCpuBuffer {
+    let x = (bounds[0] * size.0 as f32).round() as i32;
+    let y = (bounds[1] * size.1 as f32).round() as i32;
+    let width = (bounds[2] * size.0 as f32).round().max(0.0) as u32;
+    let height = (bounds[3] * size.1 as f32).round().max(0.0) as u32;
+
+    let mut bound_framebuffer = [0];
+    unsafe { gl.get_integer_v(gl::FRAMEBUFFER_BINDING, &mut bound_framebuffer) };
+
+    let framebuffer = gl.gen_framebuffers(1)[0];
+    gl.bind_framebuffer(gl::FRAMEBUFFER, framebuffer);
+    match attachment {
+        Attachment::Texture2D(texture_id) =>
+            gl.framebuffer_texture_2d(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, texture_id, 0),
+        Attachment::TextureLayer(texture_id, layer) =>
+            gl.framebuffer_texture_layer(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, texture_id, 0, layer as gl::GLint),
+    }
+
+    let mut data = vec![0; width as usize * height as usize * 4];
+    if gl.check_frame_buffer_status(gl::FRAMEBUFFER) == gl::FRAMEBUFFER_COMPLETE {
+        gl.pixel_store_i(gl::PACK_ALIGNMENT, 4);
+        gl.read_pixels_into_buffer(
+            x,
+            y,
+            width as gl::GLsizei,
+            height as gl::GLsizei,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            &mut data[..],
+        );
+    } else {
+        error!("Headless VR display can't read back {:?}", attachment);
+    }
+
+    gl.bind_framebuffer(gl::FRAMEBUFFER, bound_framebuffer[0] as gl::GLuint);
+    gl.delete_framebuffers(&[framebuffer]);
+
+    // GL reads bottom row first, images are stored top row first.
+    let stride = width as usize * 4;
+    let flipped = data.chunks(stride.max(1)).rev().flat_map(|row| row.iter().cloned()).collect();
+
+    VRCpuBuffer {
+        width,
+        height,
+        data: flipped,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_webvr_api::VRCpuBuffer;
+    use rust_webvr_api::VRDisplay;
+    use rust_webvr_api::VRLayer;
+    use rust_webvr_api::VRQuadLayer;
+    use rust_webvr_api::VRTexture;
+    use super::HeadlessVRDisplay;
+    use super::super::capture::HeadlessVRCapture;
+
+    #[test]
+    #[allow(deprecated)]
+    fn quad_layers_are_composited_into_the_next_frame() {
+        let capture = HeadlessVRCapture::new();
+        let mut display = HeadlessVRDisplay::new(String::from("test"), 8, 8, capture.clone());
+        assert!(display.supports_quad_layers());
+
+        let red = VRCpuBuffer::from_rgba(1, 1, vec![255, 0, 0, 255]).unwrap();
+        let mut quad = VRQuadLayer {
+            buffer: red,
+            ..VRQuadLayer::default()
+        };
+        quad.transform[14] = -1.0;
+        let layer = VRLayer {
+            texture: VRTexture::Cpu(VRCpuBuffer::new(1, 1)),
+            ..VRLayer::default()
+        };
+
+        display.render_layer(&layer);
+        display.render_quad_layer(&quad);
+        display.submit_frame();
+        assert_eq!(capture.latest().unwrap().left.pixel(4, 4), [255, 0, 0, 255]);
+
+        // Quads only last for the frame they were rendered in.
+        display.render_layer(&layer);
+        display.submit_frame();
+        assert_eq!(capture.latest().unwrap().left.pixel(4, 4), [0, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,6 @@
+mod capture;
+mod display;
+mod service;
+
+pub use self::capture::{HeadlessVRCapture, HeadlessVRFrame};
+pub use self::service::HeadlessVRService;
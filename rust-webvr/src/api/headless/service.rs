@@ -0,0 +1,73 @@
+use rust_webvr_api::VRDisplayPtr;
+use rust_webvr_api::VREvent;
+use rust_webvr_api::VRGamepadPtr;
+use rust_webvr_api::VRService;
+use std::cell::RefCell;
+use std::sync::Arc;
+use super::capture::HeadlessVRCapture;
+use super::display::HeadlessVRDisplay;
+use super::display::HeadlessVRDisplayPtr;
+
+// A display without a window: submitted layers are read back into memory
+// so that they can be compared against reference images.
+pub struct HeadlessVRService {
+    name: String,
+    width: u32,
+    height: u32,
+    capture: HeadlessVRCapture,
+    display: Option<HeadlessVRDisplayPtr>,
+}
+
+// The display is only used from the thread which submits layers.
+unsafe impl Send for HeadlessVRService {}
+
+impl VRService for HeadlessVRService {
+    fn initialize(&mut self) -> Result<(), String> {
+        self.get_display();
+        Ok(())
+    }
+
+    fn fetch_displays(&mut self) -> Result<Vec<VRDisplayPtr>, String> {
+        Ok(vec![ self.get_display().clone() ])
+    }
+
+    fn fetch_gamepads(&mut self) -> Result<Vec<VRGamepadPtr>, String> {
+        Ok(vec![])
+    }
+
+    fn is_available(&self) -> bool {
+        true
+    }
+
+    fn poll_events(&self) -> Vec<VREvent> {
+        vec![]
+    }
+}
+
+impl HeadlessVRService {
+    // The width and height are the render size of each eye.
+    pub fn new(name: String, width: u32, height: u32) -> HeadlessVRService {
+        HeadlessVRService {
+            name,
+            width,
+            height,
+            capture: HeadlessVRCapture::new(),
+            display: None,
+        }
+    }
+
+    // A handle to the captured frames, which stays valid after the service is registered.
+    pub fn capture(&self) -> HeadlessVRCapture {
+        self.capture.clone()
+    }
+
+    fn get_display(&mut self) -> &mut HeadlessVRDisplayPtr {
+        let name = &self.name;
+        let (width, height) = (self.width, self.height);
+        let capture = &self.capture;
+        self.display.get_or_insert_with(|| {
+            let display = HeadlessVRDisplay::new(name.clone(), width, height, capture.clone());
+            Arc::new(RefCell::new(display))
+        })
+    }
+}
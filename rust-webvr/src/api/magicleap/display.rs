@@ -10,6 +10,7 @@ use rust_webvr_api::VRFramebuffer;
 use rust_webvr_api::VRFramebufferAttributes;
 use rust_webvr_api::VRGamepadPtr;
 use rust_webvr_api::VRLayer;
+use rust_webvr_api::VRTexture;
 use std::cell::RefCell;
 use std::sync::Arc;
 use std::sync::mpsc::Sender;
@@ -80,6 +81,14 @@ impl VRDisplay for MagicLeapVRDisplay {
     }
 
     fn submit_layer(&mut self, gl: &Gl, layer: &VRLayer) {
+        let source_id = match layer.texture {
+            VRTexture::Gl(texture_id) => texture_id,
+            ref texture => {
+                error!("Magic Leap display can't present {:?} textures", texture.kind());
+                return;
+            }
+        };
+
         // So... why does this blit exist? Well...
         // submit_layer is called from the WebGL thread,
         // but we need to display the texture in the main thread,
@@ -99,9 +108,9 @@ impl VRDisplay for MagicLeapVRDisplay {
         // so when the main thread is no longer using the texture, it gets returned
         // to the pool. It might be nice to use the same trick for webrender,
         // but that probably involves changing the webrender API.
-        let pooled_id = self.blit_texture(gl, layer);
-        let texture_id = pooled_id.texture_id();
-        let layer = VRLayer { texture_id, ..layer.clone() };
+        let pooled_id = self.blit_texture(gl, source_id, layer);
+        let texture = VRTexture::Gl(pooled_id.texture_id());
+        let layer = VRLayer { texture, ..layer.clone() };
         let _ = self.sender.send(MagicLeapVRMessage::StopFrame(layer, pooled_id));
     }
 
@@ -129,7 +138,7 @@ impl MagicLeapVRDisplay {
         MagicLeapVRDisplay { display_data, sender, fbos, texture_id_pool }
     }
 
-    fn blit_texture(&mut self, gl: &Gl, layer: &VRLayer) -> PooledGLTextureId {
+    fn blit_texture(&mut self, gl: &Gl, source_id: GLuint, layer: &VRLayer) -> PooledGLTextureId {
         // Sigh, all this code just to copy a texture...
 
         // The dimensions of the texture
@@ -154,7 +163,7 @@ impl MagicLeapVRDisplay {
         gl.framebuffer_texture_2d(gl::READ_FRAMEBUFFER,
                                   gl::COLOR_ATTACHMENT0,
                                   gl::TEXTURE_2D,
-                                  layer.texture_id, 0);
+                                  source_id, 0);
         debug_assert_eq!(gl.get_error(), gl::NO_ERROR);
 
         // Set the viewport
@@ -173,7 +182,7 @@ impl MagicLeapVRDisplay {
         debug_assert_eq!(gl.get_error(), gl::NO_ERROR);
 
         // Do the blit
-        debug!("Blitting from {} to {} ({}x{})", layer.texture_id, texture_id, texture_w, texture_h);
+        debug!("Blitting from {} to {} ({}x{})", source_id, texture_id, texture_w, texture_h);
         gl.blit_framebuffer(0, 0, texture_w, texture_h,
                             0, 0, texture_w, texture_h,
                             gl::COLOR_BUFFER_BIT, gl::LINEAR);
@@ -0,0 +1,308 @@
+use std::cmp::Ordering;
+use {MockVRTimeline, MockVRPoseKeyframe, VRPose, VRStageParameters};
+
+// Plays back a MockVRTimeline, starting at a given time (in milliseconds).
+pub struct MockVRAnimation {
+    timeline: MockVRTimeline,
+    start: f64,
+    // The loop count and index of the active stage keyframe, so that a looping
+    // timeline which wraps back to the same stage keyframe activates it again.
+    stage_index: Option<(u64, usize)>,
+}
+
+impl MockVRAnimation {
+    pub fn new(mut timeline: MockVRTimeline, start: f64) -> MockVRAnimation {
+        // Keyframes may arrive in any order, the lookups below need them sorted by time.
+        // The sort is stable, so keyframes with the same time keep their order.
+        timeline.pose.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap_or(Ordering::Equal));
+        timeline.stage.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap_or(Ordering::Equal));
+        MockVRAnimation {
+            timeline,
+            start,
+            stage_index: None,
+        }
+    }
+
+    // Time since the start of the timeline, wrapped if the timeline loops.
+    fn local_time(&self, now: f64) -> f64 {
+        self.loop_and_local_time(now).1
+    }
+
+    // How many times the timeline has looped, and the time since the start of the current loop.
+    fn loop_and_local_time(&self, now: f64) -> (u64, f64) {
+        let t = f64::max(0.0, now - self.start);
+        let duration = self.timeline.duration();
+        if self.timeline.looping && duration > 0.0 {
+            ((t / duration) as u64, t % duration)
+        } else {
+            (0, t)
+        }
+    }
+
+    // Returns the interpolated pose, or None if the timeline has no pose keyframes.
+    pub fn pose_at(&self, now: f64) -> Option<VRPose> {
+        let keyframes = &self.timeline.pose;
+        let t = self.local_time(now);
+        let next = keyframes.iter().position(|k| k.time > t);
+        match next {
+            None => keyframes.last().map(still_pose),
+            Some(0) => keyframes.first().map(still_pose),
+            Some(i) => Some(interpolate(&keyframes[i - 1], &keyframes[i], t)),
+        }
+    }
+
+    // Returns the stage parameters if a later stage keyframe has become active
+    // since the last call. The stage never moves back to an earlier keyframe.
+    pub fn stage_at(&mut self, now: f64) -> Option<VRStageParameters> {
+        let (count, t) = self.loop_and_local_time(now);
+        let index = self.timeline.stage.iter().rposition(|k| k.time <= t).map(|i| (count, i));
+        if index <= self.stage_index {
+            return None;
+        }
+        self.stage_index = index;
+        index.map(|(_, i)| self.timeline.stage[i].stage_parameters.clone())
+    }
+}
+
+fn still_pose(keyframe: &MockVRPoseKeyframe) -> VRPose {
+    VRPose {
+        position: Some(keyframe.position),
+        orientation: Some(keyframe.orientation),
+        linear_velocity: Some([0.0, 0.0, 0.0]),
+        angular_velocity: Some([0.0, 0.0, 0.0]),
+        ..VRPose::default()
+    }
+}
+
+fn interpolate(from: &MockVRPoseKeyframe, to: &MockVRPoseKeyframe, t: f64) -> VRPose {
+    let dt = (to.time - from.time) as f32;
+    let s = (t - from.time) as f32 / dt;
+    // Velocities are given per second, keyframe times are in milliseconds.
+    let dt = dt / 1000.0;
+
+    let p0 = from.position;
+    let p1 = to.position;
+    let position = [
+        p0[0] + (p1[0] - p0[0]) * s,
+        p0[1] + (p1[1] - p0[1]) * s,
+        p0[2] + (p1[2] - p0[2]) * s,
+    ];
+    let linear_velocity = [
+        (p1[0] - p0[0]) / dt,
+        (p1[1] - p0[1]) / dt,
+        (p1[2] - p0[2]) / dt,
+    ];
+
+    let q0 = normalize(from.orientation);
+    let q1 = normalize(to.orientation);
+    let orientation = slerp(q0, q1, s);
+
+    // The rotation from q0 to q1, taking the shortest path.
+    let mut delta = multiply(q1, conjugate(q0));
+    if delta[3] < 0.0 {
+        delta = [-delta[0], -delta[1], -delta[2], -delta[3]];
+    }
+    let w = delta[3].min(1.0);
+    let sin_half = (1.0 - w * w).sqrt();
+    let angular_velocity = if sin_half < 1e-6 {
+        [0.0, 0.0, 0.0]
+    } else {
+        let rate = 2.0 * w.acos() / (sin_half * dt);
+        [delta[0] * rate, delta[1] * rate, delta[2] * rate]
+    };
+
+    VRPose {
+        position: Some(position),
+        orientation: Some(orientation),
+        linear_velocity: Some(linear_velocity),
+        angular_velocity: Some(angular_velocity),
+        ..VRPose::default()
+    }
+}
+
+// Quaternions are stored as [x, y, z, w].
+
+pub fn normalize(q: [f32; 4]) -> [f32; 4] {
+    let len = (q[0] * q[0] + q[1] * q[1] + q[2] * q[2] + q[3] * q[3]).sqrt();
+    if len == 0.0 {
+        return [0.0, 0.0, 0.0, 1.0];
+    }
+    [q[0] / len, q[1] / len, q[2] / len, q[3] / len]
+}
+
+fn conjugate(q: [f32; 4]) -> [f32; 4] {
+    [-q[0], -q[1], -q[2], q[3]]
+}
+
+pub fn multiply(a: [f32; 4], b: [f32; 4]) -> [f32; 4] {
+    [
+        a[3] * b[0] + a[0] * b[3] + a[1] * b[2] - a[2] * b[1],
+        a[3] * b[1] - a[0] * b[2] + a[1] * b[3] + a[2] * b[0],
+        a[3] * b[2] + a[0] * b[1] - a[1] * b[0] + a[2] * b[3],
+        a[3] * b[3] - a[0] * b[0] - a[1] * b[1] - a[2] * b[2],
+    ]
+}
+
+fn slerp(a: [f32; 4], b: [f32; 4], s: f32) -> [f32; 4] {
+    let mut b = b;
+    let mut cos = a[0] * b[0] + a[1] * b[1] + a[2] * b[2] + a[3] * b[3];
+    if cos < 0.0 {
+        b = [-b[0], -b[1], -b[2], -b[3]];
+        cos = -cos;
+    }
+
+    // Fall back to a normalized lerp when the quaternions are very close.
+    let (wa, wb) = if cos > 0.9995 {
+        (1.0 - s, s)
+    } else {
+        let angle = cos.acos();
+        let sin = angle.sin();
+        (((1.0 - s) * angle).sin() / sin, (s * angle).sin() / sin)
+    };
+
+    normalize([
+        wa * a[0] + wb * b[0],
+        wa * a[1] + wb * b[1],
+        wa * a[2] + wb * b[2],
+        wa * a[3] + wb * b[3],
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {MockVRStageKeyframe, VRStageParameters};
+
+    const EPSILON: f32 = 1e-4;
+
+    fn assert_near(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < EPSILON, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    // A rotation of angle radians about the y axis.
+    fn yaw(angle: f32) -> [f32; 4] {
+        [0.0, (angle / 2.0).sin(), 0.0, (angle / 2.0).cos()]
+    }
+
+    fn keyframe(time: f64, x: f32, angle: f32) -> MockVRPoseKeyframe {
+        MockVRPoseKeyframe {
+            time,
+            position: [x, 0.0, 0.0],
+            orientation: yaw(angle),
+        }
+    }
+
+    fn stage(time: f64, size_x: f32) -> MockVRStageKeyframe {
+        MockVRStageKeyframe {
+            time,
+            stage_parameters: VRStageParameters {
+                sitting_to_standing_transform: [0.0; 16],
+                size_x,
+                size_z: size_x,
+            },
+        }
+    }
+
+    #[test]
+    fn slerp_halfway() {
+        let half = slerp(yaw(0.0), yaw(1.0), 0.5);
+        assert_near(&half, &yaw(0.5));
+    }
+
+    #[test]
+    fn slerp_takes_the_shortest_path() {
+        // -q is the same rotation as q, so this shouldn't go the long way round.
+        let q = yaw(1.0);
+        let half = slerp(yaw(0.0), [-q[0], -q[1], -q[2], -q[3]], 0.5);
+        assert_near(&half, &yaw(0.5));
+    }
+
+    #[test]
+    fn interpolates_between_keyframes() {
+        let timeline = MockVRTimeline {
+            pose: vec![keyframe(0.0, 0.0, 0.0), keyframe(1000.0, 2.0, 1.0)],
+            ..MockVRTimeline::default()
+        };
+        let animation = MockVRAnimation::new(timeline, 500.0);
+        let pose = animation.pose_at(750.0).unwrap();
+        assert_near(&pose.position.unwrap(), &[0.5, 0.0, 0.0]);
+        assert_near(&pose.orientation.unwrap(), &yaw(0.25));
+        assert_near(&pose.linear_velocity.unwrap(), &[2.0, 0.0, 0.0]);
+        assert_near(&pose.angular_velocity.unwrap(), &[0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn holds_the_end_keyframes() {
+        let timeline = MockVRTimeline {
+            pose: vec![keyframe(100.0, 1.0, 0.0), keyframe(200.0, 2.0, 0.0)],
+            ..MockVRTimeline::default()
+        };
+        let animation = MockVRAnimation::new(timeline, 0.0);
+        assert_near(&animation.pose_at(0.0).unwrap().position.unwrap(), &[1.0, 0.0, 0.0]);
+        assert_near(&animation.pose_at(500.0).unwrap().position.unwrap(), &[2.0, 0.0, 0.0]);
+        assert_near(&animation.pose_at(500.0).unwrap().linear_velocity.unwrap(), &[0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn sorts_keyframes() {
+        let timeline = MockVRTimeline {
+            pose: vec![keyframe(1000.0, 2.0, 0.0), keyframe(0.0, 0.0, 0.0)],
+            ..MockVRTimeline::default()
+        };
+        let animation = MockVRAnimation::new(timeline, 0.0);
+        assert_near(&animation.pose_at(250.0).unwrap().position.unwrap(), &[0.5, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn loops() {
+        let timeline = MockVRTimeline {
+            pose: vec![keyframe(0.0, 0.0, 0.0), keyframe(1000.0, 2.0, 0.0)],
+            looping: true,
+            ..MockVRTimeline::default()
+        };
+        let animation = MockVRAnimation::new(timeline, 0.0);
+        assert_near(&animation.pose_at(2250.0).unwrap().position.unwrap(), &[0.5, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn stage_changes_once_per_keyframe() {
+        let timeline = MockVRTimeline {
+            stage: vec![stage(0.0, 1.0), stage(500.0, 2.0)],
+            ..MockVRTimeline::default()
+        };
+        let mut animation = MockVRAnimation::new(timeline, 0.0);
+        assert_eq!(animation.stage_at(0.0).unwrap().size_x, 1.0);
+        assert!(animation.stage_at(100.0).is_none());
+        assert_eq!(animation.stage_at(600.0).unwrap().size_x, 2.0);
+        assert!(animation.stage_at(700.0).is_none());
+    }
+
+    #[test]
+    fn stage_changes_again_when_looping() {
+        let timeline = MockVRTimeline {
+            stage: vec![stage(0.0, 1.0), stage(500.0, 2.0), stage(1000.0, 3.0)],
+            looping: true,
+            ..MockVRTimeline::default()
+        };
+        let mut animation = MockVRAnimation::new(timeline, 0.0);
+        assert_eq!(animation.stage_at(600.0).unwrap().size_x, 2.0);
+        // One loop later, back on the same keyframe.
+        assert_eq!(animation.stage_at(1600.0).unwrap().size_x, 2.0);
+        assert!(animation.stage_at(1700.0).is_none());
+    }
+
+    #[test]
+    fn stage_does_not_go_back() {
+        let timeline = MockVRTimeline {
+            stage: vec![stage(0.0, 1.0), stage(500.0, 2.0)],
+            ..MockVRTimeline::default()
+        };
+        let mut animation = MockVRAnimation::new(timeline, 0.0);
+        assert_eq!(animation.stage_at(600.0).unwrap().size_x, 2.0);
+        assert!(animation.stage_at(100.0).is_none());
+        assert!(animation.stage_at(600.0).is_none());
+    }
+}
@@ -1,38 +1,111 @@
-use {VRDisplay, VRDisplayData, VRDisplayEvent, VREvent, VRFramebuffer, VRFramebufferAttributes, VRFrameData, VRGamepadPtr, VRStageParameters, VRLayer, VRViewport};
+use {VRDisplay, VRDisplayData, VRDisplayEvent, VREvent, VRFramebuffer, VRFramebufferAttributes, VRFrameData, VRGamepadPtr, VRLayer, VRViewport};
+use {MOCK_VR_FRAME_LOG_LENGTH, MockVRDisplayProfile, MockVRFrameRecord, MockVRFrameTiming, MockVRGamepadInit, MockVRSubmittedLayer};
+use {VRFutureFrameData, VRResolveFrameData, VRTextureKind};
+use {VRGamepad, VRGamepadButton, VRGamepadData, VRGamepadEvent, VRGamepadState};
 use rust_webvr_api::utils;
-use std::sync::{Arc, Mutex};
+use rust_webvr_api::utils::VRClockPtr;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::mpsc::{channel, Receiver, Sender};
 use std::cell::RefCell;
+use std::cmp;
+use std::collections::VecDeque;
 use std::mem;
-pub type MockVRDisplayPtr = Arc<RefCell<MockVRDisplay>>;
-use std::time::Duration;
 use std::thread;
+pub type MockVRDisplayPtr = Arc<RefCell<MockVRDisplay>>;
 use super::MockVRControlMsg;
+use super::animation::MockVRAnimation;
+use super::gamepad::{MockVRGamepad, MockVRGamepadHandle, MockVRGamepadPtr};
+use super::tracking::MockVRTracking;
 
 pub struct MockVRDisplay {
     display_id: u32,
     attributes: VRFramebufferAttributes,
     state: Arc<Mutex<MockVRState>>,
+    gamepads: Vec<MockVRGamepadPtr>,
+    vsync: Sender<MockVRFrameRequest>,
+}
+
+// A request for frame data, resolved on the next simulated vsync: near, far, resolver.
+struct MockVRFrameRequest(f64, f64, VRResolveFrameData);
+
+// The frame most recently handed to the application.
+struct MockVRFrame {
+    timestamp: f64,
+    deadline: f64,
+    submitted: bool,
 }
 
 pub struct MockVRState {
     display_data: VRDisplayData,
     frame_data: VRFrameData,
+    projection_matrices: Option<([f32; 16], [f32; 16])>,
     events: Vec<VREvent>,
+    animation: Option<MockVRAnimation>,
+    tracking: MockVRTracking,
+    gamepads: Vec<MockVRGamepadHandle>,
+    presenting: bool,
+    // False between Deactivate and Activate, e.g. while the headset is taken off.
+    activated: bool,
+    paused: bool,
+    resumed: Arc<Condvar>,
+    last_frame_data: VRFrameData,
+    pending_layer: Option<VRLayer>,
+    frame_log: VecDeque<MockVRFrameRecord>,
+    frame_log_length: usize,
+    clock: VRClockPtr,
+    timing: MockVRFrameTiming,
+    vsync_epoch: f64,
+    last_vsync: Option<u64>,
+    current_frame: Option<MockVRFrame>,
 }
 
 unsafe impl Send for MockVRDisplay {}
 unsafe impl Sync for MockVRDisplay {}
 
 impl MockVRDisplay {
-    pub fn new() -> MockVRDisplayPtr {
+    pub fn new(clock: VRClockPtr) -> MockVRDisplayPtr {
         let display_id = utils::new_id();
+        let state = Arc::new(Mutex::new(MockVRState::new(display_id, clock)));
+        let (vsync, requests) = channel();
+        let vsync_state = state.clone();
+        thread::spawn(move || MockVRDisplay::run_vsync(vsync_state, requests));
         Arc::new(RefCell::new(MockVRDisplay {
             display_id,
             attributes: Default::default(),
-            state: Arc::new(Mutex::new(MockVRState::new(display_id))),
+            state,
+            gamepads: Vec::new(),
+            vsync,
         }))
     }
 
+    // Resolves frame requests on simulated vsyncs, until the display is dropped.
+    // While the display is paused, requests are held until it is resumed.
+    fn run_vsync(state: Arc<Mutex<MockVRState>>, requests: Receiver<MockVRFrameRequest>) {
+        while let Ok(MockVRFrameRequest(near, far, mut resolver)) = requests.recv() {
+            let data = loop {
+                let (clock, vsync) = {
+                    let mut guard = state.lock().unwrap();
+                    let resumed = guard.resumed.clone();
+                    while guard.paused {
+                        guard = resumed.wait(guard).unwrap();
+                    }
+                    let now = guard.clock.now();
+                    (guard.clock.clone(), guard.next_vsync(now))
+                };
+                clock.wait_until(vsync);
+                let mut guard = state.lock().unwrap();
+                if !guard.paused {
+                    break guard.vsync_frame_data(near, far, vsync);
+                }
+            };
+            let _ = resolver.resolve(data);
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.state.lock().unwrap().display_data.connected
+    }
+
     pub fn state_handle(&self) -> Arc<Mutex<MockVRState>> {
         self.state.clone()
     }
@@ -50,11 +123,13 @@ impl VRDisplay for MockVRDisplay {
     }
 
     fn data(&self) -> VRDisplayData {
-        self.state.lock().unwrap().display_data.clone()
+        let mut state = self.state.lock().unwrap();
+        state.animate();
+        state.display_data.clone()
     }
 
-    fn immediate_frame_data(&self, _near_z: f64, _far_z: f64) -> VRFrameData {
-        self.state.lock().unwrap().frame_data.clone()
+    fn immediate_frame_data(&self, near_z: f64, far_z: f64) -> VRFrameData {
+        self.state.lock().unwrap().frame_data(near_z, far_z)
     }
 
     fn synced_frame_data(&self, near_z: f64, far_z: f64) -> VRFrameData {
@@ -65,44 +140,108 @@ impl VRDisplay for MockVRDisplay {
         // No op
     }
 
+    fn future_frame_data(&mut self, near: f64, far: f64) -> VRFutureFrameData {
+        let (resolver, result) = VRFutureFrameData::blocked();
+        let _ = self.vsync.send(MockVRFrameRequest(near, far, resolver));
+        result
+    }
+
     fn sync_poses(&mut self) {
         // Simulate Vsync
-        thread::sleep(Duration::from_millis(1));
+        let (clock, vsync) = {
+            let mut state = self.state.lock().unwrap();
+            // A paused display doesn't start new frames, the last frame data stays current.
+            if state.paused {
+                return;
+            }
+            let now = state.clock.now();
+            (state.clock.clone(), state.next_vsync(now))
+        };
+        clock.wait_until(vsync);
+        self.state.lock().unwrap().begin_frame(vsync);
     }
 
-    fn bind_framebuffer(&mut self, _index: u32) {
-        // No op
+    fn bind_framebuffer(&mut self, index: u32) {
+        self.state.lock().unwrap().log(MockVRFrameRecord::BindFramebuffer(index));
     }
 
     fn get_framebuffers(&self) -> Vec<VRFramebuffer> {
+        // As the mock always has, each eye gets half of its render width, so that
+        // the default Vive profile keeps its 756 pixel wide viewports.
+        let data = self.state.lock().unwrap().display_data.clone();
+        let left = data.left_eye_parameters;
+        let right = data.right_eye_parameters;
         vec![VRFramebuffer {
                 eye_index: 0,
                 attributes: self.attributes,
-                viewport: VRViewport::new(0, 0, 1512/2, 1680)
+                viewport: VRViewport::new(0, 0, left.render_width as i32 / 2, left.render_height as i32)
             },
             VRFramebuffer {
                 eye_index: 1,
                 attributes: self.attributes,
-                viewport: VRViewport::new(1512/2, 0, 1512/2, 1680)
+                viewport: VRViewport::new(left.render_width as i32 / 2, 0,
+                                          right.render_width as i32 / 2, right.render_height as i32)
             }]
     }
 
-    fn render_layer(&mut self, _layer: &VRLayer) {
-        // No op
+    // Layers are only recorded, so any kind of texture will do.
+    fn supported_textures(&self) -> Vec<VRTextureKind> {
+        vec![
+            VRTextureKind::Gl,
+            VRTextureKind::GlExternalOes,
+            VRTextureKind::GlArrayLayer,
+            VRTextureKind::Dmabuf,
+            VRTextureKind::GeckoSurfaceTexture,
+            VRTextureKind::Cpu,
+        ]
+    }
+
+    fn render_layer(&mut self, layer: &VRLayer) {
+        self.state.lock().unwrap().pending_layer = Some(layer.clone());
     }
 
     fn fetch_gamepads(&mut self) -> Result<Vec<VRGamepadPtr>,String> {
-        Ok(Vec::new())
+        let state = self.state.lock().unwrap();
+        // Forget about gamepads which have been removed.
+        self.gamepads.retain(|gamepad| {
+            let id = gamepad.borrow().id();
+            state.gamepads.iter().any(|handle| handle.gamepad_id == id)
+        });
+        for handle in &state.gamepads {
+            let id = handle.gamepad_id;
+            if !self.gamepads.iter().any(|gamepad| gamepad.borrow().id() == id) {
+                self.gamepads.push(MockVRGamepad::new(handle.data.clone(), handle.state.clone()));
+            }
+        }
+        Ok(self.gamepads.iter().map(|gamepad| gamepad.clone() as VRGamepadPtr).collect())
     }
 
     fn submit_frame(&mut self) {
-        // No op
+        let mut state = self.state.lock().unwrap();
+        if let Some(layer) = state.pending_layer.take() {
+            state.check_frame_submission();
+            let submitted = MockVRSubmittedLayer {
+                layer,
+                frame_timestamp: state.last_frame_data.timestamp,
+                attributes: self.attributes,
+            };
+            state.log(MockVRFrameRecord::SubmitLayer(submitted));
+        }
     }
 
     fn start_present(&mut self, attributes: Option<VRFramebufferAttributes>) {
         if let Some(attributes) = attributes {
             self.attributes = attributes;
         }
+        let mut state = self.state.lock().unwrap();
+        state.set_presenting(true);
+        state.log(MockVRFrameRecord::StartPresent(attributes));
+    }
+
+    fn stop_present(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        state.set_presenting(false);
+        state.log(MockVRFrameRecord::StopPresent);
     }
 }
 
@@ -116,100 +255,710 @@ impl MockVRState {
             MockVRControlMsg::SetEyeParameters(left, right) => {
                 self.display_data.left_eye_parameters = left;
                 self.display_data.right_eye_parameters = right;
+                self.update_view_matrices();
                 self.events.push(VREvent::Display(VRDisplayEvent::Change(self.display_data.clone())))
             }
             MockVRControlMsg::SetProjectionMatrices(left, right) => {
-                self.frame_data.left_projection_matrix = left;
-                self.frame_data.right_projection_matrix = right;
+                self.projection_matrices = Some((left, right));
             }
             MockVRControlMsg::SetStageParameters(stage) => {
                 self.display_data.stage_parameters = Some(stage);
                 self.events.push(VREvent::Display(VRDisplayEvent::Change(self.display_data.clone())))
             }
+            MockVRControlMsg::SetDisplayProfile(profile) => {
+                self.set_profile(profile);
+                self.events.push(VREvent::Display(VRDisplayEvent::Change(self.display_data.clone())))
+            }
+            MockVRControlMsg::SetTimeline(timeline) => {
+                self.animation = Some(MockVRAnimation::new(timeline, self.clock.now()));
+                self.animate();
+            }
+            MockVRControlMsg::ClearTimeline => {
+                self.animation = None;
+            }
+            MockVRControlMsg::AddGamepad(index, init) => {
+                self.add_gamepad(index, init);
+            }
+            MockVRControlMsg::RemoveGamepad(index) => {
+                if let Some(i) = self.gamepads.iter().position(|handle| handle.index == index) {
+                    let handle = self.gamepads.remove(i);
+                    handle.state.lock().unwrap().connected = false;
+                    self.events.push(VREvent::Gamepad(VRGamepadEvent::Disconnect(handle.gamepad_id)));
+                }
+            }
+            MockVRControlMsg::SetGamepadPose(index, pose) => {
+                self.update_gamepad(index, |state| state.pose = pose);
+            }
+            MockVRControlMsg::SetGamepadButton(index, button, value) => {
+                self.update_gamepad(index, |state| {
+                    if let Some(b) = state.buttons.get_mut(button) {
+                        *b = value;
+                    }
+                });
+            }
+            MockVRControlMsg::SetGamepadAxis(index, axis, value) => {
+                self.update_gamepad(index, |state| {
+                    if let Some(a) = state.axes.get_mut(axis) {
+                        *a = value;
+                    }
+                });
+            }
+            MockVRControlMsg::GetFrameLog(sender) => {
+                let _ = sender.send(self.frame_log.iter().cloned().collect());
+            }
+            MockVRControlMsg::GetDisplayData(sender) => {
+                let _ = sender.send(self.display_data.clone());
+            }
+            MockVRControlMsg::GetFrameData(sender) => {
+                let _ = sender.send(self.last_frame_data.clone());
+            }
+            MockVRControlMsg::ClearFrameLog => {
+                self.frame_log.clear();
+            }
+            MockVRControlMsg::SetFrameLogLength(length) => {
+                self.frame_log_length = length;
+                let excess = self.frame_log.len().saturating_sub(length);
+                self.frame_log.drain(..excess);
+            }
+            MockVRControlMsg::SetFrameTiming(timing) => {
+                if timing.refresh_rate > 0.0 {
+                    self.timing = timing;
+                    self.vsync_epoch = self.clock.now();
+                    self.last_vsync = None;
+                } else {
+                    warn!("Ignoring mock refresh rate {}", timing.refresh_rate);
+                }
+            }
+            MockVRControlMsg::LoseTracking(loss) => {
+                self.tracking.lose(loss, self.clock.now());
+            }
+            MockVRControlMsg::RestoreTracking => {
+                self.tracking.restore();
+            }
+            MockVRControlMsg::SetTrackingNoise(noise) => {
+                self.tracking.set_noise(noise, self.clock.now());
+            }
             MockVRControlMsg::Focus => {
                 self.events.push(VREvent::Display(VRDisplayEvent::Focus(self.display_data.clone())))
             }
             MockVRControlMsg::Blur => {
                 self.events.push(VREvent::Display(VRDisplayEvent::Blur(self.display_data.clone())))
             }
+            MockVRControlMsg::Connect => {
+                if !self.display_data.connected {
+                    self.display_data.connected = true;
+                    self.events.push(VREvent::Display(VRDisplayEvent::Connect(self.display_data.clone())))
+                }
+            }
+            MockVRControlMsg::Disconnect => {
+                if self.display_data.connected {
+                    // Unplugging the headset ends any presentation on it.
+                    self.set_presenting(false);
+                    self.display_data.connected = false;
+                    self.events.push(VREvent::Display(VRDisplayEvent::Disconnect(self.display_data.display_id)))
+                }
+            }
+            MockVRControlMsg::Activate(reason) => {
+                if !self.activated {
+                    self.activated = true;
+                    self.events.push(VREvent::Display(VRDisplayEvent::Activate(self.display_data.clone(), reason)))
+                }
+            }
+            MockVRControlMsg::Deactivate(reason) => {
+                if self.activated {
+                    // Taking the headset off ends any presentation on it.
+                    self.set_presenting(false);
+                    self.activated = false;
+                    self.events.push(VREvent::Display(VRDisplayEvent::Deactivate(self.display_data.clone(), reason)))
+                }
+            }
+            MockVRControlMsg::Pause => {
+                if !self.paused {
+                    self.paused = true;
+                    self.events.push(VREvent::Display(VRDisplayEvent::Pause(self.display_data.display_id)))
+                }
+            }
+            MockVRControlMsg::Resume => {
+                if self.paused {
+                    self.paused = false;
+                    self.resumed.notify_all();
+                    self.events.push(VREvent::Display(VRDisplayEvent::Resume(self.display_data.display_id)))
+                }
+            }
+            MockVRControlMsg::Exit => {
+                self.set_presenting(false);
+                self.events.push(VREvent::Display(VRDisplayEvent::Exit(self.display_data.display_id)))
+            }
+            MockVRControlMsg::PresentChange(presenting) => {
+                self.set_presenting(presenting);
+            }
+        }
+    }
+
+    // Records a presentation call, forgetting the oldest ones once the log is full.
+    fn log(&mut self, record: MockVRFrameRecord) {
+        if self.frame_log_length == 0 {
+            return;
+        }
+        if self.frame_log.len() >= self.frame_log_length {
+            self.frame_log.pop_front();
+        }
+        self.frame_log.push_back(record);
+    }
+
+    fn set_presenting(&mut self, presenting: bool) {
+        if self.presenting != presenting {
+            self.presenting = presenting;
+            self.events.push(VREvent::Display(VRDisplayEvent::PresentChange(self.display_data.clone(), presenting)))
+        }
+    }
+
+    fn add_gamepad(&mut self, index: u32, init: MockVRGamepadInit) {
+        if self.gamepads.iter().any(|handle| handle.index == index) {
+            warn!("Mock gamepad {} is already connected", index);
+            return;
+        }
+        let gamepad_id = utils::new_id();
+        let data = VRGamepadData {
+            display_id: self.display_data.display_id,
+            name: init.name,
+            hand: init.hand,
+        };
+        let state = VRGamepadState {
+            gamepad_id,
+            connected: true,
+            timestamp: self.clock.now(),
+            axes: vec![0.0; init.axis_count],
+            buttons: vec![VRGamepadButton::new(false); init.button_count],
+            pose: Default::default(),
+        };
+        self.events.push(VREvent::Gamepad(VRGamepadEvent::Connect(data.clone(), state.clone())));
+        self.gamepads.push(MockVRGamepadHandle {
+            index,
+            gamepad_id,
+            data,
+            state: Arc::new(Mutex::new(state)),
+        });
+    }
+
+    fn update_gamepad<F: FnOnce(&mut VRGamepadState)>(&mut self, index: u32, f: F) {
+        let now = self.clock.now();
+        match self.gamepads.iter().find(|handle| handle.index == index) {
+            Some(handle) => {
+                let mut state = handle.state.lock().unwrap();
+                f(&mut state);
+                state.timestamp = now;
+            }
+            None => warn!("No mock gamepad {}", index),
+        }
+    }
+
+    // Picks the vsync at which the next frame will be resolved,
+    // skipping dropped vsyncs and never using the same vsync twice.
+    fn next_vsync(&mut self, now: f64) -> f64 {
+        let period = 1000.0 / self.timing.refresh_rate;
+        let mut index = (f64::max(0.0, now - self.vsync_epoch) / period).floor() as u64 + 1;
+        if let Some(last) = self.last_vsync {
+            index = cmp::max(index, last + 1);
+        }
+        let dropped = &self.timing.dropped_frames;
+        for _ in 0..dropped.len() {
+            if !dropped[(index % dropped.len() as u64) as usize] {
+                break;
+            }
+            index += 1;
+        }
+        self.last_vsync = Some(index);
+        self.vsync_time(index)
+    }
+
+    // The time of a vsync, including its jitter.
+    fn vsync_time(&self, index: u64) -> f64 {
+        let period = 1000.0 / self.timing.refresh_rate;
+        let jitter = &self.timing.jitter;
+        let offset = if jitter.is_empty() { 0.0 } else { jitter[(index % jitter.len() as u64) as usize] };
+        self.vsync_epoch + index as f64 * period + offset
+    }
+
+    fn begin_frame(&mut self, vsync: f64) {
+        self.current_frame = Some(MockVRFrame {
+            timestamp: vsync,
+            deadline: vsync + 1000.0 / self.timing.refresh_rate,
+            submitted: false,
+        });
+    }
+
+    // The frame data for a vsync, with the pose predicted for when the frame is shown.
+    fn vsync_frame_data(&mut self, near: f64, far: f64, vsync: f64) -> VRFrameData {
+        self.begin_frame(vsync);
+        let latency = self.timing.latency;
+        let mut data = self.frame_data_at(near, far, vsync + latency);
+        data.timestamp = vsync;
+        self.last_frame_data = data.clone();
+        data
+    }
+
+    // Logs any problems with submitting a layer for the current frame.
+    fn check_frame_submission(&mut self) {
+        let now = self.clock.now();
+        let (timestamp, double_submit, missed_deadline) = match self.current_frame {
+            Some(ref mut frame) => {
+                let double_submit = frame.submitted;
+                frame.submitted = true;
+                (frame.timestamp, double_submit, now > frame.deadline)
+            }
+            None => return,
+        };
+        if double_submit {
+            self.log(MockVRFrameRecord::DoubleSubmit(timestamp));
+        }
+        if missed_deadline {
+            self.log(MockVRFrameRecord::MissedDeadline(timestamp));
+        }
+    }
+
+    fn animate(&mut self) {
+        let now = self.clock.now();
+        self.animate_at(now);
+    }
+
+    // Moves the pose along the current animation to the given time, if any.
+    // Frame poses are predicted ahead of the clock, so the stage follows the clock
+    // instead, which only moves forward.
+    fn animate_at(&mut self, time: f64) {
+        let now = self.clock.now();
+        let (pose, stage) = match self.animation {
+            Some(ref mut animation) => (animation.pose_at(time), animation.stage_at(now)),
+            None => return,
+        };
+        if let Some(pose) = pose {
+            self.frame_data.pose = pose;
+        }
+        if let Some(stage) = stage {
+            self.display_data.stage_parameters = Some(stage);
+            self.events.push(VREvent::Display(VRDisplayEvent::Change(self.display_data.clone())))
         }
     }
 }
 
 impl MockVRState {
-    pub fn new(display_id: u32) -> Self {
-        let mut display_data = VRDisplayData::default();
-        
-        // Mock display data
+    pub fn new(display_id: u32, clock: VRClockPtr) -> Self {
+        let display_data = VRDisplayData {
+            display_id,
+            connected: true,
+            ..VRDisplayData::default()
+        };
+
+        let mut state = Self {
+            display_data,
+            frame_data: VRFrameData::default(),
+            projection_matrices: None,
+            events: vec![],
+            animation: None,
+            tracking: MockVRTracking::new(),
+            gamepads: vec![],
+            presenting: false,
+            activated: true,
+            paused: false,
+            resumed: Arc::new(Condvar::new()),
+            last_frame_data: VRFrameData::default(),
+            pending_layer: None,
+            frame_log: VecDeque::new(),
+            frame_log_length: MOCK_VR_FRAME_LOG_LENGTH,
+            clock,
+            timing: MockVRFrameTiming::default(),
+            vsync_epoch: 0.0,
+            last_vsync: None,
+            current_frame: None,
+        };
+
+        state.vsync_epoch = state.clock.now();
+
         // Simulates a virtual HTC Vive
+        state.set_profile(MockVRDisplayProfile::htc_vive());
+
+        // Position vector
+        state.frame_data.pose.position = Some([0.5, -0.7, -0.3]);
+        // Orientation quaternion
+        state.frame_data.pose.orientation = Some([0.9385081, -0.08066622, -0.3347714, 0.024972256]);
+
+        state
+    }
+
+    fn set_profile(&mut self, profile: MockVRDisplayProfile) {
+        self.display_data.display_name = profile.display_name;
+        self.display_data.capabilities = profile.capabilities;
+        self.display_data.stage_parameters = profile.stage_parameters;
+        self.display_data.left_eye_parameters = profile.left_eye_parameters;
+        self.display_data.right_eye_parameters = profile.right_eye_parameters;
+        self.projection_matrices = None;
+        self.update_view_matrices();
+    }
+
+    // The view matrices move the world from the head to each eye.
+    // As the mock always has, the depth offset is used as it is, so the default
+    // Vive profile keeps the view matrices it has always reported.
+    fn update_view_matrices(&mut self) {
+        let view_matrix = |offset: [f32; 3]| -> [f32; 16] {
+            [1.0, 0.0, 0.0, 0.0,
+             0.0, 1.0, 0.0, 0.0,
+             0.0, 0.0, 1.0, 0.0,
+             -offset[0], -offset[1], offset[2], 1.0]
+        };
+        self.frame_data.left_view_matrix = view_matrix(self.display_data.left_eye_parameters.offset);
+        self.frame_data.right_view_matrix = view_matrix(self.display_data.right_eye_parameters.offset);
+    }
+
+    fn frame_data(&mut self, near: f64, far: f64) -> VRFrameData {
+        // The pose doesn't move while the display is paused.
+        if self.paused {
+            return self.last_frame_data.clone();
+        }
+        let now = self.clock.now();
+        self.frame_data_at(near, far, now)
+    }
+
+    fn frame_data_at(&mut self, near: f64, far: f64, time: f64) -> VRFrameData {
+        self.animate_at(time);
+        let mut data = self.frame_data.clone();
+        data.timestamp = time;
+
+        // Unless they have been set explicitly, the projections follow the field of view.
+        match self.projection_matrices {
+            Some((left, right)) => {
+                data.left_projection_matrix = left;
+                data.right_projection_matrix = right;
+            }
+            None => {
+                let left_fov = &self.display_data.left_eye_parameters.field_of_view;
+                let right_fov = &self.display_data.right_eye_parameters.field_of_view;
+                data.left_projection_matrix = utils::fov_to_projection_matrix(left_fov, near, far);
+                data.right_projection_matrix = utils::fov_to_projection_matrix(right_fov, near, far);
+            }
+        }
+
+        self.tracking.apply(&mut data.pose, time);
+
+        // A display without positional tracking never reports a position.
+        if !self.display_data.capabilities.has_position {
+            data.pose.position = None;
+            data.pose.linear_velocity = None;
+            data.pose.linear_acceleration = None;
+        }
+
+        self.last_frame_data = data.clone();
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_webvr_api::utils::ManualClock;
+    use std::time::{Duration, Instant};
+    use {MockVRStageKeyframe, MockVRTimeline, VRDisplayEventReason, VRStageParameters};
+    use {VRCpuBuffer, VRDmabuf, VRTexture};
+
+    fn state() -> MockVRState {
+        MockVRState::new(1, Arc::new(ManualClock::new(0.0)))
+    }
+
+    fn present_changes(events: &[VREvent]) -> Vec<bool> {
+        events.iter().filter_map(|event| match *event {
+            VREvent::Display(VRDisplayEvent::PresentChange(_, presenting)) => Some(presenting),
+            _ => None,
+        }).collect()
+    }
+
+    #[test]
+    fn pause_freezes_the_pose() {
+        let mut state = state();
+        state.handle_msg(MockVRControlMsg::SetViewerPose([1.0, 0.0, 0.0], [0.0, 0.0, 0.0, 1.0]));
+        state.frame_data(0.1, 100.0);
+        state.handle_msg(MockVRControlMsg::Pause);
+        state.handle_msg(MockVRControlMsg::SetViewerPose([2.0, 0.0, 0.0], [0.0, 0.0, 0.0, 1.0]));
+        assert_eq!(state.frame_data(0.1, 100.0).pose.position, Some([1.0, 0.0, 0.0]));
+        state.handle_msg(MockVRControlMsg::Resume);
+        assert_eq!(state.frame_data(0.1, 100.0).pose.position, Some([2.0, 0.0, 0.0]));
+    }
 
-        display_data.display_name = "Mock VRDisplay".into();
-        display_data.display_id = display_id;
-        display_data.connected = true;
+    #[test]
+    #[allow(deprecated)]
+    fn paused_display_starts_no_frames() {
+        let display = MockVRDisplay::new(Arc::new(ManualClock::new(0.0)));
+        display.borrow().state_handle().lock().unwrap().handle_msg(MockVRControlMsg::Pause);
+        display.borrow_mut().sync_poses();
+        assert!(display.borrow().state_handle().lock().unwrap().current_frame.is_none());
+    }
+
+    #[test]
+    fn exit_ends_presentation() {
+        let mut state = state();
+        state.set_presenting(true);
+        state.handle_msg(MockVRControlMsg::Exit);
+        assert!(!state.presenting);
+        assert_eq!(present_changes(&state.events), vec![true, false]);
+        match state.events.last() {
+            Some(&VREvent::Display(VRDisplayEvent::Exit(1))) => (),
+            event => panic!("Expected an exit event, got {:?}", event),
+        }
+    }
+
+    #[test]
+    fn start_and_stop_present_change_presentation() {
+        let display = MockVRDisplay::new(Arc::new(ManualClock::new(0.0)));
+        display.borrow_mut().start_present(None);
+        display.borrow_mut().start_present(None);
+        display.borrow_mut().stop_present();
+        assert_eq!(present_changes(&display.borrow().poll_events()), vec![true, false]);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn every_supported_texture_is_submitted() {
+        let display = MockVRDisplay::new(Arc::new(ManualClock::new(0.0)));
+        let dmabuf = VRDmabuf { width: 1, height: 1, fourcc: 0, modifier: 0, planes: vec![] };
+        let textures = vec![
+            VRTexture::Gl(1),
+            VRTexture::GlExternalOes(2),
+            VRTexture::GlArrayLayer { texture_id: 3, layer: 1 },
+            VRTexture::Dmabuf(dmabuf),
+            VRTexture::GeckoSurfaceTexture(4),
+            VRTexture::Cpu(VRCpuBuffer::new(1, 1)),
+        ];
+        for texture in textures {
+            display.borrow_mut().render_layer(&VRLayer { texture, ..VRLayer::default() });
+            display.borrow_mut().submit_frame();
+        }
+        let state = display.borrow().state_handle();
+        let submitted: Vec<_> = state.lock().unwrap().frame_log.iter().filter_map(|record| match *record {
+            MockVRFrameRecord::SubmitLayer(ref submitted) => Some(submitted.layer.texture.kind()),
+            _ => None,
+        }).collect();
+        assert_eq!(submitted, display.borrow().supported_textures());
+    }
+
+    fn timed_display(clock: &ManualClock, timing: MockVRFrameTiming) -> MockVRDisplayPtr {
+        let display = MockVRDisplay::new(Arc::new(clock.clone()));
+        display.borrow().state_handle().lock().unwrap().handle_msg(MockVRControlMsg::SetFrameTiming(timing));
+        display
+    }
+
+    // Requests frame data, and moves the clock on to the vsync which resolves it.
+    fn next_frame(display: &MockVRDisplayPtr, clock: &ManualClock) -> VRFrameData {
+        let state = display.borrow().state_handle();
+        let last_vsync = state.lock().unwrap().last_vsync;
+        let future = display.borrow_mut().future_frame_data(0.1, 100.0);
+        // Wait for the vsync thread to pick its vsync, which it then waits for.
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let vsync = loop {
+            {
+                let state = state.lock().unwrap();
+                if state.last_vsync != last_vsync {
+                    break state.vsync_time(state.last_vsync.unwrap());
+                }
+            }
+            assert!(Instant::now() < deadline, "Timed out waiting for the vsync thread");
+            thread::sleep(Duration::from_millis(1));
+        };
+        clock.set(vsync);
+        future.block()
+    }
+
+    #[allow(deprecated)]
+    fn submit_at(display: &MockVRDisplayPtr, clock: &ManualClock, time: f64) {
+        clock.set(time);
+        display.borrow_mut().render_layer(&VRLayer::default());
+        display.borrow_mut().submit_frame();
+    }
+
+    fn timing_records(display: &MockVRDisplayPtr) -> Vec<String> {
+        let state = display.borrow().state_handle();
+        let state = state.lock().unwrap();
+        state.frame_log.iter().filter_map(|record| match *record {
+            MockVRFrameRecord::MissedDeadline(timestamp) => Some(format!("missed {}", timestamp)),
+            MockVRFrameRecord::DoubleSubmit(timestamp) => Some(format!("double {}", timestamp)),
+            _ => None,
+        }).collect()
+    }
+
+    fn hz(refresh_rate: f64) -> MockVRFrameTiming {
+        MockVRFrameTiming { refresh_rate, ..MockVRFrameTiming::default() }
+    }
+
+    #[test]
+    fn frames_submitted_on_time() {
+        let clock = ManualClock::new(0.0);
+        let display = timed_display(&clock, hz(100.0));
+        assert_eq!(next_frame(&display, &clock).timestamp, 10.0);
+        submit_at(&display, &clock, 15.0);
+        assert_eq!(next_frame(&display, &clock).timestamp, 20.0);
+        submit_at(&display, &clock, 30.0);
+        assert!(timing_records(&display).is_empty());
+    }
+
+    #[test]
+    fn frames_submitted_late() {
+        let clock = ManualClock::new(0.0);
+        let display = timed_display(&clock, hz(100.0));
+        next_frame(&display, &clock);
+        submit_at(&display, &clock, 25.0);
+        // The late submission pushes the next frame on to the following vsync.
+        assert_eq!(next_frame(&display, &clock).timestamp, 30.0);
+        assert_eq!(timing_records(&display), vec!["missed 10"]);
+    }
 
-        display_data.capabilities.can_present = true;
-        display_data.capabilities.has_orientation = true;
-        display_data.capabilities.has_external_display = true;
-        display_data.capabilities.has_position = true;
+    #[test]
+    fn frames_submitted_twice() {
+        let clock = ManualClock::new(0.0);
+        let display = timed_display(&clock, hz(100.0));
+        next_frame(&display, &clock);
+        submit_at(&display, &clock, 12.0);
+        submit_at(&display, &clock, 14.0);
+        assert_eq!(timing_records(&display), vec!["double 10"]);
+    }
 
-        display_data.stage_parameters = Some(VRStageParameters {
-            sitting_to_standing_transform: [-0.9317312, 0.0, 0.36314875, 0.0, 0.0, 0.99999994, 0.0, 0.0, -0.36314875, 
-                                            0.0, -0.9317312, 0.0, 0.23767996, 1.6813644, 0.45370483, 1.0],
-            size_x: 2.0,
-            size_z: 2.0
+    #[test]
+    fn dropped_frames_skip_vsyncs() {
+        let clock = ManualClock::new(0.0);
+        // Two out of every three vsyncs are dropped.
+        let display = timed_display(&clock, MockVRFrameTiming {
+            dropped_frames: vec![false, true, true],
+            ..hz(100.0)
         });
+        let timestamps: Vec<_> = (0..3).map(|_| next_frame(&display, &clock).timestamp).collect();
+        assert_eq!(timestamps, vec![30.0, 60.0, 90.0]);
+    }
 
-        display_data.left_eye_parameters.offset = [0.035949998, 0.0, 0.015];
-        display_data.left_eye_parameters.render_width = 1512;
-        display_data.left_eye_parameters.render_height = 1680;
-        display_data.left_eye_parameters.field_of_view.up_degrees = 55.82093048095703;
-        display_data.left_eye_parameters.field_of_view.right_degrees = 51.26948547363281;
-        display_data.left_eye_parameters.field_of_view.down_degrees = 55.707801818847656;
-        display_data.left_eye_parameters.field_of_view.left_degrees = 54.42263412475586;
-
-        display_data.right_eye_parameters.offset = [-0.035949998, 0.0, 0.015];
-        display_data.right_eye_parameters.render_width = 1512;
-        display_data.right_eye_parameters.render_height = 1680;
-        display_data.right_eye_parameters.field_of_view.up_degrees = 55.898048400878906;
-        display_data.right_eye_parameters.field_of_view.right_degrees = 54.37410354614258;
-        display_data.right_eye_parameters.field_of_view.down_degrees = 55.614715576171875;
-        display_data.right_eye_parameters.field_of_view.left_degrees = 51.304901123046875;
-        
-
-        let mut frame_data = VRFrameData::default();
-        // Position vector
-        frame_data.pose.position = Some([0.5, -0.7, -0.3]);
-        // Orientation quaternion
-        // TODO: Add animation
-        frame_data.pose.orientation = Some([0.9385081, -0.08066622, -0.3347714, 0.024972256]);
+    #[test]
+    fn jitter_moves_vsyncs() {
+        let clock = ManualClock::new(0.0);
+        let display = timed_display(&clock, MockVRFrameTiming {
+            jitter: vec![1.0, -1.0],
+            ..hz(100.0)
+        });
+        let timestamps: Vec<_> = (0..3).map(|_| next_frame(&display, &clock).timestamp).collect();
+        assert_eq!(timestamps, vec![9.0, 21.0, 29.0]);
+    }
 
-        // Simulates HTC Vive projections
-        frame_data.left_projection_matrix = [0.75620246, 0.0, 0.0, 0.0,
-                                       0.0, 0.68050665, 0.0, 0.0,
-                                      -0.05713458, -0.0021225351, -1.0000999, -1.0, 
-                                       0.0, 0.0, -0.10000999, 0.0];
+    #[test]
+    fn default_viewports() {
+        let display = MockVRDisplay::new(Arc::new(ManualClock::new(0.0)));
+        let viewports: Vec<_> = display.borrow().get_framebuffers().iter()
+            .map(|framebuffer| {
+                let viewport = &framebuffer.viewport;
+                (viewport.x, viewport.y, viewport.width, viewport.height)
+            })
+            .collect();
+        assert_eq!(viewports, vec![(0, 0, 756, 1680), (756, 0, 756, 1680)]);
+    }
 
-        frame_data.left_view_matrix = [1.0, 0.0, 0.0, 0.0, 
-                                 0.0, 1.0, 0.0, 0.0, 
-                                 0.0, 0.0, 1.0, 0.0, 
-                                -0.035949998, 0.0, 0.015, 1.0];
+    #[test]
+    fn default_view_matrices() {
+        let mut state = state();
+        let data = state.frame_data(0.1, 100.0);
+        assert_eq!(data.left_view_matrix, [1.0, 0.0, 0.0, 0.0,
+                                           0.0, 1.0, 0.0, 0.0,
+                                           0.0, 0.0, 1.0, 0.0,
+                                           -0.035949998, 0.0, 0.015, 1.0]);
+        assert_eq!(data.right_view_matrix, [1.0, 0.0, 0.0, 0.0,
+                                            0.0, 1.0, 0.0, 0.0,
+                                            0.0, 0.0, 1.0, 0.0,
+                                            0.035949998, 0.0, 0.015, 1.0]);
+    }
 
-        frame_data.right_projection_matrix = [0.75646526, 0.0, 0.0, 0.0, 
-                                        0.0, 0.68069947, 0.0, 0.0, 
-                                        0.055611316, -0.005315368, -1.0000999, -1.0, 
-                                        0.0, 0.0, -0.10000999, 0.0];
+    #[test]
+    fn frame_log_keeps_the_latest_records() {
+        let mut state = state();
+        state.handle_msg(MockVRControlMsg::SetFrameLogLength(2));
+        for index in 0..5 {
+            state.log(MockVRFrameRecord::BindFramebuffer(index));
+        }
+        let (sender, receiver) = channel();
+        state.handle_msg(MockVRControlMsg::GetFrameLog(sender.into()));
+        let indices: Vec<_> = receiver.recv().unwrap().into_iter().map(|record| match record {
+            MockVRFrameRecord::BindFramebuffer(index) => index,
+            record => panic!("Unexpected {:?}", record),
+        }).collect();
+        assert_eq!(indices, vec![3, 4]);
+    }
 
-        frame_data.right_view_matrix = [1.0, 0.0, 0.0, 0.0,
-                                  0.0, 1.0, 0.0, 0.0,
-                                  0.0, 0.0, 1.0, 0.0,
-                                  0.035949998, 0.0, 0.015, 1.0];
+    #[test]
+    fn predicted_frames_do_not_flip_the_stage() {
+        let clock = Arc::new(ManualClock::new(0.0));
+        let mut state = MockVRState::new(1, clock.clone());
+        state.handle_msg(MockVRControlMsg::SetFrameTiming(MockVRFrameTiming {
+            latency: 100.0,
+            ..MockVRFrameTiming::default()
+        }));
+        let stage = |time, size_x| MockVRStageKeyframe {
+            time,
+            stage_parameters: VRStageParameters { sitting_to_standing_transform: [0.0; 16], size_x, size_z: size_x },
+        };
+        state.handle_msg(MockVRControlMsg::SetTimeline(MockVRTimeline {
+            stage: vec![stage(0.0, 1.0), stage(50.0, 2.0)],
+            ..MockVRTimeline::default()
+        }));
+        let sizes = |state: &MockVRState| -> Vec<f32> {
+            state.events.iter().filter_map(|event| match *event {
+                VREvent::Display(VRDisplayEvent::Change(ref data)) => data.stage_parameters.as_ref().map(|stage| stage.size_x),
+                _ => None,
+            }).collect()
+        };
+        // Frames are predicted past the second keyframe before the clock reaches it.
+        for _ in 0..3 {
+            state.vsync_frame_data(0.1, 100.0, 1.0);
+            state.frame_data(0.1, 100.0);
+        }
+        assert_eq!(sizes(&state), vec![1.0]);
+        clock.set(60.0);
+        for _ in 0..3 {
+            state.vsync_frame_data(0.1, 100.0, 61.0);
+            state.frame_data(0.1, 100.0);
+        }
+        assert_eq!(sizes(&state), vec![1.0, 2.0]);
+    }
 
-        frame_data.timestamp = utils::timestamp();
+    #[test]
+    fn deactivate_ends_presentation() {
+        let mut state = state();
+        state.set_presenting(true);
+        state.handle_msg(MockVRControlMsg::Deactivate(VRDisplayEventReason::Unmounted));
+        assert!(!state.activated);
+        assert!(!state.presenting);
+        assert_eq!(present_changes(&state.events), vec![true, false]);
+        match state.events.last() {
+            Some(&VREvent::Display(VRDisplayEvent::Deactivate(_, VRDisplayEventReason::Unmounted))) => (),
+            event => panic!("Expected a deactivate event, got {:?}", event),
+        }
+    }
 
-        Self {
-            display_data,
-            frame_data,
-            events: vec![]
+    #[test]
+    fn activate_follows_deactivate() {
+        let display = MockVRDisplay::new(Arc::new(ManualClock::new(0.0)));
+        let state = display.borrow().state_handle();
+        // The display starts out activated.
+        state.lock().unwrap().handle_msg(MockVRControlMsg::Activate(VRDisplayEventReason::Mounted));
+        assert!(display.borrow().poll_events().is_empty());
+        state.lock().unwrap().handle_msg(MockVRControlMsg::Deactivate(VRDisplayEventReason::Unmounted));
+        state.lock().unwrap().handle_msg(MockVRControlMsg::Deactivate(VRDisplayEventReason::Unmounted));
+        assert!(!state.lock().unwrap().activated);
+        assert_eq!(display.borrow().poll_events().len(), 1);
+        state.lock().unwrap().handle_msg(MockVRControlMsg::Activate(VRDisplayEventReason::Mounted));
+        assert!(state.lock().unwrap().activated);
+        let events = display.borrow().poll_events();
+        match &events[..] {
+            [VREvent::Display(VRDisplayEvent::Activate(_, VRDisplayEventReason::Mounted))] => (),
+            events => panic!("Expected an activate event, got {:?}", events),
         }
     }
+
+    #[test]
+    fn disconnect_ends_presentation() {
+        let mut state = state();
+        state.set_presenting(true);
+        state.handle_msg(MockVRControlMsg::Disconnect);
+        assert!(!state.display_data.connected);
+        assert_eq!(present_changes(&state.events), vec![true, false]);
+    }
 }
@@ -0,0 +1,42 @@
+use {VRGamepad, VRGamepadData, VRGamepadState};
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
+
+pub type MockVRGamepadPtr = Arc<RefCell<MockVRGamepad>>;
+
+pub struct MockVRGamepad {
+    data: VRGamepadData,
+    state: Arc<Mutex<VRGamepadState>>,
+}
+
+impl MockVRGamepad {
+    pub fn new(data: VRGamepadData, state: Arc<Mutex<VRGamepadState>>) -> MockVRGamepadPtr {
+        Arc::new(RefCell::new(MockVRGamepad {
+            data,
+            state,
+        }))
+    }
+}
+
+impl VRGamepad for MockVRGamepad {
+    fn id(&self) -> u32 {
+        self.state.lock().unwrap().gamepad_id
+    }
+
+    fn data(&self) -> VRGamepadData {
+        self.data.clone()
+    }
+
+    fn state(&self) -> VRGamepadState {
+        self.state.lock().unwrap().clone()
+    }
+}
+
+// The part of a mock gamepad which lives in the shared MockVRState,
+// and is updated by control messages.
+pub struct MockVRGamepadHandle {
+    pub index: u32,
+    pub gamepad_id: u32,
+    pub data: VRGamepadData,
+    pub state: Arc<Mutex<VRGamepadState>>,
+}
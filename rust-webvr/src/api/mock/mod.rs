@@ -1,25 +1,100 @@
+mod animation;
 mod display;
+mod gamepad;
 mod service;
+mod tracking;
 
 pub use {VRService, VRServiceCreator, VREyeParameters, VRStageParameters, MockVRControlMsg};
+use rust_webvr_api::utils::{SystemClock, VRClockPtr};
+#[cfg(feature = "ipc")]
+use ipc_channel::ipc::{self, IpcOneShotServer, IpcReceiver, IpcSender};
+use std::sync::Arc;
 use std::sync::mpsc::{channel, Sender};
+#[cfg(feature = "ipc")]
+use std::thread;
 
 pub struct MockServiceCreator;
 
 impl MockServiceCreator {
-    pub fn new() -> Box<VRServiceCreator> {
+    pub fn new() -> Box<dyn VRServiceCreator> {
         Box::new(MockServiceCreator)
     }
 
-    pub fn new_service_with_remote() -> (Box<VRService>, Sender<MockVRControlMsg>) {
+    pub fn new_service_with_remote() -> (Box<dyn VRService>, Sender<MockVRControlMsg>) {
+        MockServiceCreator::new_service_with_clock(Arc::new(SystemClock))
+    }
+
+    // The mock takes all its timestamps and vsync waits from the given clock.
+    pub fn new_service_with_clock(clock: VRClockPtr) -> (Box<dyn VRService>, Sender<MockVRControlMsg>) {
         let (send, rcv) = channel();
-        let service = service::MockVRService::new_with_receiver(rcv);
+        let service = service::MockVRService::new_with_receiver(rcv, clock);
         (Box::new(service), send)
     }
+
+    // Like `new_service_with_remote`, but the remote can be sent to another process.
+    #[cfg(feature = "ipc")]
+    pub fn new_service_with_ipc_remote() -> Result<(Box<dyn VRService>, IpcSender<MockVRControlMsg>), String> {
+        let (send, rcv) = ipc::channel().map_err(|e| e.to_string())?;
+        let (service, remote) = MockServiceCreator::new_service_with_remote();
+        forward_ipc(rcv, remote);
+        Ok((service, send))
+    }
+
+    // Returns the name of a one shot IPC server. Another process can control the mock by
+    // connecting an `IpcSender<MockVRControlMsg>` to it with `IpcSender::connect`.
+    #[cfg(feature = "ipc")]
+    pub fn new_service_with_ipc_server() -> Result<(Box<dyn VRService>, String), String> {
+        let (server, name) = IpcOneShotServer::new().map_err(|e| e.to_string())?;
+        let (service, remote) = MockServiceCreator::new_service_with_remote();
+        thread::spawn(move || {
+            let (rcv, msg) = match server.accept() {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    error!("Failed to accept mock IPC connection: {:?}", e);
+                    return;
+                }
+            };
+            if remote.send(msg).is_ok() {
+                forward_ipc(rcv, remote);
+            }
+        });
+        Ok((service, name))
+    }
+}
+
+// Passes messages from an IPC receiver on to the mock display, until either side goes away.
+#[cfg(feature = "ipc")]
+fn forward_ipc(rcv: IpcReceiver<MockVRControlMsg>, remote: Sender<MockVRControlMsg>) {
+    thread::spawn(move || {
+        while let Ok(msg) = rcv.recv() {
+            if remote.send(msg).is_err() {
+                break;
+            }
+        }
+    });
 }
 
 impl VRServiceCreator for MockServiceCreator {
-     fn new_service(&self) -> Box<VRService> {
+     fn new_service(&self) -> Box<dyn VRService> {
          Box::new(service::MockVRService::new())
      }
 }
+
+#[cfg(all(test, feature = "ipc"))]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn ipc_remote_controls_the_mock() {
+        let (mut service, remote) = MockServiceCreator::new_service_with_ipc_remote().unwrap();
+        assert_eq!(service.fetch_displays().unwrap().len(), 1);
+        remote.send(MockVRControlMsg::Disconnect).unwrap();
+        // The message is forwarded and handled on other threads.
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !service.fetch_displays().unwrap().is_empty() {
+            assert!(Instant::now() < deadline, "Timed out waiting for the display to disconnect");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
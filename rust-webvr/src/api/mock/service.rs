@@ -1,6 +1,8 @@
-use {VRService, VRDisplayPtr, VREvent, VRGamepadPtr};
+use {VRDisplay, VRService, VRDisplayPtr, VREvent, VRGamepadPtr};
 use super::display::{MockVRDisplay, MockVRDisplayPtr};
 use super::MockVRControlMsg;
+use rust_webvr_api::utils::{SystemClock, VRClockPtr};
+use std::sync::Arc;
 use std::thread;
 use std::sync::mpsc::Receiver;
 
@@ -16,11 +18,15 @@ impl VRService for MockVRService {
     }
 
     fn fetch_displays(&mut self) -> Result<Vec<VRDisplayPtr>,String> {
+        // An unplugged headset isn't there to be found.
+        if !self.display.borrow().is_connected() {
+            return Ok(vec![]);
+        }
         Ok(vec![self.display.clone()])
     }
 
     fn fetch_gamepads(&mut self) -> Result<Vec<VRGamepadPtr>,String> {
-        Ok(Vec::new())
+        self.display.borrow_mut().fetch_gamepads()
     }
 
     fn is_available(&self) -> bool {
@@ -35,12 +41,12 @@ impl VRService for MockVRService {
 impl MockVRService {
     pub fn new() -> MockVRService {
         MockVRService {
-            display: MockVRDisplay::new(),
+            display: MockVRDisplay::new(Arc::new(SystemClock)),
         }
     }
 
-    pub fn new_with_receiver(rcv: Receiver<MockVRControlMsg>) -> MockVRService {
-        let display = MockVRDisplay::new();
+    pub fn new_with_receiver(rcv: Receiver<MockVRControlMsg>, clock: VRClockPtr) -> MockVRService {
+        let display = MockVRDisplay::new(clock);
         let state = display.borrow().state_handle();
         thread::spawn(move || {
             while let Ok(msg) = rcv.recv() {
@@ -57,3 +63,104 @@ impl MockVRService {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_webvr_api::utils::ManualClock;
+    use std::sync::mpsc::{channel, Sender};
+    use std::time::{Duration, Instant};
+    use {MockVRGamepadInit, VRGamepadButton, VRGamepadEvent, VRGamepadHand, VRPose};
+
+    // Control messages are handled in order on another thread, so once a reply
+    // comes back all the messages sent before it have been handled.
+    fn sync(send: &Sender<MockVRControlMsg>) {
+        let (reply, replied) = channel();
+        send.send(MockVRControlMsg::GetDisplayData(reply.into())).unwrap();
+        replied.recv().unwrap();
+    }
+
+    fn add_gamepad(send: &Sender<MockVRControlMsg>, index: u32) {
+        send.send(MockVRControlMsg::AddGamepad(index, MockVRGamepadInit {
+            name: String::from("Mock controller"),
+            hand: VRGamepadHand::Left,
+            button_count: 2,
+            axis_count: 1,
+        })).unwrap();
+    }
+
+    #[test]
+    fn gamepads_are_added_and_removed() {
+        let (send, rcv) = channel();
+        let mut service = MockVRService::new_with_receiver(rcv, Arc::new(ManualClock::new(0.0)));
+        add_gamepad(&send, 0);
+        sync(&send);
+
+        let gamepads = service.fetch_gamepads().unwrap();
+        assert_eq!(gamepads.len(), 1);
+        let gamepad = gamepads[0].clone();
+        let id = gamepad.borrow().id();
+        assert_eq!(gamepad.borrow().data().name, "Mock controller");
+        let state = gamepad.borrow().state();
+        assert!(state.connected);
+        assert_eq!((state.buttons.len(), state.axes.len()), (2, 1));
+        match &service.poll_events()[..] {
+            [VREvent::Gamepad(VRGamepadEvent::Connect(data, state))] => {
+                assert_eq!(data.name, "Mock controller");
+                assert_eq!(state.gamepad_id, id);
+            }
+            events => panic!("Expected a gamepad connect event, got {:?}", events),
+        }
+
+        send.send(MockVRControlMsg::RemoveGamepad(0)).unwrap();
+        sync(&send);
+        assert!(service.fetch_gamepads().unwrap().is_empty());
+        assert!(!gamepad.borrow().state().connected);
+        match &service.poll_events()[..] {
+            [VREvent::Gamepad(VRGamepadEvent::Disconnect(gamepad_id))] => assert_eq!(*gamepad_id, id),
+            events => panic!("Expected a gamepad disconnect event, got {:?}", events),
+        }
+    }
+
+    #[test]
+    fn gamepads_follow_control_messages() {
+        let (send, rcv) = channel();
+        let clock = Arc::new(ManualClock::new(0.0));
+        let mut service = MockVRService::new_with_receiver(rcv, clock.clone());
+        add_gamepad(&send, 0);
+        sync(&send);
+        let gamepad = service.fetch_gamepads().unwrap()[0].clone();
+
+        clock.set(5.0);
+        let pose = VRPose { position: Some([1.0, 2.0, 3.0]), ..VRPose::default() };
+        send.send(MockVRControlMsg::SetGamepadButton(0, 1, VRGamepadButton { pressed: true, touched: true })).unwrap();
+        send.send(MockVRControlMsg::SetGamepadAxis(0, 0, 0.5)).unwrap();
+        send.send(MockVRControlMsg::SetGamepadPose(0, pose)).unwrap();
+        // Buttons and axes which the gamepad doesn't have are ignored.
+        send.send(MockVRControlMsg::SetGamepadButton(0, 2, VRGamepadButton::new(true))).unwrap();
+        send.send(MockVRControlMsg::SetGamepadAxis(0, 1, 1.0)).unwrap();
+        sync(&send);
+
+        let state = gamepad.borrow().state();
+        let buttons: Vec<_> = state.buttons.iter().map(|button| (button.pressed, button.touched)).collect();
+        assert_eq!(buttons, vec![(false, false), (true, true)]);
+        assert_eq!(state.axes, vec![0.5]);
+        assert_eq!(state.pose.position, Some([1.0, 2.0, 3.0]));
+        assert_eq!(state.timestamp, 5.0);
+    }
+
+    #[test]
+    fn disconnected_display_is_not_fetched() {
+        let (send, rcv) = channel();
+        let mut service = MockVRService::new_with_receiver(rcv, Arc::new(ManualClock::new(0.0)));
+        assert_eq!(service.fetch_displays().unwrap().len(), 1);
+        send.send(MockVRControlMsg::Disconnect).unwrap();
+        // Control messages are handled on another thread.
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while service.display.borrow().is_connected() {
+            assert!(Instant::now() < deadline, "Timed out waiting for the display to disconnect");
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(service.fetch_displays().unwrap().is_empty());
+    }
+}
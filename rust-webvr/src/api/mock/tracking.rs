@@ -0,0 +1,187 @@
+use {MockVRTrackingLoss, MockVRTrackingNoise, VRPose};
+use super::animation::{multiply, normalize};
+
+// Degrades the mock display pose: tracking loss, noise and drift.
+pub struct MockVRTracking {
+    loss: Option<MockVRTrackingLoss>,
+    loss_start: f64,
+    noise: MockVRTrackingNoise,
+    noise_start: f64,
+    rng: u64,
+}
+
+impl MockVRTracking {
+    pub fn new() -> MockVRTracking {
+        MockVRTracking {
+            loss: None,
+            loss_start: 0.0,
+            noise: MockVRTrackingNoise::default(),
+            noise_start: 0.0,
+            rng: 1,
+        }
+    }
+
+    pub fn lose(&mut self, loss: MockVRTrackingLoss, now: f64) {
+        self.loss = Some(loss);
+        self.loss_start = now;
+    }
+
+    pub fn restore(&mut self) {
+        self.loss = None;
+    }
+
+    pub fn set_noise(&mut self, noise: MockVRTrackingNoise, now: f64) {
+        // xorshift gets stuck at zero, so avoid that seed.
+        self.rng = if noise.seed == 0 { 1 } else { noise.seed };
+        self.noise = noise;
+        self.noise_start = now;
+    }
+
+    pub fn apply(&mut self, pose: &mut VRPose, time: f64) {
+        let elapsed = (f64::max(0.0, time - self.noise_start) / 1000.0) as f32;
+
+        if let Some(position) = pose.position.as_mut() {
+            let drift = self.noise.position_drift;
+            let amount = self.noise.position;
+            for i in 0..3 {
+                position[i] += drift[i] * elapsed + amount * self.random();
+            }
+        }
+
+        if let Some(orientation) = pose.orientation.as_mut() {
+            let amount = self.noise.orientation;
+            // Small angle approximation of a random rotation.
+            let noise = normalize([
+                amount * self.random() / 2.0,
+                amount * self.random() / 2.0,
+                amount * self.random() / 2.0,
+                1.0,
+            ]);
+            let half_yaw = self.noise.yaw_drift * elapsed / 2.0;
+            let drift = [0.0, half_yaw.sin(), 0.0, half_yaw.cos()];
+            *orientation = normalize(multiply(drift, multiply(*orientation, noise)));
+        }
+
+        let lost = match self.loss {
+            Some(ref loss) => match loss.duration {
+                Some(duration) if time >= self.loss_start + duration => None,
+                _ => Some((loss.position, loss.orientation)),
+            },
+            None => None,
+        };
+        match lost {
+            Some((position, orientation)) => {
+                if position {
+                    pose.position = None;
+                    pose.linear_velocity = None;
+                    pose.linear_acceleration = None;
+                }
+                if orientation {
+                    pose.orientation = None;
+                    pose.angular_velocity = None;
+                    pose.angular_acceleration = None;
+                }
+            }
+            None => self.loss = None,
+        }
+    }
+
+    // A xorshift64* random number in [-1, 1].
+    fn random(&mut self) -> f32 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        let value = self.rng.wrapping_mul(0x2545F4914F6CDD1D);
+        ((value >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pose() -> VRPose {
+        VRPose {
+            position: Some([1.0, 2.0, 3.0]),
+            orientation: Some([0.0, 0.0, 0.0, 1.0]),
+            ..VRPose::default()
+        }
+    }
+
+    fn pose_at(tracking: &mut MockVRTracking, time: f64) -> VRPose {
+        let mut pose = pose();
+        tracking.apply(&mut pose, time);
+        pose
+    }
+
+    #[test]
+    fn loss_lasts_for_its_duration() {
+        let mut tracking = MockVRTracking::new();
+        tracking.lose(MockVRTrackingLoss { position: true, orientation: false, duration: Some(100.0) }, 1000.0);
+        let lost = pose_at(&mut tracking, 1000.0);
+        assert_eq!(lost.position, None);
+        assert_eq!(lost.orientation, Some([0.0, 0.0, 0.0, 1.0]));
+        assert_eq!(pose_at(&mut tracking, 1099.0).position, None);
+        assert_eq!(pose_at(&mut tracking, 1100.0).position, Some([1.0, 2.0, 3.0]));
+        // Once restored, tracking stays restored.
+        assert_eq!(pose_at(&mut tracking, 1050.0).position, Some([1.0, 2.0, 3.0]));
+    }
+
+    #[test]
+    fn loss_without_a_duration_lasts_until_restored() {
+        let mut tracking = MockVRTracking::new();
+        tracking.lose(MockVRTrackingLoss { position: true, orientation: true, duration: None }, 0.0);
+        let lost = pose_at(&mut tracking, 1e9);
+        assert_eq!((lost.position, lost.orientation), (None, None));
+        tracking.restore();
+        assert_eq!(pose_at(&mut tracking, 1e9).orientation, Some([0.0, 0.0, 0.0, 1.0]));
+    }
+
+    #[test]
+    fn noise_is_seeded_and_bounded() {
+        let noise = MockVRTrackingNoise { position: 0.01, orientation: 0.02, seed: 42, ..MockVRTrackingNoise::default() };
+        let poses = |noise: &MockVRTrackingNoise| -> Vec<VRPose> {
+            let mut tracking = MockVRTracking::new();
+            tracking.set_noise(noise.clone(), 0.0);
+            (0..100).map(|i| pose_at(&mut tracking, i as f64)).collect()
+        };
+        let first = poses(&noise);
+        let second = poses(&noise);
+        for (a, b) in first.iter().zip(&second) {
+            assert_eq!(a.position, b.position);
+            assert_eq!(a.orientation, b.orientation);
+        }
+        assert!(first.iter().any(|pose| pose.position != Some([1.0, 2.0, 3.0])));
+        for pose in &first {
+            let position = pose.position.unwrap();
+            for (actual, expected) in position.iter().zip(&[1.0, 2.0, 3.0]) {
+                assert!((actual - expected).abs() <= 0.01 + 1e-6, "{:?}", position);
+            }
+            // Each half angle is at most half the amplitude.
+            let orientation = pose.orientation.unwrap();
+            for component in &orientation[..3] {
+                assert!(component.abs() <= 0.01 + 1e-6, "{:?}", orientation);
+            }
+        }
+        let other = poses(&MockVRTrackingNoise { seed: 43, ..noise });
+        assert!(first.iter().zip(&other).any(|(a, b)| a.position != b.position));
+    }
+
+    #[test]
+    fn drift_grows_linearly() {
+        let mut tracking = MockVRTracking::new();
+        tracking.set_noise(MockVRTrackingNoise {
+            position_drift: [0.1, 0.0, -0.2],
+            yaw_drift: 0.5,
+            ..MockVRTrackingNoise::default()
+        }, 1000.0);
+        for &seconds in &[0.0f32, 1.0, 2.0, 4.0] {
+            let pose = pose_at(&mut tracking, 1000.0 + seconds as f64 * 1000.0);
+            let position = pose.position.unwrap();
+            assert!((position[0] - (1.0 + 0.1 * seconds)).abs() < 1e-5, "{:?}", position);
+            assert!((position[2] - (3.0 - 0.2 * seconds)).abs() < 1e-5, "{:?}", position);
+            let yaw = 2.0 * pose.orientation.unwrap()[1].atan2(pose.orientation.unwrap()[3]);
+            assert!((yaw - 0.5 * seconds).abs() < 1e-5, "{} after {}s", yaw, seconds);
+        }
+    }
+}
@@ -2,8 +2,8 @@
 mod vrexternal;
 #[cfg(feature = "vrexternal")]
 pub use self::vrexternal::VRExternalShmemPtr;
-#[cfg(all(feature = "vrexternal", target_os= "android"))]
-pub use self::vrexternal::VRExternalServiceCreator;
+#[cfg(all(feature = "vrexternal", any(target_os= "android", target_os = "linux")))]
+pub use self::vrexternal::{mozgfx, VRExternalServiceCreator, VRExternalTimeouts};
 
 #[cfg(feature = "mock")]
 mod mock;
@@ -13,7 +13,17 @@ pub use self::mock::{MockServiceCreator, MockVRControlMsg};
 #[cfg(feature = "glwindow")]
 mod glwindow;
 #[cfg(feature = "glwindow")]
-pub use self::glwindow::GlWindowVRService;
+pub use self::glwindow::{EventsLoopFactory, GlWindowVRAction, GlWindowVRBindings, GlWindowVRMainThreadHeartbeat, GlWindowVRPreview, GlWindowVRService, GlWindowVRServiceBuilder};
+
+#[cfg(feature = "headless")]
+mod headless;
+#[cfg(feature = "headless")]
+pub use self::headless::{HeadlessVRCapture, HeadlessVRFrame, HeadlessVRService};
+
+#[cfg(all(feature = "dmabuf", target_os = "linux"))]
+mod dmabuf;
+#[cfg(all(feature = "dmabuf", target_os = "linux"))]
+pub use self::dmabuf::{DmabufVRServer, DmabufVRService};
 
 #[cfg(feature = "magicleap")]
 mod magicleap;
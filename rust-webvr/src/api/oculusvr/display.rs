@@ -2,7 +2,8 @@
 #![cfg(feature = "oculusvr")]
 
 use {VRDisplay, VRDisplayData, VRDisplayCapabilities, VREvent, VRDisplayEvent, 
-    VREyeParameters, VRFramebuffer, VRFramebufferAttributes, VRFrameData, VRGamepadPtr, VRLayer, VRViewport};
+    VREyeParameters, VRFramebuffer, VRFramebufferAttributes, VRFrameData, VRGamepadPtr, VRLayer, VRTexture,
+    VRViewport};
 use android_injected_glue::ffi as ndk;
 use gl;
 use egl;
@@ -174,6 +175,14 @@ impl VRDisplay for OculusVRDisplay {
             return;
         }
 
+        let texture_id = match layer.texture {
+            VRTexture::Gl(texture_id) => texture_id,
+            ref texture => {
+                error!("OculusVR can't present {:?} textures", texture.kind());
+                return;
+            }
+        };
+
         // Save current fbo to restore it when the frame is submitted.
         let mut current_fbo = 0;
         unsafe {
@@ -183,16 +192,16 @@ impl VRDisplay for OculusVRDisplay {
         for (i, eye) in self.eye_framebuffers.iter_mut().enumerate() {
             let swap_chain_index = (self.frame_index % eye.swap_chain_length as i64) as i32;
 
-            if self.read_texture != layer.texture_id {
+            if self.read_texture != texture_id {
                 // Attach external texture to the used later in BlitFramebuffer.
                 unsafe {
                     gl::BindFramebuffer(gl::FRAMEBUFFER, self.read_fbo);
                     gl::FramebufferTexture2D(gl::FRAMEBUFFER,
                                              gl::COLOR_ATTACHMENT0,
                                              gl::TEXTURE_2D,
-                                             layer.texture_id, 0);
+                                             texture_id, 0);
                 }
-                self.read_texture = layer.texture_id;
+                self.read_texture = texture_id;
             }
 
             let texture_size = layer.texture_size.unwrap_or_else(|| {
@@ -1,5 +1,5 @@
 use {VRDisplay, VRDisplayData, VRDisplayCapabilities, VREyeParameters, VRFrameData};
-use {VRFramebuffer, VRPose, VRStageParameters, VRFieldOfView, VRGamepadPtr, VRLayer};
+use {VRFramebuffer, VRPose, VRStageParameters, VRFieldOfView, VRGamepadPtr, VRLayer, VRTexture};
 use super::binding as openvr;
 use super::binding::ETrackedPropertyError::*;
 use super::binding::ETrackedDeviceProperty::*;
@@ -156,7 +156,14 @@ impl VRDisplay for OpenVRDisplay {
     }
 
     fn render_layer(&mut self, layer: &VRLayer) {
-        self.frame_texture.handle = unsafe { mem::transmute(layer.texture_id as usize) };
+        let texture_id = match layer.texture {
+            VRTexture::Gl(texture_id) => texture_id,
+            ref texture => {
+                error!("OpenVR can't present {:?} textures", texture.kind());
+                return;
+            }
+        };
+        self.frame_texture.handle = unsafe { mem::transmute(texture_id as usize) };
         self.left_bounds = texture_bounds_to_openvr(&layer.left_bounds);
         self.right_bounds = texture_bounds_to_openvr(&layer.right_bounds);
     }
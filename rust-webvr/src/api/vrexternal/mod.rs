@@ -1,9 +1,9 @@
-#[cfg(target_os = "android")]
-mod android;
-#[cfg(target_os = "android")]
-pub use self::android::*;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+mod shmem;
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub use self::shmem::*;
 
-#[cfg(not(target_os = "android"))]
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
 mod other;
-#[cfg(not(target_os = "android"))]
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
 pub use self::other::*;
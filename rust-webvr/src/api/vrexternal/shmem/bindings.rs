@@ -0,0 +1,318 @@
+// The types of cpp/moz_external_vr.h, written out by hand in the form bindgen generates
+// on Android. They use the Android layout of VRExternalShmem, where the state is guarded
+// by pthread mutexes and condvars, since glibc supports those in shared memory as well.
+//
+// This diverges from the header on desktop Linux, which has no mutexes and guards the
+// state with generation counters instead. A Linux VR runtime has to be built from the
+// header with __ANDROID__ defined to share memory with us. The layout tests below check
+// the types against the header built that way (g++ -D__ANDROID__ on x86_64 Linux),
+// which matches 64-bit Android as well.
+
+use std::mem;
+use std::os::raw::c_char;
+
+pub type VRLayerTextureHandle = u64;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct pthread_mutex_t(libc::pthread_mutex_t);
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct pthread_cond_t(libc::pthread_cond_t);
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Point3D_POD {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct IntSize_POD {
+    pub width: i32,
+    pub height: i32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct FloatSize_POD {
+    pub width: f32,
+    pub height: f32,
+}
+
+pub const ControllerHand__empty: ControllerHand = 0;
+pub const ControllerHand_Left: ControllerHand = 1;
+pub const ControllerHand_Right: ControllerHand = 2;
+pub const ControllerHand_EndGuard_: ControllerHand = 3;
+pub type ControllerHand = u8;
+
+pub const ControllerCapabilityFlags_Cap_None: ControllerCapabilityFlags = 0;
+pub const ControllerCapabilityFlags_Cap_Position: ControllerCapabilityFlags = 2;
+pub const ControllerCapabilityFlags_Cap_Orientation: ControllerCapabilityFlags = 4;
+pub const ControllerCapabilityFlags_Cap_AngularAcceleration: ControllerCapabilityFlags = 8;
+pub const ControllerCapabilityFlags_Cap_LinearAcceleration: ControllerCapabilityFlags = 16;
+pub const ControllerCapabilityFlags_Cap_All: ControllerCapabilityFlags = 31;
+pub type ControllerCapabilityFlags = u16;
+
+pub const VRDisplayCapabilityFlags_Cap_None: VRDisplayCapabilityFlags = 0;
+pub const VRDisplayCapabilityFlags_Cap_Position: VRDisplayCapabilityFlags = 2;
+pub const VRDisplayCapabilityFlags_Cap_Orientation: VRDisplayCapabilityFlags = 4;
+pub const VRDisplayCapabilityFlags_Cap_Present: VRDisplayCapabilityFlags = 8;
+pub const VRDisplayCapabilityFlags_Cap_External: VRDisplayCapabilityFlags = 16;
+pub const VRDisplayCapabilityFlags_Cap_AngularAcceleration: VRDisplayCapabilityFlags = 32;
+pub const VRDisplayCapabilityFlags_Cap_LinearAcceleration: VRDisplayCapabilityFlags = 64;
+pub const VRDisplayCapabilityFlags_Cap_StageParameters: VRDisplayCapabilityFlags = 128;
+pub const VRDisplayCapabilityFlags_Cap_MountDetection: VRDisplayCapabilityFlags = 256;
+pub const VRDisplayCapabilityFlags_Cap_All: VRDisplayCapabilityFlags = 511;
+pub type VRDisplayCapabilityFlags = u16;
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct VRPose {
+    pub orientation: [f32; 4],
+    pub position: [f32; 3],
+    pub angularVelocity: [f32; 3],
+    pub angularAcceleration: [f32; 3],
+    pub linearVelocity: [f32; 3],
+    pub linearAcceleration: [f32; 3],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct VRHMDSensorState {
+    pub inputFrameID: u64,
+    pub timestamp: f64,
+    pub flags: VRDisplayCapabilityFlags,
+    pub pose: VRPose,
+    pub leftViewMatrix: [f32; 16],
+    pub rightViewMatrix: [f32; 16],
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct VRFieldOfView {
+    pub upDegrees: f64,
+    pub rightDegrees: f64,
+    pub downDegrees: f64,
+    pub leftDegrees: f64,
+}
+
+pub const VRDisplayState_Eye_Eye_Left: VRDisplayState_Eye = 0;
+pub const VRDisplayState_Eye_Eye_Right: VRDisplayState_Eye = 1;
+pub const VRDisplayState_Eye_NumEyes: VRDisplayState_Eye = 2;
+pub type VRDisplayState_Eye = u32;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct VRDisplayState {
+    pub shutdown: bool,
+    pub minRestartInterval: u32,
+    pub displayName: [c_char; 256usize],
+    pub eightCC: u64,
+    pub capabilityFlags: VRDisplayCapabilityFlags,
+    pub eyeFOV: [VRFieldOfView; 2usize],
+    pub eyeTranslation: [Point3D_POD; 2usize],
+    pub eyeResolution: IntSize_POD,
+    pub suppressFrames: bool,
+    pub isConnected: bool,
+    pub isMounted: bool,
+    pub stageSize: FloatSize_POD,
+    pub sittingToStandingTransform: [f32; 16usize],
+    pub lastSubmittedFrameId: u64,
+    pub lastSubmittedFrameSuccessful: bool,
+    pub presentingGeneration: u32,
+    pub reportsDroppedFrames: bool,
+    pub droppedFrameCount: u64,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct VRControllerState {
+    pub controllerName: [c_char; 256usize],
+    pub hand: ControllerHand,
+    pub numButtons: u32,
+    pub numAxes: u32,
+    pub numHaptics: u32,
+    pub buttonPressed: u64,
+    pub buttonTouched: u64,
+    pub triggerValue: [f32; 64usize],
+    pub axisValue: [f32; 16usize],
+    pub flags: ControllerCapabilityFlags,
+    pub pose: VRPose,
+    pub isPositionValid: bool,
+    pub isOrientationValid: bool,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct VRLayerEyeRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+pub const VRLayerType_LayerType_None: VRLayerType = 0;
+pub const VRLayerType_LayerType_2D_Content: VRLayerType = 1;
+pub const VRLayerType_LayerType_Stereo_Immersive: VRLayerType = 2;
+pub type VRLayerType = u16;
+
+pub const VRLayerTextureType_LayerTextureType_None: VRLayerTextureType = 0;
+pub const VRLayerTextureType_LayerTextureType_D3D10SurfaceDescriptor: VRLayerTextureType = 1;
+pub const VRLayerTextureType_LayerTextureType_MacIOSurface: VRLayerTextureType = 2;
+pub const VRLayerTextureType_LayerTextureType_GeckoSurfaceTexture: VRLayerTextureType = 3;
+pub type VRLayerTextureType = u16;
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct VRLayer_2D_Content {
+    pub textureHandle: VRLayerTextureHandle,
+    pub textureType: VRLayerTextureType,
+    pub frameId: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct VRLayer_Stereo_Immersive {
+    pub textureHandle: VRLayerTextureHandle,
+    pub textureType: VRLayerTextureType,
+    pub frameId: u64,
+    pub inputFrameId: u64,
+    pub leftEyeRect: VRLayerEyeRect,
+    pub rightEyeRect: VRLayerEyeRect,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct VRLayerState {
+    pub type_: VRLayerType,
+    pub __bindgen_anon_1: VRLayerState__bindgen_ty_1,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub union VRLayerState__bindgen_ty_1 {
+    pub layer_2d_content: VRLayer_2D_Content,
+    pub layer_stereo_immersive: VRLayer_Stereo_Immersive,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct VRHapticState {
+    pub inputFrameID: u64,
+    pub controllerIndex: u32,
+    pub hapticIndex: u32,
+    pub pulseStart: f32,
+    pub pulseDuration: f32,
+    pub pulseIntensity: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct VRBrowserState {
+    pub shutdown: bool,
+    pub presentationActive: bool,
+    pub navigationTransitionActive: bool,
+    pub layerState: [VRLayerState; 8usize],
+    pub hapticState: [VRHapticState; 32usize],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct VRSystemState {
+    pub enumerationCompleted: bool,
+    pub displayState: VRDisplayState,
+    pub sensorState: VRHMDSensorState,
+    pub controllerState: [VRControllerState; 16usize],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct VRExternalShmem {
+    pub version: i32,
+    pub size: i32,
+    pub systemMutex: pthread_mutex_t,
+    pub geckoMutex: pthread_mutex_t,
+    pub servoMutex: pthread_mutex_t,
+    pub systemCond: pthread_cond_t,
+    pub geckoCond: pthread_cond_t,
+    pub servoCond: pthread_cond_t,
+    pub state: VRSystemState,
+    pub geckoState: VRBrowserState,
+    pub servoState: VRBrowserState,
+}
+
+// Like bindgen, types with large arrays or unions default to all zeroes.
+macro_rules! impl_zeroed_default {
+    ($($name:ident),*) => {
+        $(
+            impl Default for $name {
+                fn default() -> $name {
+                    unsafe { mem::zeroed() }
+                }
+            }
+        )*
+    }
+}
+
+impl_zeroed_default!(
+    pthread_mutex_t,
+    pthread_cond_t,
+    VRHMDSensorState,
+    VRDisplayState,
+    VRControllerState,
+    VRLayerState,
+    VRLayerState__bindgen_ty_1,
+    VRBrowserState,
+    VRSystemState,
+    VRExternalShmem
+);
+
+#[cfg(all(test, target_pointer_width = "64"))]
+mod layout_tests {
+    use std::mem::{offset_of, size_of};
+    use super::*;
+
+    macro_rules! assert_layout {
+        ($name:ident, $size:expr, { $($field:ident: $offset:expr),* }) => {
+            assert_eq!(size_of::<$name>(), $size, "Size of {}", stringify!($name));
+            $(
+                assert_eq!(offset_of!($name, $field), $offset, "Offset of {}::{}", stringify!($name), stringify!($field));
+            )*
+        }
+    }
+
+    #[test]
+    fn layouts_match_the_header() {
+        assert_layout!(pthread_mutex_t, 40, {});
+        assert_layout!(pthread_cond_t, 48, {});
+        assert_layout!(VRPose, 76, {});
+        assert_layout!(VRHMDSensorState, 224, { pose: 20, leftViewMatrix: 96 });
+        assert_layout!(VRFieldOfView, 32, {});
+        assert_layout!(VRDisplayState, 488, {
+            eightCC: 264,
+            eyeFOV: 280,
+            stageSize: 380,
+            lastSubmittedFrameId: 456,
+            droppedFrameCount: 480
+        });
+        assert_layout!(VRControllerState, 696, { hand: 256, buttonPressed: 272, pose: 612, isOrientationValid: 689 });
+        assert_layout!(VRLayerEyeRect, 16, {});
+        assert_layout!(VRLayer_2D_Content, 24, {});
+        assert_layout!(VRLayer_Stereo_Immersive, 64, { frameId: 16, leftEyeRect: 32 });
+        assert_layout!(VRLayerState, 72, {});
+        assert_layout!(VRHapticState, 32, {});
+        assert_layout!(VRBrowserState, 1608, { layerState: 8, hapticState: 584 });
+        assert_layout!(VRSystemState, 11856, { displayState: 8, sensorState: 496, controllerState: 720 });
+        assert_layout!(VRExternalShmem, 15344, {
+            systemMutex: 8,
+            systemCond: 128,
+            state: 272,
+            geckoState: 12128,
+            servoState: 13736
+        });
+    }
+}
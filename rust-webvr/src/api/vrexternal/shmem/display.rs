@@ -0,0 +1,472 @@
+use super::{mozgfx, VRExternalShmemPtr};
+use super::gamepad::{self, VRExternalGamepad, VRExternalGamepadPtr};
+use rust_webvr_api::utils;
+use std::cell::RefCell;
+use std::mem;
+use std::sync::Arc;
+use std::time::Duration;
+use {
+    VRDisplay, VRDisplayData, VRDisplayEvent, VREvent, VRFrameData, VRFramebuffer,
+    VRFramebufferAttributes, VRGamepad, VRGamepadEvent, VRGamepadPtr, VRLayer, VRTexture,
+    VRTextureKind, VRViewport,
+};
+
+pub type VRExternalDisplayPtr = Arc<RefCell<VRExternalDisplay>>;
+
+pub struct VRExternalDisplay {
+    system_state: mozgfx::VRSystemState,
+    browser_state: mozgfx::VRBrowserState,
+    rendered_layer: Option<VRLayer>,
+    shmem: VRExternalShmemPtr,
+    display_id: u32,
+    attributes: VRFramebufferAttributes,
+    presenting: bool,
+    gamepads: Vec<VRExternalGamepadPtr>,
+    events: Vec<VREvent>,
+    frame_timeout: Option<Duration>,
+    // False once the runtime has failed to provide a frame in time.
+    connected: bool,
+    synced_frame_id: Option<u64>,
+}
+
+unsafe impl Send for VRExternalDisplay {}
+unsafe impl Sync for VRExternalDisplay {}
+
+impl VRExternalDisplay {
+    pub fn new(shmem: VRExternalShmemPtr, frame_timeout: Option<Duration>) -> Result<VRExternalDisplayPtr, String> {
+        let system_state = shmem.pull_system(&|_| true, frame_timeout)?;
+        let browser_state = shmem.pull_browser()?;
+        let mut display = VRExternalDisplay {
+            system_state,
+            browser_state,
+            rendered_layer: None,
+            shmem,
+            display_id: utils::new_id(),
+            attributes: Default::default(),
+            presenting: false,
+            gamepads: Vec::new(),
+            events: Vec::new(),
+            frame_timeout,
+            connected: true,
+            synced_frame_id: None,
+        };
+        display.update_gamepads();
+        Ok(Arc::new(RefCell::new(display)))
+    }
+
+    pub fn poll_events(&mut self) -> Vec<VREvent> {
+        // While presenting the state is pulled every frame by sync_poses,
+        // otherwise pull it here so that controllers keep up to date.
+        // This only waits for the mutex, which a stuck runtime may be holding.
+        if !self.presenting {
+            match self.shmem.pull_system(&|_| true, self.frame_timeout) {
+                Ok(state) => self.update_system_state(state),
+                Err(err) => self.disconnect(err),
+            }
+        }
+        mem::take(&mut self.events)
+    }
+}
+
+impl VRExternalDisplay {
+    fn push_browser(&mut self) {
+        if let Err(err) = self.shmem.push_browser(self.browser_state) {
+            self.disconnect(err);
+        }
+    }
+
+    // A runtime which has been reported as disconnected counts as back once it provides a new frame.
+    fn update_system_state(&mut self, state: mozgfx::VRSystemState) {
+        if !self.connected {
+            if state.sensorState.inputFrameID == self.system_state.sensorState.inputFrameID {
+                return;
+            }
+            self.connected = true;
+            self.system_state = state;
+            self.events.push(VRDisplayEvent::Connect(self.data()).into());
+        } else {
+            self.system_state = state;
+        }
+        self.update_gamepads();
+    }
+
+    // The runtime has stopped responding, or the shared memory can't be used,
+    // so the display and its controllers are reported as disconnected.
+    fn disconnect(&mut self, err: String) {
+        if !self.connected {
+            return;
+        }
+        error!("VRExternal display disconnected: {}", err);
+        self.connected = false;
+        self.events.push(VRDisplayEvent::Disconnect(self.display_id).into());
+        for gamepad in self.gamepads.drain(..) {
+            self.events.push(VRGamepadEvent::Disconnect(gamepad.borrow().id()).into());
+        }
+    }
+
+    // Copies the controller slots into the gamepads, connecting a gamepad when a slot
+    // gets a controller and disconnecting it when the slot is emptied or reused.
+    fn update_gamepads(&mut self) {
+        let timestamp = self.system_state.sensorState.timestamp;
+        for (index, controller) in self.system_state.controllerState.iter().enumerate() {
+            let name = gamepad::controller_name(controller);
+            let position = self.gamepads.iter().position(|gamepad| gamepad.borrow().index() == index);
+            if let Some(position) = position {
+                if name.as_deref() == Some(self.gamepads[position].borrow().name()) {
+                    self.gamepads[position].borrow_mut().update(controller, timestamp);
+                    continue;
+                }
+                let gamepad = self.gamepads.remove(position);
+                self.events.push(VRGamepadEvent::Disconnect(gamepad.borrow().id()).into());
+            }
+            if let Some(name) = name {
+                let gamepad = VRExternalGamepad::new(index, name, self.display_id, controller, timestamp);
+                let event = {
+                    let gamepad = gamepad.borrow();
+                    VRGamepadEvent::Connect(gamepad.data(), gamepad.state())
+                };
+                self.events.push(event.into());
+                self.gamepads.push(gamepad);
+            }
+        }
+    }
+}
+
+impl VRDisplay for VRExternalDisplay {
+    fn id(&self) -> u32 {
+        self.display_id
+    }
+
+    fn data(&self) -> VRDisplayData {
+        let mut data = VRDisplayData::default();
+
+        let state: &mozgfx::VRDisplayState = &self.system_state.displayState;
+        data.display_name = state.displayName.iter().map(|x| *x as u8 as char).collect();
+        data.display_id = self.display_id;
+        data.connected = state.isConnected && self.connected;
+
+        let flags = state.capabilityFlags;
+        data.capabilities.has_position =
+            (flags & mozgfx::VRDisplayCapabilityFlags_Cap_Position) != 0;
+        data.capabilities.can_present = (flags & mozgfx::VRDisplayCapabilityFlags_Cap_Present) != 0;
+        data.capabilities.has_orientation =
+            (flags & mozgfx::VRDisplayCapabilityFlags_Cap_Orientation) != 0;
+        data.capabilities.has_external_display =
+            (flags & mozgfx::VRDisplayCapabilityFlags_Cap_External) != 0;
+
+        data.stage_parameters = None;
+
+        data.left_eye_parameters.offset = [
+            state.eyeTranslation[0].x,
+            state.eyeTranslation[0].y,
+            state.eyeTranslation[0].z,
+        ];
+
+        data.left_eye_parameters.render_width = state.eyeResolution.width as u32;
+        data.left_eye_parameters.render_height = state.eyeResolution.height as u32;
+
+        data.right_eye_parameters.offset = [
+            state.eyeTranslation[1].x,
+            state.eyeTranslation[1].y,
+            state.eyeTranslation[1].z,
+        ];
+
+        data.right_eye_parameters.render_width = state.eyeResolution.width as u32;
+        data.right_eye_parameters.render_height = state.eyeResolution.height as u32;
+
+        let l_fov = state.eyeFOV[mozgfx::VRDisplayState_Eye_Eye_Left as usize];
+        let r_fov = state.eyeFOV[mozgfx::VRDisplayState_Eye_Eye_Right as usize];
+
+        data.left_eye_parameters.field_of_view.up_degrees = l_fov.upDegrees;
+        data.left_eye_parameters.field_of_view.right_degrees = l_fov.rightDegrees;
+        data.left_eye_parameters.field_of_view.down_degrees = l_fov.downDegrees;
+        data.left_eye_parameters.field_of_view.left_degrees = l_fov.leftDegrees;
+
+        data.right_eye_parameters.field_of_view.up_degrees = r_fov.upDegrees;
+        data.right_eye_parameters.field_of_view.right_degrees = r_fov.rightDegrees;
+        data.right_eye_parameters.field_of_view.down_degrees = r_fov.downDegrees;
+        data.right_eye_parameters.field_of_view.left_degrees = r_fov.leftDegrees;
+
+        data
+    }
+
+    fn immediate_frame_data(&self, near_z: f64, far_z: f64) -> VRFrameData {
+        let sys = &self.system_state;
+
+        let mut data = VRFrameData::default();
+
+        data.pose.position = Some(sys.sensorState.pose.position);
+        data.pose.orientation = Some(sys.sensorState.pose.orientation);
+        data.left_view_matrix = sys.sensorState.leftViewMatrix;
+        data.right_view_matrix = sys.sensorState.rightViewMatrix;
+
+        let right_handed = sys.controllerState[0].hand == mozgfx::ControllerHand_Right;
+
+        let proj = |fov: mozgfx::VRFieldOfView| -> [f32; 16] {
+            use std::f64::consts::PI;
+
+            let up_tan = (fov.upDegrees * PI / 180.0).tan();
+            let down_tan = (fov.downDegrees * PI / 180.0).tan();
+            let left_tan = (fov.leftDegrees * PI / 180.0).tan();
+            let right_tan = (fov.rightDegrees * PI / 180.0).tan();
+            let handedness_scale = if right_handed { -1.0 } else { 1.0 };
+            let pxscale = 2.0 / (left_tan + right_tan);
+            let pxoffset = (left_tan - right_tan) * pxscale * 0.5;
+            let pyscale = 2.0 / (up_tan + down_tan);
+            let pyoffset = (up_tan - down_tan) * pyscale * 0.5;
+            // Column major, so m[column * 4 + row].
+            let mut m = [0.0f32; 16];
+            m[0] = pxscale as f32;
+            m[5] = pyscale as f32;
+            m[8] = (pxoffset * handedness_scale) as f32;
+            m[9] = (-pyoffset * handedness_scale) as f32;
+            m[10] = (far_z / (near_z - far_z) * -handedness_scale) as f32;
+            m[11] = handedness_scale as f32;
+            m[14] = ((far_z * near_z) / (near_z - far_z)) as f32;
+            m
+        };
+
+        let left_fov =
+            sys.displayState.eyeFOV[mozgfx::VRDisplayState_Eye_Eye_Left as usize];
+        let right_fov =
+            sys.displayState.eyeFOV[mozgfx::VRDisplayState_Eye_Eye_Right as usize];
+
+        data.left_projection_matrix = proj(left_fov);
+        data.right_projection_matrix = proj(right_fov);
+
+        data.timestamp = sys.sensorState.timestamp;
+
+        data
+    }
+
+    fn synced_frame_data(&self, near_z: f64, far_z: f64) -> VRFrameData {
+        self.immediate_frame_data(near_z, far_z)
+    }
+
+    fn reset_pose(&mut self) {
+    }
+
+    fn sync_poses(&mut self) {
+        if !self.presenting {
+            self.start_present(None);
+        }
+
+        // Block until the runtime provides a new frame. Once it has missed the deadline,
+        // don't keep the page waiting, just check whether it has come back.
+        let synced_frame_id = self.synced_frame_id;
+        let last_pres_gen = self.system_state.displayState.presentingGeneration;
+        let timeout = if self.connected { self.frame_timeout } else { Some(Duration::from_secs(0)) };
+        let sys = match self.shmem.pull_system(&|sys| {
+            Some(sys.sensorState.inputFrameID) != synced_frame_id ||
+                sys.displayState.suppressFrames ||
+                !sys.displayState.isConnected
+        }, timeout) {
+            Ok(sys) => sys,
+            Err(err) => return self.disconnect(err),
+        };
+        if sys.displayState.presentingGeneration != last_pres_gen {
+            self.events.push(VRDisplayEvent::Exit(0).into());
+        } else {
+            self.synced_frame_id = Some(sys.sensorState.inputFrameID);
+            self.update_system_state(sys);
+        }
+    }
+
+    fn bind_framebuffer(&mut self, _index: u32) {
+    }
+
+    fn get_framebuffers(&self) -> Vec<VRFramebuffer> {
+        let rendered_layer = self.rendered_layer.as_ref().unwrap();
+        let l = rendered_layer.left_bounds;
+        let r = rendered_layer.right_bounds;
+        vec![
+            VRFramebuffer {
+                eye_index: 0,
+                attributes: self.attributes,
+                viewport: VRViewport::new(l[0] as i32, l[1] as i32, l[2] as i32, l[3] as i32),
+            },
+            VRFramebuffer {
+                eye_index: 1,
+                attributes: self.attributes,
+                viewport: VRViewport::new(r[0] as i32, r[1] as i32, r[2] as i32, r[3] as i32),
+            },
+        ]
+    }
+
+    fn supported_textures(&self) -> Vec<VRTextureKind> {
+        vec![VRTextureKind::GeckoSurfaceTexture]
+    }
+
+    fn render_layer(&mut self, layer: &VRLayer) {
+        self.rendered_layer = Some(layer.clone());
+    }
+
+    fn submit_frame(&mut self) {
+        let layer_stereo_immersive = {
+            let rendered_layer = self.rendered_layer.as_ref().unwrap();
+            let texture_handle = match rendered_layer.texture {
+                VRTexture::GeckoSurfaceTexture(handle) => handle,
+                // Before VRTexture, the surface texture handle was passed as a GL texture id,
+                // so keep treating GL texture ids that way.
+                VRTexture::Gl(texture_id) => texture_id as u64,
+                ref texture => {
+                    error!("VRExternal display can't present {:?} textures", texture.kind());
+                    return;
+                }
+            };
+            mozgfx::VRLayer_Stereo_Immersive {
+                textureHandle: texture_handle,
+                textureType: mozgfx::VRLayerTextureType_LayerTextureType_GeckoSurfaceTexture,
+                frameId: self.system_state.sensorState.inputFrameID,
+                leftEyeRect: mozgfx::VRLayerEyeRect {
+                    x: rendered_layer.left_bounds[0],
+                    y: rendered_layer.left_bounds[1],
+                    width: rendered_layer.left_bounds[2],
+                    height: rendered_layer.left_bounds[3],
+                },
+                rightEyeRect: mozgfx::VRLayerEyeRect {
+                    x: rendered_layer.right_bounds[0],
+                    y: rendered_layer.right_bounds[1],
+                    width: rendered_layer.right_bounds[2],
+                    height: rendered_layer.right_bounds[3],
+                },
+                inputFrameId: 0,
+                ..mozgfx::VRLayer_Stereo_Immersive::default()
+           }
+        };
+
+        let layer = mozgfx::VRLayerState {
+            type_: mozgfx::VRLayerType_LayerType_Stereo_Immersive,
+            __bindgen_anon_1: mozgfx::VRLayerState__bindgen_ty_1 {
+                layer_stereo_immersive,
+            },
+            ..mozgfx::VRLayerState::default()
+        };
+
+        self.browser_state.layerState[0] = layer;
+        self.push_browser();
+    }
+
+    fn fetch_gamepads(&mut self) -> Result<Vec<VRGamepadPtr>, String> {
+        Ok(self.gamepads.iter().map(|gamepad| gamepad.clone() as VRGamepadPtr).collect())
+    }
+
+    fn start_present(&mut self, attributes: Option<VRFramebufferAttributes>) {
+        if self.presenting {
+            return;
+        }
+        self.presenting = true;
+        if let Some(attributes) = attributes {
+            self.attributes = attributes;
+        }
+        self.browser_state.layerState[0].type_ = mozgfx::VRLayerType_LayerType_Stereo_Immersive;
+        let count = self.browser_state.layerState.len();
+        for i in 1..count {
+            self.browser_state.layerState[i].type_ = mozgfx::VRLayerType_LayerType_None;
+        }
+        self.browser_state.presentationActive = true;
+        self.push_browser();
+    }
+
+    fn stop_present(&mut self) {
+        if !self.presenting {
+            return;
+        }
+        self.browser_state.presentationActive = false;
+        self.push_browser();
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::process;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+    use super::super::{mozgfx, VRExternalShmemPtr};
+    use super::VRExternalDisplay;
+    use {VRDisplay, VRDisplayEvent, VREvent, VRGamepadEvent};
+
+    const TIMEOUT: Duration = Duration::from_millis(50);
+
+    // Shared memory which the test plays the VR runtime on, from another thread.
+    fn shmem(suffix: &str) -> VRExternalShmemPtr {
+        let name = format!("/rust-webvr-test-{}-display-{}", process::id(), suffix);
+        let shmem = VRExternalShmemPtr::create(&name).unwrap();
+        VRExternalShmemPtr::unlink(&name).unwrap();
+        shmem
+    }
+
+    fn push_frame(shmem: &VRExternalShmemPtr, frame_id: u64) {
+        let shmem = shmem.clone();
+        thread::spawn(move || {
+            let mut state = mozgfx::VRSystemState { enumerationCompleted: true, ..Default::default() };
+            state.displayState.isConnected = true;
+            state.sensorState.inputFrameID = frame_id;
+            state.controllerState[0].controllerName[0] = b'c' as _;
+            shmem.push_system(state).unwrap();
+        }).join().unwrap();
+    }
+
+    fn event_names(events: Vec<VREvent>) -> Vec<&'static str> {
+        events.into_iter().map(|event| match event {
+            VREvent::Display(VRDisplayEvent::Connect(_)) => "display connect",
+            VREvent::Display(VRDisplayEvent::Disconnect(_)) => "display disconnect",
+            VREvent::Gamepad(VRGamepadEvent::Connect(..)) => "gamepad connect",
+            VREvent::Gamepad(VRGamepadEvent::Disconnect(_)) => "gamepad disconnect",
+            _ => "other",
+        }).collect()
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn missed_frames_disconnect_until_the_runtime_is_back() {
+        let shmem = shmem("frames");
+        push_frame(&shmem, 1);
+        let display = VRExternalDisplay::new(shmem.clone(), Some(TIMEOUT)).unwrap();
+        let mut display = display.borrow_mut();
+        assert_eq!(event_names(display.poll_events()), vec!["gamepad connect"]);
+
+        display.sync_poses();
+        assert!(display.connected);
+        // No new frame arrives before the deadline.
+        display.sync_poses();
+        assert!(!display.connected);
+        assert_eq!(event_names(display.poll_events()), vec!["display disconnect", "gamepad disconnect"]);
+        display.sync_poses();
+        assert!(display.poll_events().is_empty());
+
+        push_frame(&shmem, 2);
+        display.sync_poses();
+        assert!(display.connected);
+        assert_eq!(event_names(display.poll_events()), vec!["display connect", "gamepad connect"]);
+    }
+
+    #[test]
+    fn a_runtime_stuck_holding_the_mutex_disconnects() {
+        let shmem = shmem("stuck");
+        push_frame(&shmem, 1);
+        let display = VRExternalDisplay::new(shmem.clone(), Some(TIMEOUT)).unwrap();
+        let mut display = display.borrow_mut();
+        display.poll_events();
+
+        let (locked_sender, locked) = mpsc::channel();
+        let (unlock, unlock_receiver) = mpsc::channel::<()>();
+        let runtime = {
+            let shmem = shmem.clone();
+            thread::spawn(move || unsafe {
+                (*shmem.0).systemMutex.lock().unwrap();
+                locked_sender.send(()).unwrap();
+                let _ = unlock_receiver.recv();
+                (*shmem.0).systemMutex.unlock().unwrap();
+            })
+        };
+        locked.recv().unwrap();
+        assert_eq!(event_names(display.poll_events()), vec!["display disconnect", "gamepad disconnect"]);
+        unlock.send(()).unwrap();
+        runtime.join().unwrap();
+
+        push_frame(&shmem, 2);
+        assert_eq!(event_names(display.poll_events()), vec!["display connect", "gamepad connect"]);
+    }
+}
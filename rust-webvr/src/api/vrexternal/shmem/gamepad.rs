@@ -0,0 +1,209 @@
+use super::mozgfx;
+use rust_webvr_api::utils;
+use std::cell::RefCell;
+use std::sync::Arc;
+use {VRGamepad, VRGamepadButton, VRGamepadData, VRGamepadHand, VRGamepadState, VRPose};
+
+pub type VRExternalGamepadPtr = Arc<RefCell<VRExternalGamepad>>;
+
+// The controller in one of the slots of VRSystemState::controllerState.
+// The display copies the latest state of the slot into the gamepad.
+pub struct VRExternalGamepad {
+    gamepad_id: u32,
+    display_id: u32,
+    index: usize,
+    name: String,
+    controller: mozgfx::VRControllerState,
+    timestamp: f64,
+}
+
+impl VRExternalGamepad {
+    pub fn new(
+        index: usize,
+        name: String,
+        display_id: u32,
+        controller: &mozgfx::VRControllerState,
+        timestamp: f64,
+    ) -> VRExternalGamepadPtr {
+        Arc::new(RefCell::new(VRExternalGamepad {
+            gamepad_id: utils::new_id(),
+            display_id,
+            index,
+            name,
+            controller: *controller,
+            timestamp,
+        }))
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn update(&mut self, controller: &mozgfx::VRControllerState, timestamp: f64) {
+        self.controller = *controller;
+        self.timestamp = timestamp;
+    }
+}
+
+impl VRGamepad for VRExternalGamepad {
+    fn id(&self) -> u32 {
+        self.gamepad_id
+    }
+
+    fn data(&self) -> VRGamepadData {
+        let hand = match self.controller.hand {
+            mozgfx::ControllerHand_Left => VRGamepadHand::Left,
+            mozgfx::ControllerHand_Right => VRGamepadHand::Right,
+            _ => VRGamepadHand::Unknown,
+        };
+        VRGamepadData {
+            display_id: self.display_id,
+            name: self.name.clone(),
+            hand,
+        }
+    }
+
+    fn state(&self) -> VRGamepadState {
+        let controller = &self.controller;
+        let num_buttons = (controller.numButtons as usize).min(controller.triggerValue.len());
+        let num_axes = (controller.numAxes as usize).min(controller.axisValue.len());
+
+        let buttons = (0..num_buttons).map(|button| {
+            let mask = 1u64 << button;
+            let pressed = (controller.buttonPressed & mask) != 0;
+            VRGamepadButton {
+                pressed,
+                touched: pressed || (controller.buttonTouched & mask) != 0,
+            }
+        }).collect();
+
+        // Buttons don't have analog values, so like the OpenVR backend
+        // the trigger values are reported as extra axes after the real ones.
+        let axes = controller.axisValue[..num_axes].iter()
+            .chain(&controller.triggerValue[..num_buttons])
+            .map(|&value| value as f64)
+            .collect();
+
+        let mut pose = VRPose::default();
+        if controller.isOrientationValid {
+            pose.orientation = Some(controller.pose.orientation);
+            pose.angular_velocity = Some(controller.pose.angularVelocity);
+            pose.angular_acceleration = Some(controller.pose.angularAcceleration);
+        }
+        if controller.isPositionValid {
+            pose.position = Some(controller.pose.position);
+            pose.linear_velocity = Some(controller.pose.linearVelocity);
+            pose.linear_acceleration = Some(controller.pose.linearAcceleration);
+        }
+
+        VRGamepadState {
+            gamepad_id: self.gamepad_id,
+            connected: true,
+            timestamp: self.timestamp,
+            axes,
+            buttons,
+            pose,
+        }
+    }
+}
+
+// A slot holds a controller if the controller has a name.
+pub fn controller_name(controller: &mozgfx::VRControllerState) -> Option<String> {
+    let name: String = controller.controllerName.iter()
+        .take_while(|&&c| c != 0)
+        .map(|&c| c as u8 as char)
+        .collect();
+    if name.is_empty() { None } else { Some(name) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mozgfx;
+    use super::{controller_name, VRExternalGamepad};
+    use {VRGamepad, VRGamepadHand};
+
+    fn controller(name: &str) -> mozgfx::VRControllerState {
+        let mut controller = mozgfx::VRControllerState::default();
+        for (dst, src) in controller.controllerName.iter_mut().zip(name.bytes()) {
+            *dst = src as _;
+        }
+        controller
+    }
+
+    #[test]
+    fn named_slots_hold_controllers() {
+        assert_eq!(controller_name(&controller("")), None);
+        assert_eq!(controller_name(&controller("Knuckles")), Some(String::from("Knuckles")));
+    }
+
+    #[test]
+    fn hands_are_mapped() {
+        let mut state = controller("c");
+        let mut hand = |hand| {
+            state.hand = hand;
+            let gamepad = VRExternalGamepad::new(0, String::from("c"), 7, &state, 0.0);
+            let data = gamepad.borrow().data();
+            assert_eq!(data.display_id, 7);
+            data.hand
+        };
+        // VRGamepadHand has no PartialEq, so match on it.
+        assert!(matches!(hand(mozgfx::ControllerHand_Left), VRGamepadHand::Left));
+        assert!(matches!(hand(mozgfx::ControllerHand_Right), VRGamepadHand::Right));
+        assert!(matches!(hand(mozgfx::ControllerHand__empty), VRGamepadHand::Unknown));
+    }
+
+    #[test]
+    fn buttons_and_axes_are_mapped() {
+        let mut state = controller("c");
+        state.numButtons = 3;
+        state.numAxes = 2;
+        state.buttonPressed = 0b001;
+        state.buttonTouched = 0b010;
+        state.triggerValue[0] = 1.0;
+        state.triggerValue[1] = 0.25;
+        state.axisValue[0] = -0.5;
+        state.axisValue[1] = 0.5;
+        let gamepad = VRExternalGamepad::new(0, String::from("c"), 1, &state, 3.0);
+        let state = gamepad.borrow().state();
+
+        assert!(state.connected);
+        assert_eq!(state.timestamp, 3.0);
+        let buttons: Vec<_> = state.buttons.iter().map(|button| (button.pressed, button.touched)).collect();
+        assert_eq!(buttons, vec![(true, true), (false, true), (false, false)]);
+        // The trigger values follow the real axes.
+        assert_eq!(state.axes, vec![-0.5, 0.5, 1.0, 0.25, 0.0]);
+    }
+
+    #[test]
+    fn counts_are_clamped_to_the_arrays() {
+        let mut state = controller("c");
+        state.numButtons = 1000;
+        state.numAxes = 1000;
+        let gamepad = VRExternalGamepad::new(0, String::from("c"), 1, &state, 0.0);
+        let state = gamepad.borrow().state();
+        assert_eq!(state.buttons.len(), 64);
+        assert_eq!(state.axes.len(), 16 + 64);
+    }
+
+    #[test]
+    fn only_valid_parts_of_the_pose_are_reported() {
+        let mut state = controller("c");
+        state.pose.orientation = [0.0, 0.0, 0.0, 1.0];
+        state.pose.position = [1.0, 2.0, 3.0];
+        state.isOrientationValid = true;
+        let gamepad = VRExternalGamepad::new(0, String::from("c"), 1, &state, 0.0);
+        let pose = gamepad.borrow().state().pose;
+        assert_eq!(pose.orientation, Some([0.0, 0.0, 0.0, 1.0]));
+        assert_eq!(pose.position, None);
+
+        state.isPositionValid = true;
+        gamepad.borrow_mut().update(&state, 1.0);
+        let state = gamepad.borrow().state();
+        assert_eq!(state.pose.position, Some([1.0, 2.0, 3.0]));
+        assert_eq!(state.timestamp, 1.0);
+    }
+}
@@ -0,0 +1,131 @@
+use libc;
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::os::raw::c_void;
+use std::ptr;
+use super::mozgfx;
+use super::VRExternalShmemPtr;
+
+// On desktop Linux the shared memory is a POSIX shared memory object, which is
+// created by the VR runtime and opened by the browser. The mapping lasts as long as the process.
+impl VRExternalShmemPtr {
+    /// Creates the shared memory object with the given name (e.g. "/rust-webvr-external"),
+    /// as the VR runtime does. Fails if an object with that name already exists,
+    /// since it may belong to another runtime.
+    pub fn create(name: &str) -> Result<VRExternalShmemPtr, String> {
+        let c_name = shm_name(name)?;
+        let size = mem::size_of::<mozgfx::VRExternalShmem>();
+        unsafe {
+            let fd = libc::shm_open(c_name.as_ptr(), libc::O_RDWR | libc::O_CREAT | libc::O_EXCL, 0o600);
+            if fd < 0 {
+                return Err(format!("Failed to create shared memory {} ({})", name, io::Error::last_os_error()));
+            }
+            if libc::ftruncate(fd, size as libc::off_t) != 0 {
+                let err = io::Error::last_os_error();
+                libc::close(fd);
+                libc::shm_unlink(c_name.as_ptr());
+                return Err(format!("Failed to resize shared memory {} ({})", name, err));
+            }
+            let ptr = map(fd, size).map_err(|err| {
+                libc::shm_unlink(c_name.as_ptr());
+                format!("Failed to map shared memory {} ({})", name, err)
+            })?;
+            // The new object is all zeroes, which is a valid default state.
+            if let Err(err) = (*ptr).init() {
+                libc::munmap(ptr as *mut c_void, size);
+                libc::shm_unlink(c_name.as_ptr());
+                return Err(format!("Failed to initialize shared memory {} ({})", name, err));
+            }
+            Ok(VRExternalShmemPtr(ptr))
+        }
+    }
+
+    /// Creates the shared memory object with the given name, replacing any existing object,
+    /// e.g. one left behind by a runtime which crashed. Processes which have the old object
+    /// mapped keep using it, and no longer hear from this runtime.
+    pub fn create_replacing(name: &str) -> Result<VRExternalShmemPtr, String> {
+        let c_name = shm_name(name)?;
+        unsafe { libc::shm_unlink(c_name.as_ptr()) };
+        VRExternalShmemPtr::create(name)
+    }
+
+    /// Opens a shared memory object created by the VR runtime, as the browser does.
+    pub fn open(name: &str) -> Result<VRExternalShmemPtr, String> {
+        let c_name = shm_name(name)?;
+        let size = mem::size_of::<mozgfx::VRExternalShmem>();
+        unsafe {
+            let fd = libc::shm_open(c_name.as_ptr(), libc::O_RDWR, 0);
+            if fd < 0 {
+                return Err(format!("Failed to open shared memory {} ({})", name, io::Error::last_os_error()));
+            }
+            let mut stat: libc::stat = mem::zeroed();
+            if libc::fstat(fd, &mut stat) != 0 || (stat.st_size as usize) < size {
+                libc::close(fd);
+                return Err(format!("Shared memory {} is too small", name));
+            }
+            let ptr = map(fd, size).map_err(|err| format!("Failed to map shared memory {} ({})", name, err))?;
+            let (version, shmem_size) = ((*ptr).version, (*ptr).size);
+            if version != mozgfx::kVRExternalVersion || shmem_size as usize != size {
+                libc::munmap(ptr as *mut c_void, size);
+                return Err(format!(
+                    "Shared memory {} has version {} and size {}, expected version {} and size {}",
+                    name, version, shmem_size, mozgfx::kVRExternalVersion, size,
+                ));
+            }
+            Ok(VRExternalShmemPtr(ptr))
+        }
+    }
+
+    /// Removes the name of a shared memory object. Processes which have it mapped can keep using it.
+    pub fn unlink(name: &str) -> Result<(), String> {
+        let c_name = shm_name(name)?;
+        if unsafe { libc::shm_unlink(c_name.as_ptr()) } != 0 {
+            return Err(format!("Failed to unlink shared memory {} ({})", name, io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+}
+
+fn shm_name(name: &str) -> Result<CString, String> {
+    CString::new(name).map_err(|_| format!("Invalid shared memory name {:?}", name))
+}
+
+// Maps the whole of the shared memory object, and closes the file descriptor.
+unsafe fn map(fd: libc::c_int, size: usize) -> Result<*mut mozgfx::VRExternalShmem, io::Error> {
+    let ptr = libc::mmap(ptr::null_mut(), size, libc::PROT_READ | libc::PROT_WRITE, libc::MAP_SHARED, fd, 0);
+    let err = io::Error::last_os_error();
+    libc::close(fd);
+    if ptr == libc::MAP_FAILED {
+        return Err(err);
+    }
+    Ok(ptr as *mut mozgfx::VRExternalShmem)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+    use super::super::VRExternalShmemPtr;
+
+    fn name(suffix: &str) -> String {
+        format!("/rust-webvr-test-{}-{}", process::id(), suffix)
+    }
+
+    #[test]
+    fn created_memory_can_be_opened() {
+        let name = name("open");
+        let _shmem = VRExternalShmemPtr::create(&name).unwrap();
+        assert!(VRExternalShmemPtr::open(&name).is_ok());
+        VRExternalShmemPtr::unlink(&name).unwrap();
+        assert!(VRExternalShmemPtr::open(&name).is_err());
+    }
+
+    #[test]
+    fn existing_memory_is_only_replaced_when_asked() {
+        let name = name("replace");
+        let _first = VRExternalShmemPtr::create(&name).unwrap();
+        assert!(VRExternalShmemPtr::create(&name).is_err());
+        let _second = VRExternalShmemPtr::create_replacing(&name).unwrap();
+        VRExternalShmemPtr::unlink(&name).unwrap();
+    }
+}
@@ -0,0 +1,93 @@
+mod display;
+mod gamepad;
+#[cfg(target_os = "linux")]
+mod linux;
+pub mod mozgfx;
+mod service;
+
+use libc;
+use std::io;
+use std::os::raw::c_void;
+use std::time::Duration;
+use {VRService, VRServiceCreator};
+
+#[derive(Clone)]
+pub struct VRExternalShmemPtr(*mut mozgfx::VRExternalShmem);
+
+unsafe impl Send for VRExternalShmemPtr {}
+unsafe impl Sync for VRExternalShmemPtr {}
+
+// The shared memory is guarded by its own mutexes, since it is changed by other processes
+// whatever we do, so it is accessed through a raw pointer rather than a &mut.
+impl VRExternalShmemPtr {
+    pub fn new(raw: *mut c_void) -> VRExternalShmemPtr {
+        VRExternalShmemPtr(raw as *mut mozgfx::VRExternalShmem)
+    }
+
+    /// Publishes a new system state, as the VR runtime does.
+    pub fn push_system(&self, state: mozgfx::VRSystemState) -> Result<(), String> {
+        unsafe { (*self.0).push_system(state) }.map_err(|err| pthread_error("push the system state", err))
+    }
+
+    /// The latest state published by the browser.
+    pub fn pull_browser(&self) -> Result<mozgfx::VRBrowserState, String> {
+        unsafe { (*self.0).pull_browser() }.map_err(|err| pthread_error("pull the browser state", err))
+    }
+
+    fn pull_system(
+        &self,
+        exit_cond: &dyn Fn(&mozgfx::VRSystemState) -> bool,
+        timeout: Option<Duration>,
+    ) -> Result<mozgfx::VRSystemState, String> {
+        unsafe { (*self.0).pull_system(exit_cond, timeout) }.map_err(|err| match err {
+            libc::ETIMEDOUT => format!("Timed out after {:?} waiting for the VR runtime", timeout.unwrap_or_default()),
+            err => pthread_error("pull the system state", err),
+        })
+    }
+
+    fn push_browser(&self, state: mozgfx::VRBrowserState) -> Result<(), String> {
+        unsafe { (*self.0).push_browser(state) }.map_err(|err| pthread_error("push the browser state", err))
+    }
+}
+
+fn pthread_error(action: &str, err: i32) -> String {
+    format!("Failed to {} ({})", action, io::Error::from_raw_os_error(err))
+}
+
+/// How long the vrexternal backend waits for the VR runtime before giving up on it.
+/// A timeout of `None` waits forever.
+#[derive(Clone, Copy, Debug)]
+pub struct VRExternalTimeouts {
+    /// How long fetching the displays waits for the runtime to finish enumerating them.
+    pub enumeration: Option<Duration>,
+    /// How long syncing poses waits for the runtime to provide a new frame.
+    /// A display whose runtime misses this deadline is reported as disconnected.
+    pub frame: Option<Duration>,
+}
+
+impl Default for VRExternalTimeouts {
+    fn default() -> VRExternalTimeouts {
+        VRExternalTimeouts {
+            enumeration: Some(Duration::from_secs(5)),
+            frame: Some(Duration::from_secs(1)),
+        }
+    }
+}
+
+pub struct VRExternalServiceCreator(VRExternalShmemPtr, VRExternalTimeouts);
+
+impl VRExternalServiceCreator {
+    pub fn new(ptr: VRExternalShmemPtr) -> Box<dyn VRServiceCreator> {
+        VRExternalServiceCreator::new_with_timeouts(ptr, VRExternalTimeouts::default())
+    }
+
+    pub fn new_with_timeouts(ptr: VRExternalShmemPtr, timeouts: VRExternalTimeouts) -> Box<dyn VRServiceCreator> {
+        Box::new(VRExternalServiceCreator(ptr, timeouts))
+    }
+}
+
+impl VRServiceCreator for VRExternalServiceCreator {
+    fn new_service(&self) -> Box<dyn VRService> {
+        Box::new(service::VRExternalService::new(self.0.clone(), self.1))
+    }
+}
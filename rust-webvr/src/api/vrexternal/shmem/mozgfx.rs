@@ -0,0 +1,257 @@
+#![allow(non_upper_case_globals)]
+#![allow(non_camel_case_types)]
+#![allow(non_snake_case)]
+#![allow(dead_code)]
+
+use libc;
+use std::convert::TryFrom;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(target_os = "android")]
+include!(concat!(env!("OUT_DIR"), "/moz_external_vr.rs"));
+#[cfg(target_os = "linux")]
+include!("bindings.rs");
+
+pub const kVRExternalVersion: i32 = 6;
+
+pub type PthreadResult = Result<(), i32>;
+
+impl VRExternalShmem {
+    // Sets up freshly zeroed shared memory, so that it can be used by several processes.
+    #[cfg(target_os = "linux")]
+    pub fn init(&mut self) -> PthreadResult {
+        self.version = kVRExternalVersion;
+        self.size = ::std::mem::size_of::<VRExternalShmem>() as i32;
+        self.systemMutex.init_shared()?;
+        self.geckoMutex.init_shared()?;
+        self.servoMutex.init_shared()?;
+        self.systemCond.init_shared()?;
+        self.geckoCond.init_shared()?;
+        self.servoCond.init_shared()?;
+        Ok(())
+    }
+    pub fn push_system(&mut self, state: VRSystemState) -> PthreadResult {
+        self.systemMutex.lock()?;
+        self.state = state;
+        let result = self.systemCond.broadcast();
+        self.systemMutex.unlock()?;
+        result
+    }
+    // Waits until exit_cond holds, giving up with ETIMEDOUT if it still doesn't after the timeout.
+    pub fn pull_system(
+        &mut self,
+        exit_cond: &dyn Fn(&VRSystemState) -> bool,
+        timeout: Option<Duration>,
+    ) -> Result<VRSystemState, i32> {
+        let deadline = timeout.map(deadline);
+        // The runtime may be stuck while holding the mutex, so locking it has the same deadline.
+        match deadline {
+            Some(ref deadline) => self.systemMutex.timed_lock(deadline)?,
+            None => self.systemMutex.lock()?,
+        }
+        let result = self.wait_system(exit_cond, deadline.as_ref());
+        let state = self.state;
+        self.systemMutex.unlock()?;
+        result.map(|()| state)
+    }
+    fn wait_system(
+        &mut self,
+        exit_cond: &dyn Fn(&VRSystemState) -> bool,
+        deadline: Option<&libc::timespec>,
+    ) -> PthreadResult {
+        while !exit_cond(&self.state) {
+            match deadline {
+                Some(deadline) => self.systemCond.timed_wait(&mut self.systemMutex, deadline)?,
+                None => self.systemCond.wait(&mut self.systemMutex)?,
+            }
+        }
+        Ok(())
+    }
+    pub fn pull_browser(&mut self) -> Result<VRBrowserState, i32> {
+        self.servoMutex.lock()?;
+        let state = self.servoState;
+        self.servoMutex.unlock()?;
+        Ok(state)
+    }
+    pub fn push_browser(&mut self, state: VRBrowserState) -> PthreadResult {
+        self.servoMutex.lock()?;
+        self.servoState = state;
+        let result = self.servoCond.signal();
+        self.servoMutex.unlock()?;
+        result
+    }
+}
+
+// The time after the timeout, on the CLOCK_REALTIME clock used by pthread_cond_timedwait,
+// which is the clock SystemTime reads.
+fn deadline(timeout: Duration) -> libc::timespec {
+    let since_epoch = SystemTime::now().checked_add(timeout)
+        .and_then(|deadline| deadline.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or(Duration::from_secs(u64::MAX));
+    libc::timespec {
+        tv_sec: libc::time_t::try_from(since_epoch.as_secs()).unwrap_or(libc::time_t::MAX),
+        tv_nsec: since_epoch.subsec_nanos() as _,
+    }
+}
+
+impl pthread_mutex_t {
+    fn as_libc(&mut self) -> *mut libc::pthread_mutex_t {
+        self as *mut _ as *mut libc::pthread_mutex_t
+    }
+    pub fn lock(&mut self) -> PthreadResult {
+        let r = unsafe { libc::pthread_mutex_lock(self.as_libc()) };
+        self.recover(r)
+    }
+    // Gives up with ETIMEDOUT if the mutex is still held at the deadline.
+    pub fn timed_lock(&mut self, deadline: &libc::timespec) -> PthreadResult {
+        let r = unsafe { libc::pthread_mutex_timedlock(self.as_libc(), deadline) };
+        self.recover(r)
+    }
+    pub fn unlock(&mut self) -> PthreadResult {
+        let r = unsafe { libc::pthread_mutex_unlock(self.as_libc()) };
+        if r == 0 { Ok(()) } else { Err(r) }
+    }
+    #[cfg(target_os = "linux")]
+    fn init_shared(&mut self) -> PthreadResult {
+        unsafe {
+            let mut attr = ::std::mem::zeroed();
+            let r = libc::pthread_mutexattr_init(&mut attr);
+            if r != 0 { return Err(r); }
+            let mut r = libc::pthread_mutexattr_setpshared(&mut attr, libc::PTHREAD_PROCESS_SHARED);
+            if r == 0 {
+                // So that a process which dies while holding the mutex doesn't leave it locked forever.
+                r = libc::pthread_mutexattr_setrobust(&mut attr, libc::PTHREAD_MUTEX_ROBUST);
+            }
+            if r == 0 {
+                r = libc::pthread_mutex_init(self.as_libc(), &attr);
+            }
+            libc::pthread_mutexattr_destroy(&mut attr);
+            if r == 0 { Ok(()) } else { Err(r) }
+        }
+    }
+    // Locking a robust mutex whose owner died succeeds with EOWNERDEAD, and the mutex
+    // can be used again once it is marked as consistent. The state it guards is plain data,
+    // which is at worst half updated, and the next push overwrites it.
+    #[cfg(target_os = "linux")]
+    fn recover(&mut self, r: i32) -> PthreadResult {
+        let r = match r {
+            libc::EOWNERDEAD => unsafe { libc::pthread_mutex_consistent(self.as_libc()) },
+            r => r,
+        };
+        if r == 0 { Ok(()) } else { Err(r) }
+    }
+    #[cfg(not(target_os = "linux"))]
+    fn recover(&mut self, r: i32) -> PthreadResult {
+        if r == 0 { Ok(()) } else { Err(r) }
+    }
+}
+
+impl pthread_cond_t {
+    fn as_libc(&mut self) -> *mut libc::pthread_cond_t {
+        self as *mut _ as *mut libc::pthread_cond_t
+    }
+    pub fn wait(&mut self, mutex: &mut pthread_mutex_t) -> PthreadResult {
+        let r = unsafe { libc::pthread_cond_wait(self.as_libc(), mutex.as_libc()) };
+        mutex.recover(r)
+    }
+    pub fn timed_wait(&mut self, mutex: &mut pthread_mutex_t, deadline: &libc::timespec) -> PthreadResult {
+        let r = unsafe { libc::pthread_cond_timedwait(self.as_libc(), mutex.as_libc(), deadline) };
+        mutex.recover(r)
+    }
+    pub fn signal(&mut self) -> PthreadResult {
+        let r = unsafe { libc::pthread_cond_signal(self.as_libc()) };
+        if r == 0 { Ok(()) } else { Err(r) }
+    }
+    pub fn broadcast(&mut self) -> PthreadResult {
+        let r = unsafe { libc::pthread_cond_broadcast(self.as_libc()) };
+        if r == 0 { Ok(()) } else { Err(r) }
+    }
+    #[cfg(target_os = "linux")]
+    fn init_shared(&mut self) -> PthreadResult {
+        unsafe {
+            let mut attr = ::std::mem::zeroed();
+            let r = libc::pthread_condattr_init(&mut attr);
+            if r != 0 { return Err(r); }
+            let mut r = libc::pthread_condattr_setpshared(&mut attr, libc::PTHREAD_PROCESS_SHARED);
+            if r == 0 {
+                r = libc::pthread_cond_init(self.as_libc(), &attr);
+            }
+            libc::pthread_condattr_destroy(&mut attr);
+            if r == 0 { Ok(()) } else { Err(r) }
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use libc;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    use super::{deadline, VRExternalShmem};
+
+    // Lets another thread lock the mutexes, as another process would.
+    struct SharedPtr(*mut VRExternalShmem);
+    unsafe impl Send for SharedPtr {}
+
+    fn shmem() -> Box<VRExternalShmem> {
+        let mut shmem = Box::new(VRExternalShmem::default());
+        shmem.init().unwrap();
+        shmem
+    }
+
+    #[test]
+    fn deadline_is_the_timeout_from_now() {
+        let before = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let deadline = deadline(Duration::from_secs(10));
+        let after = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let deadline = Duration::new(deadline.tv_sec as u64, deadline.tv_nsec as u32);
+        assert!(deadline >= before + Duration::from_secs(10));
+        assert!(deadline <= after + Duration::from_secs(10));
+    }
+
+    #[test]
+    fn deadline_saturates() {
+        let deadline = deadline(Duration::from_secs(u64::MAX));
+        assert_eq!(deadline.tv_sec, libc::time_t::MAX);
+        assert!(deadline.tv_nsec < 1_000_000_000);
+    }
+
+    #[test]
+    fn pulls_time_out_while_the_mutex_is_held() {
+        let mut shmem = shmem();
+        let ptr = SharedPtr(&mut *shmem);
+        let (locked_sender, locked) = mpsc::channel();
+        let (unlock, unlock_receiver) = mpsc::channel::<()>();
+        let holder = thread::spawn(move || {
+            let shmem = unsafe { &mut *ptr.0 };
+            shmem.systemMutex.lock().unwrap();
+            locked_sender.send(()).unwrap();
+            let _ = unlock_receiver.recv();
+            shmem.systemMutex.unlock().unwrap();
+        });
+        locked.recv().unwrap();
+        let result = shmem.pull_system(&|_| true, Some(Duration::from_millis(50)));
+        assert_eq!(result.err(), Some(libc::ETIMEDOUT));
+        unlock.send(()).unwrap();
+        holder.join().unwrap();
+        assert!(shmem.pull_system(&|_| true, Some(Duration::from_millis(50))).is_ok());
+    }
+
+    #[test]
+    fn mutexes_survive_an_owner_dying() {
+        let mut shmem = shmem();
+        let ptr = SharedPtr(&mut *shmem);
+        // The thread exits without unlocking, as a crashing runtime would.
+        thread::spawn(move || {
+            let shmem = unsafe { &mut *ptr.0 };
+            shmem.systemMutex.lock().unwrap();
+            shmem.servoMutex.lock().unwrap();
+        }).join().unwrap();
+        assert!(shmem.pull_system(&|_| true, Some(Duration::from_millis(50))).is_ok());
+        assert!(shmem.pull_browser().is_ok());
+        // Once recovered, the mutexes work as usual.
+        assert!(shmem.push_system(Default::default()).is_ok());
+        assert!(shmem.push_browser(Default::default()).is_ok());
+    }
+}
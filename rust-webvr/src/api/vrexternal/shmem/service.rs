@@ -1,10 +1,11 @@
 use super::display::{VRExternalDisplay, VRExternalDisplayPtr};
-use super::VRExternalShmemPtr;
-use {VRDisplayPtr, VREvent, VRGamepadPtr, VRService};
+use super::{VRExternalShmemPtr, VRExternalTimeouts};
+use {VRDisplay, VRDisplayPtr, VREvent, VRGamepadPtr, VRService};
 
 pub struct VRExternalService {
     shmem: VRExternalShmemPtr,
     display: Option<VRExternalDisplayPtr>,
+    timeouts: VRExternalTimeouts,
 }
 
 unsafe impl Send for VRExternalService {}
@@ -16,16 +17,22 @@ impl VRService for VRExternalService {
 
     fn fetch_displays(&mut self) -> Result<Vec<VRDisplayPtr>, String> {
         if self.display.is_none() {
-            // Block until enumerationCompleted is true.
-            self.shmem.as_mut().pull_system(&|state| state.enumerationCompleted);
-            let display = VRExternalDisplay::new(self.shmem.clone());
+            // Block until enumerationCompleted is true, or the runtime misses the deadline,
+            // in which case the next call tries again.
+            self.shmem.pull_system(&|state| state.enumerationCompleted, self.timeouts.enumeration)?;
+            let display = VRExternalDisplay::new(self.shmem.clone(), self.timeouts.frame)?;
             self.display = Some(display);
         }
         Ok(vec![self.display.as_ref().unwrap().clone()])
     }
 
     fn fetch_gamepads(&mut self) -> Result<Vec<VRGamepadPtr>, String> {
-        Ok(Vec::new())
+        // The controllers belong to the display, which is only created once the
+        // runtime has finished enumerating, so there are none until then.
+        match self.display {
+            Some(ref display) => display.borrow_mut().fetch_gamepads(),
+            None => Ok(Vec::new()),
+        }
     }
 
     fn is_available(&self) -> bool {
@@ -35,22 +42,17 @@ impl VRService for VRExternalService {
     fn poll_events(&self) -> Vec<VREvent> {
         match &self.display {
             None => vec![],
-            Some(display) => {
-                display.borrow_mut()
-                       .poll_events()
-                       .into_iter()
-                       .map(|e| VREvent::Display(e))
-                       .collect()
-            }
+            Some(display) => display.borrow_mut().poll_events(),
         }
     }
 }
 
 impl VRExternalService {
-    pub fn new(ptr: VRExternalShmemPtr) -> VRExternalService {
+    pub fn new(ptr: VRExternalShmemPtr, timeouts: VRExternalTimeouts) -> VRExternalService {
         VRExternalService {
             shmem: ptr.clone(),
             display: None,
+            timeouts,
         }
     }
 }
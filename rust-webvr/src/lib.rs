@@ -1,4 +1,4 @@
-#[cfg(all(feature = "vrexternal", target_os= "android"))]
+#[cfg(any(all(feature = "vrexternal", any(target_os= "android", target_os = "linux")), all(feature = "dmabuf", target_os = "linux")))]
 extern crate libc;
 extern crate rust_webvr_api;
 #[cfg(all(feature = "googlevr", target_os= "android"))]
@@ -11,12 +11,20 @@ extern crate log;
 extern crate ovr_mobile_sys;
 #[cfg(any(feature = "magicleap", feature = "glwindow"))]
 extern crate euclid;
-#[cfg(any(feature = "magicleap", feature = "glwindow"))]
+#[cfg(any(feature = "magicleap", feature = "glwindow", feature = "headless", feature = "dmabuf"))]
 extern crate gleam;
 #[cfg(feature = "glwindow")]
 extern crate glutin;
-#[cfg(feature = "serde-serialization")]
+#[cfg(feature = "ipc")]
+extern crate ipc_channel;
+#[cfg(feature = "headless")]
+extern crate png;
+#[cfg(any(feature = "serde-serialization", feature = "dmabuf"))]
 #[macro_use] extern crate serde_derive;
+#[cfg(feature = "dmabuf")]
+extern crate serde;
+#[cfg(feature = "dmabuf")]
+extern crate serde_json;
 
 #[cfg(any(feature = "googlevr", feature= "oculusvr"))]
 mod gl {
@@ -41,10 +49,14 @@ mod egl {
 }
 
 #[cfg(feature= "glwindow")]
-pub use api::GlWindowVRService;
+pub use api::{GlWindowVRAction, GlWindowVRBindings, GlWindowVRPreview, GlWindowVRService, GlWindowVRServiceBuilder};
+#[cfg(feature = "headless")]
+pub use api::HeadlessVRService;
+#[cfg(all(feature = "dmabuf", target_os = "linux"))]
+pub use api::{DmabufVRServer, DmabufVRService};
 
 pub mod api;
 mod vr_manager;
 
 pub use rust_webvr_api::*;
-pub use vr_manager::VRServiceManager;
+pub use vr_manager::{VRHeartbeats, VRServiceManager};
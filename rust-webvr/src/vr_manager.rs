@@ -2,9 +2,22 @@ use std::collections::HashMap;
 use VRDisplayPtr;
 use VREvent;
 use VRGamepadPtr;
+use VRMainThreadHeartbeat;
 use VRService;
 use VRServiceCreator;
 
+#[cfg(feature = "glwindow")]
+use api::{EventsLoopFactory, GlWindowVRMainThreadHeartbeat, GlWindowVRServiceBuilder};
+
+#[cfg(feature = "glwindow")]
+use gleam::gl::Gl;
+
+#[cfg(feature = "glwindow")]
+use glutin::{NotCurrent, WindowedContext};
+
+#[cfg(feature = "glwindow")]
+use std::rc::Rc;
+
 #[cfg(target_os = "android")]
 #[cfg(feature = "googlevr")]
 use api::GoogleVRServiceCreator;
@@ -20,12 +33,18 @@ use api::OculusVRServiceCreator;
 #[cfg(feature = "mock")]
 use api::{MockServiceCreator, MockVRControlMsg};
 
+#[cfg(feature = "mock")]
+use rust_webvr_api::utils::VRClockPtr;
+
+#[cfg(all(feature = "mock", feature = "ipc"))]
+use ipc_channel::ipc::IpcSender;
+
 #[cfg(feature = "vrexternal")]
 use api::VRExternalShmemPtr;
 
-#[cfg(target_os = "android")]
+#[cfg(any(target_os = "android", target_os = "linux"))]
 #[cfg(feature = "vrexternal")]
-use api::VRExternalServiceCreator;
+use api::{VRExternalServiceCreator, VRExternalTimeouts};
 
 // Single entry point all the VRServices and displays
 pub struct VRServiceManager {
@@ -75,22 +94,31 @@ impl VRServiceManager {
     }
 
     // Register VRExternal service.
-    #[cfg(target_os = "android")]
+    #[cfg(any(target_os = "android", target_os = "linux"))]
     #[cfg(feature = "vrexternal")]
     pub fn register_vrexternal(&mut self, ptr: VRExternalShmemPtr) {
         let creator = VRExternalServiceCreator::new(ptr);
         self.register(creator.new_service());
     }
 
+    // Register VRExternal service, with its own deadlines for the VR runtime.
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[cfg(feature = "vrexternal")]
+    pub fn register_vrexternal_with_timeouts(&mut self, ptr: VRExternalShmemPtr, timeouts: VRExternalTimeouts) {
+        let creator = VRExternalServiceCreator::new_with_timeouts(ptr, timeouts);
+        self.register(creator.new_service());
+    }
+
     // Register VRExternal service.
-    #[cfg(not(target_os = "android"))]
+    // There is no shared memory to talk to the VR runtime through on this platform, so nothing is registered.
+    #[cfg(not(any(target_os = "android", target_os = "linux")))]
     #[cfg(feature = "vrexternal")]
     pub fn register_vrexternal(&mut self, _: VRExternalShmemPtr) {
-        unimplemented!();
+        error!("VRExternal is only supported on Android and Linux");
     }
 
     // Register mock VR Service
-    // Usefull for testing
+    // Useful for testing
     #[cfg(feature = "mock")]
     pub fn register_mock(&mut self) {
         let creator = MockServiceCreator::new();
@@ -98,7 +126,7 @@ impl VRServiceManager {
     }
 
     // Register mock VR Service
-    // Usefull for testing
+    // Useful for testing
     #[cfg(feature = "mock")]
     pub fn register_mock_with_remote(&mut self) -> std::sync::mpsc::Sender<MockVRControlMsg> {
         let (service, remote) = MockServiceCreator::new_service_with_remote();
@@ -106,6 +134,50 @@ impl VRServiceManager {
         remote
     }
 
+    // Register mock VR Service driven by the given clock
+    // Useful for deterministic tests
+    #[cfg(feature = "mock")]
+    pub fn register_mock_with_clock(&mut self, clock: VRClockPtr) -> std::sync::mpsc::Sender<MockVRControlMsg> {
+        let (service, remote) = MockServiceCreator::new_service_with_clock(clock);
+        self.register(service);
+        remote
+    }
+
+    // Register mock VR Service controlled from another process
+    // See MockServiceCreator::new_service_with_ipc_remote
+    #[cfg(all(feature = "mock", feature = "ipc"))]
+    pub fn register_mock_with_ipc_remote(&mut self) -> Result<IpcSender<MockVRControlMsg>, String> {
+        let (service, remote) = MockServiceCreator::new_service_with_ipc_remote()?;
+        self.register(service);
+        Ok(remote)
+    }
+
+    // Register mock VR Service controlled from another process, returns the IPC server name
+    // See MockServiceCreator::new_service_with_ipc_server
+    #[cfg(all(feature = "mock", feature = "ipc"))]
+    pub fn register_mock_with_ipc_server(&mut self) -> Result<String, String> {
+        let (service, name) = MockServiceCreator::new_service_with_ipc_server()?;
+        self.register(service);
+        Ok(name)
+    }
+
+    // Register a window which previews VR content on the desktop, built by the given builder.
+    // This should be called from the main thread, which must run the returned heartbeat,
+    // e.g. by adding it to a VRHeartbeats. Services with more than one window are built with
+    // GlWindowVRServiceBuilder::build and GlWindowVRService::add_display, then registered with register.
+    #[cfg(feature = "glwindow")]
+    pub fn register_glwindow(
+        &mut self,
+        builder: GlWindowVRServiceBuilder,
+        gl_context: WindowedContext<NotCurrent>,
+        events_loop_factory: EventsLoopFactory,
+        gl: Rc<dyn Gl>,
+    ) -> GlWindowVRMainThreadHeartbeat {
+        let (service, heartbeat) = builder.build(gl_context, events_loop_factory, gl);
+        self.register(Box::new(service));
+        heartbeat
+    }
+
     // Register a new VR service
     pub fn register(&mut self, service: Box<VRService>) {
         self.services.push(service);
@@ -197,3 +269,114 @@ impl VRServiceManager {
         }
     }
 }
+
+// The heartbeats of VR services, which have to run on the main thread.
+// They are kept apart from the VRServiceManager, which may be sent to another thread,
+// and this isn't Send, so it stays on the thread where it was made.
+#[derive(Default)]
+pub struct VRHeartbeats {
+    heartbeats: Vec<Box<dyn VRMainThreadHeartbeat>>,
+}
+
+impl VRHeartbeats {
+    pub fn new() -> VRHeartbeats {
+        VRHeartbeats {
+            heartbeats: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, heartbeat: Box<dyn VRMainThreadHeartbeat>) {
+        self.heartbeats.push(heartbeat);
+    }
+}
+
+impl VRMainThreadHeartbeat for VRHeartbeats {
+    // Runs all the heartbeats.
+    fn heartbeat(&mut self) {
+        for heartbeat in &mut self.heartbeats {
+            heartbeat.heartbeat();
+        }
+    }
+
+    // Is any of the heartbeats expecting to be called every frame?
+    fn heart_racing(&self) -> bool {
+        self.heartbeats.iter().any(|heartbeat| heartbeat.heart_racing())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    #[cfg(feature = "mock")]
+    use std::sync::mpsc::{channel, Sender};
+    #[cfg(feature = "mock")]
+    use MockVRControlMsg;
+    use VRMainThreadHeartbeat;
+    use super::VRHeartbeats;
+    #[cfg(feature = "mock")]
+    use super::VRServiceManager;
+
+    struct CountingHeartbeat(Rc<Cell<u32>>, bool);
+
+    impl VRMainThreadHeartbeat for CountingHeartbeat {
+        fn heartbeat(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+
+        fn heart_racing(&self) -> bool {
+            self.1
+        }
+    }
+
+    #[test]
+    fn heartbeats_run_together() {
+        let count = Rc::new(Cell::new(0));
+        let mut heartbeats = VRHeartbeats::new();
+        assert!(!heartbeats.heart_racing());
+
+        heartbeats.add(Box::new(CountingHeartbeat(count.clone(), false)));
+        heartbeats.add(Box::new(CountingHeartbeat(count.clone(), true)));
+        heartbeats.heartbeat();
+        assert_eq!(count.get(), 2);
+        assert!(heartbeats.heart_racing());
+    }
+
+    // Steps the viewer of a mock display forward on the main thread, as a window's heartbeat would.
+    #[cfg(feature = "mock")]
+    struct WalkingHeartbeat(Sender<MockVRControlMsg>, f32);
+
+    #[cfg(feature = "mock")]
+    impl VRMainThreadHeartbeat for WalkingHeartbeat {
+        fn heartbeat(&mut self) {
+            self.1 += 1.0;
+            self.0.send(MockVRControlMsg::SetViewerPose([self.1, 0.0, 0.0], [0.0, 0.0, 0.0, 1.0])).unwrap();
+            // Wait for the mock to handle the message.
+            let (reply, replied) = channel();
+            self.0.send(MockVRControlMsg::GetDisplayData(reply.into())).unwrap();
+            replied.recv().unwrap();
+        }
+
+        fn heart_racing(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "mock")]
+    fn registered_displays_follow_their_heartbeat() {
+        let mut manager = VRServiceManager::new();
+        let remote = manager.register_mock_with_remote();
+        let mut heartbeats = VRHeartbeats::new();
+        heartbeats.add(Box::new(WalkingHeartbeat(remote, 0.0)));
+        assert!(heartbeats.heart_racing());
+
+        let displays = manager.get_displays();
+        assert_eq!(displays.len(), 1);
+        let position = || displays[0].borrow().immediate_frame_data(0.1, 100.0).pose.position;
+        heartbeats.heartbeat();
+        assert_eq!(position(), Some([1.0, 0.0, 0.0]));
+        heartbeats.heartbeat();
+        assert_eq!(position(), Some([2.0, 0.0, 0.0]));
+    }
+}